@@ -1,9 +1,18 @@
 use std::{
     hint::black_box,
-    sync::{Arc, Barrier},
+    ptr::null_mut,
+    sync::{Arc, Barrier, atomic::Ordering, mpsc},
+    time::{Duration, Instant},
 };
 
 use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use oxidalloc::{
+    MetaData, TOTAL_IN_USE,
+    slab::{
+        SIZE_CLASSES, bulk_allocation::bulk_fill, first_block_offset, match_size_class,
+        thread_local::ThreadLocalEngine,
+    },
+};
 
 unsafe extern "C" {
     fn malloc(size: libc::size_t) -> *mut libc::c_void;
@@ -265,6 +274,168 @@ fn bench_fragmentation(c: &mut Criterion) {
     group.finish();
 }
 
+fn resident_bytes() -> usize {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap_or_default();
+    let pages = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    pages * unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// Threads run one after another, touch a small working set, free it and stay parked. Returns
+// the RSS growth while all of them are parked.
+fn park_idle_threads(num_threads: usize) -> usize {
+    const SIZES: [usize; 6] = [32, 64, 128, 256, 512, 1024];
+    const BLOCKS: usize = 192;
+
+    let release = Arc::new(Barrier::new(num_threads + 1));
+    let before = resident_bytes();
+
+    let handles: Vec<_> = (0..num_threads)
+        .map(|_| {
+            let (ready, wait_ready) = mpsc::channel();
+            let release = release.clone();
+            let handle = std::thread::spawn(move || unsafe {
+                let mut ptrs = [std::ptr::null_mut(); BLOCKS];
+                for (i, p) in ptrs.iter_mut().enumerate() {
+                    let size = SIZES[i % SIZES.len()];
+                    let ptr = black_box(malloc(size as libc::size_t));
+                    (ptr as *mut u8).write_bytes(0x5A, size);
+                    *p = ptr;
+                }
+                for p in &ptrs {
+                    black_box(free(*p));
+                }
+
+                ready.send(()).unwrap();
+                release.wait();
+            });
+            wait_ready.recv().unwrap();
+            handle
+        })
+        .collect();
+
+    let grown = resident_bytes().saturating_sub(before);
+    release.wait();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    grown
+}
+
+// Build with and without `experimental-cpu-local-global` to compare the two cache modes
+fn bench_idle_threads_rss(c: &mut Criterion) {
+    let mode = if cfg!(feature = "experimental-cpu-local-global") {
+        "per-cpu"
+    } else {
+        "per-thread"
+    };
+    let mut group = c.benchmark_group("idle_threads_rss");
+    group.sample_size(10);
+
+    for num_threads in [64usize, 256, 1024] {
+        let grown = park_idle_threads(num_threads);
+        println!(
+            "idle_threads_rss/{num_threads}threads ({mode}): {} KiB resident growth",
+            grown / 1024
+        );
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{num_threads}threads")),
+            &num_threads,
+            |b, &num_threads| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let start = Instant::now();
+                        black_box(park_idle_threads(num_threads));
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// Build with and without `hardened-malloc` to compare linear and shuffled carving. Every
+// iteration carves the same blocks of a private slab again and drops the carved list unused.
+fn bench_carve(c: &mut Criterion) {
+    let mode = if cfg!(feature = "hardened-malloc") {
+        "shuffled"
+    } else {
+        "linear"
+    };
+    let mut group = c.benchmark_group(format!("carve_{mode}"));
+
+    // Runs `bulk_fill` against an empty bin and `slab`, the thread gets its state back after.
+    // Returns the slab the carve left pending.
+    unsafe fn carve_into(
+        thread: &mut ThreadLocalEngine,
+        class: usize,
+        slab: *mut MetaData,
+    ) -> *mut MetaData {
+        let (head, usage, pending) = (
+            thread.tls[class].head,
+            thread.tls[class].usage,
+            thread.pending[class],
+        );
+        thread.tls[class].head = null_mut();
+        thread.tls[class].usage = 0;
+        thread.pending[class] = slab;
+
+        unsafe { assert!(bulk_fill(thread, class).is_ok()) };
+
+        TOTAL_IN_USE.fetch_sub(
+            thread.tls[class].usage * SIZE_CLASSES[class],
+            Ordering::Relaxed,
+        );
+        let carved = thread.pending[class];
+        thread.tls[class].head = head;
+        thread.tls[class].usage = usage;
+        thread.pending[class] = pending;
+        carved
+    }
+
+    for size in [16usize, 64, 256, 512] {
+        let class = match_size_class(size).unwrap();
+        let slab = unsafe {
+            // The first carve maps the slab, it is never handed out and stays mapped
+            carve_into(ThreadLocalEngine::get_or_init(), class, null_mut())
+        };
+        assert!(!slab.is_null());
+        let first = unsafe { (*slab).start } + first_block_offset(class);
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{size}B")),
+            &class,
+            |b, &class| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        unsafe {
+                            (*slab).next = first;
+                            let thread = ThreadLocalEngine::get_or_init();
+                            let start = Instant::now();
+                            black_box(carve_into(thread, class, slab));
+                            elapsed += start.elapsed();
+                        }
+                    }
+                    elapsed
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_alloc_free,
@@ -273,6 +444,8 @@ criterion_group!(
     bench_size_sweep,
     bench_patterns,
     bench_fragmentation,
+    bench_idle_threads_rss,
+    bench_carve,
 );
 
 criterion_main!(benches);
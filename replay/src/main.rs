@@ -0,0 +1,234 @@
+// Replays an `OX_TRACE` file against Oxidalloc and reports throughput, peak RSS and fragmentation
+// Records from every thread are merged by timestamp and replayed on one thread, so a run only
+// depends on the trace. Addresses are mapped from the trace to the replay, calls on a pointer the
+// trace never handed out (allocated before tracing started, or by another allocator) are skipped,
+// and so are the calls that failed in the traced process. One byte per page of every new block is
+// written, so RSS follows what the traced program touched at least once. Only the allocator calls
+// are timed, the bookkeeping, the page touches and the footprint lookups are not. Oxidalloc is
+// linked without `c-abi`, so the records and maps of the tool itself come from the system
+// allocator and stay out of the footprint.
+//
+//     ox-replay <trace file>
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    os::raw::c_void,
+    process::ExitCode,
+    ptr::null_mut,
+    time::{Duration, Instant},
+};
+
+use oxidalloc::{
+    abi::{
+        align::posix_memalign, calloc::calloc, ctl::ox_ctl, free::free, malloc::malloc,
+        realloc::realloc,
+    },
+    internals::trace::{TraceOp, TraceRecord, parse_trace},
+};
+
+const PAGE: usize = 4096;
+
+#[derive(Default)]
+struct Stats {
+    replayed: usize,
+    skipped: usize,
+    live: usize,
+    peak_live: usize,
+    // Slab and big allocation bytes when `live` peaked
+    footprint_at_peak: usize,
+    // Spent inside the allocator
+    elapsed: Duration,
+}
+
+fn ctl_usize(name: &str) -> usize {
+    let name = format!("{name}\0");
+    let mut val = 0usize;
+    let mut len = size_of::<usize>();
+    let ret = unsafe {
+        ox_ctl(
+            name.as_ptr().cast(),
+            (&raw mut val).cast(),
+            &mut len,
+            null_mut(),
+            0,
+        )
+    };
+    assert_eq!(ret, 0, "{name}");
+    val
+}
+
+fn footprint() -> usize {
+    ctl_usize("stats.allocated") + ctl_usize("stats.big.bytes")
+}
+
+// `VmRSS` or `VmHWM` in KiB
+fn status_kib(key: &str) -> usize {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+        .and_then(|val| val.split_whitespace().next()?.parse().ok())
+        .unwrap_or(0)
+}
+
+unsafe fn touch(ptr: *mut c_void, size: usize) {
+    for offset in (0..size).step_by(PAGE) {
+        unsafe { ptr.cast::<u8>().add(offset).write_volatile(1) };
+    }
+}
+
+struct Replay {
+    // Trace address to replay address and requested size
+    blocks: HashMap<u64, (*mut c_void, usize)>,
+    stats: Stats,
+}
+
+impl Replay {
+    fn timed<R>(&mut self, call: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let ret = call();
+        self.stats.elapsed += start.elapsed();
+        ret
+    }
+
+    unsafe fn allocated(&mut self, record: &TraceRecord, ptr: *mut c_void) {
+        let size = record.size as usize;
+        if ptr.is_null() {
+            self.stats.skipped += 1;
+            return;
+        }
+
+        unsafe { touch(ptr, size) };
+        self.blocks.insert(record.ret, (ptr, size));
+        self.stats.replayed += 1;
+        self.stats.live += size;
+        if self.stats.live > self.stats.peak_live {
+            self.stats.peak_live = self.stats.live;
+            self.stats.footprint_at_peak = footprint();
+        }
+    }
+
+    unsafe fn run(&mut self, record: &TraceRecord) {
+        let Some(op) = TraceOp::from_raw(record.op) else {
+            self.stats.skipped += 1;
+            return;
+        };
+        let size = record.size as usize;
+
+        // A failed call left nothing to replay
+        if op != TraceOp::Free && record.ret == 0 {
+            self.stats.skipped += 1;
+            return;
+        }
+
+        unsafe {
+            match op {
+                TraceOp::Malloc => {
+                    let ptr = self.timed(|| malloc(size));
+                    self.allocated(record, ptr);
+                }
+                TraceOp::Calloc => {
+                    let ptr = self.timed(|| calloc(1, size));
+                    self.allocated(record, ptr);
+                }
+                TraceOp::Memalign => {
+                    let mut ptr = null_mut();
+                    self.timed(|| posix_memalign(&mut ptr, record.align as usize, size));
+                    self.allocated(record, ptr);
+                }
+                TraceOp::Realloc if record.ptr == 0 => {
+                    let ptr = self.timed(|| realloc(null_mut(), size));
+                    self.allocated(record, ptr);
+                }
+                TraceOp::Realloc => {
+                    let Some((old, old_size)) = self.blocks.remove(&record.ptr) else {
+                        self.stats.skipped += 1;
+                        return;
+                    };
+                    self.stats.live -= old_size;
+                    let ptr = self.timed(|| realloc(old, size));
+                    self.allocated(record, ptr);
+                }
+                TraceOp::Free => {
+                    if record.ptr == 0 {
+                        return;
+                    }
+                    let Some((ptr, size)) = self.blocks.remove(&record.ptr) else {
+                        self.stats.skipped += 1;
+                        return;
+                    };
+                    self.timed(|| free(ptr));
+                    self.stats.live -= size;
+                    self.stats.replayed += 1;
+                }
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: ox-replay <trace file>");
+        return ExitCode::FAILURE;
+    };
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("ox-replay: {path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(records) = parse_trace(&bytes) else {
+        eprintln!("ox-replay: {path}: not a trace from this build");
+        return ExitCode::FAILURE;
+    };
+
+    // Stable, calls of one thread keep their order when timestamps tie
+    let mut records: Vec<TraceRecord> = records.collect();
+    records.sort_by_key(|record| record.nanos);
+    drop(bytes);
+    let threads = records
+        .iter()
+        .map(|record| record.tid)
+        .collect::<HashSet<_>>()
+        .len();
+
+    let mut replay = Replay {
+        blocks: HashMap::with_capacity(records.len() / 2),
+        stats: Stats::default(),
+    };
+    let rss_before = status_kib("VmRSS");
+
+    for record in &records {
+        unsafe { replay.run(record) };
+    }
+
+    let stats = &replay.stats;
+    let elapsed = stats.elapsed.as_secs_f64();
+    let peak_rss = status_kib("VmHWM");
+    println!("trace: {path}");
+    println!("records: {} ({threads} threads)", records.len());
+    println!("replayed: {}", stats.replayed);
+    println!("skipped: {}", stats.skipped);
+    println!("elapsed: {elapsed:.3} s");
+    println!(
+        "throughput: {:.0} ops/s",
+        stats.replayed as f64 / elapsed.max(f64::EPSILON)
+    );
+    println!("peak live: {} bytes", stats.peak_live);
+    println!("peak rss: {peak_rss} KiB ({rss_before} KiB before the replay)");
+    println!("footprint at peak live: {} bytes", stats.footprint_at_peak);
+    if stats.peak_live != 0 {
+        println!(
+            "fragmentation at peak live: {:.3}",
+            stats.footprint_at_peak as f64 / stats.peak_live as f64
+        );
+    }
+    println!("live at end: {} bytes", stats.live);
+
+    for &(ptr, _) in replay.blocks.values() {
+        unsafe { free(ptr) };
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,132 @@
+use std::{
+    env, fs,
+    hint::black_box,
+    process::{Command, Output},
+    ptr::null_mut,
+};
+
+use oxidalloc::{
+    abi::{align::posix_memalign, calloc::calloc, free::free, malloc::malloc, realloc::realloc},
+    internals::trace::{TRACE_MAGIC, TRACE_VERSION, TraceOp, TraceRecord},
+};
+
+const CHILD: &str = "OX_REPLAY_TEST_CHILD";
+
+fn trace_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("ox-replay-{}-{name}.bin", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn replay(path: &str) -> Output {
+    let out = Command::new(env!("CARGO_BIN_EXE_ox-replay"))
+        .arg(path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(path);
+    out
+}
+
+// Makes a few calls in a fresh process, traced into the file the parent replays
+#[test]
+fn replay_child() {
+    if env::var_os(CHILD).is_none() {
+        return;
+    }
+
+    unsafe {
+        let ptr = black_box(malloc(12345));
+        let ptr = black_box(realloc(ptr, 234567));
+        let mut aligned = null_mut();
+        assert_eq!(posix_memalign(&mut aligned, 256, 777), 0);
+        let zeroed = black_box(calloc(3, 1000));
+        free(ptr);
+        free(aligned);
+        free(zeroed);
+    }
+}
+
+#[test]
+fn replay_reports_the_trace() {
+    let path = trace_path("calls");
+    let out = Command::new(env::current_exe().unwrap())
+        .args(["replay_child", "--exact", "--nocapture"])
+        .env(CHILD, "1")
+        .env("OX_TRACE", &path)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = replay(&path);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{stdout}");
+
+    let value = |key: &str| -> f64 {
+        let (_, rest) = stdout.split_once(&format!("{key}: ")).unwrap();
+        rest.split_whitespace().next().unwrap().parse().unwrap()
+    };
+    // Only the calls of the child, the test harness stays on the system allocator
+    assert!(stdout.contains("replayed: 7\n"), "{stdout}");
+    assert!(stdout.contains("skipped: 0\n"), "{stdout}");
+    // The realloc'd block and the others were all live at once
+    assert!(value("peak live") >= (234567 + 777 + 3000) as f64);
+    assert!(value("fragmentation at peak live") >= 1.0);
+    assert!(value("peak rss") > 0.0);
+}
+
+#[test]
+fn replay_rejects_other_files() {
+    let path = trace_path("bogus");
+    fs::write(&path, b"not a trace").unwrap();
+    assert!(!replay(&path).status.success());
+}
+
+// Blocks from before the trace can still be passed around
+#[test]
+fn untraced_pointers_are_skipped() {
+    let records = [
+        TraceRecord {
+            nanos: 1,
+            ptr: 0x1000,
+            ret: 0,
+            size: 0,
+            align: 0,
+            tid: 1,
+            op: TraceOp::Free as u32,
+        },
+        TraceRecord {
+            nanos: 2,
+            ptr: 0,
+            ret: 0x2000,
+            size: 64,
+            align: 0,
+            tid: 1,
+            op: TraceOp::Malloc as u32,
+        },
+    ];
+    let mut bytes = Vec::from(TRACE_MAGIC);
+    bytes.extend_from_slice(&TRACE_VERSION.to_ne_bytes());
+    bytes.extend_from_slice(&(size_of::<TraceRecord>() as u32).to_ne_bytes());
+    for record in &records {
+        let raw = unsafe {
+            std::slice::from_raw_parts(
+                (record as *const TraceRecord).cast::<u8>(),
+                size_of::<TraceRecord>(),
+            )
+        };
+        bytes.extend_from_slice(raw);
+    }
+
+    let path = trace_path("skip");
+    fs::write(&path, &bytes).unwrap();
+    let out = replay(&path);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{stdout}");
+    assert!(stdout.contains("replayed: 1\n"));
+    assert!(stdout.contains("skipped: 1\n"));
+    assert!(stdout.contains("live at end: 64 bytes\n"));
+}
@@ -1,18 +1,45 @@
 use std::{
+    hint::likely,
     os::raw::{c_int, c_void},
     ptr::null_mut,
 };
 
 use crate::{
-    abi::malloc::malloc,
-    internals::size_t,
+    MAX_ALLOC_SIZE, OxHeader,
+    abi::malloc::{allocate_class, allocate_cold_aligned, malloc},
+    internals::{
+        size_t,
+        trace::{TraceOp, trace_call, tracing},
+    },
+    slab::{match_aligned_class, match_size_class},
     sys::{EINVAL, NOMEM},
 };
 
 const OFFSET_SIZE: usize = size_of::<usize>();
 const TAG_SIZE: usize = OFFSET_SIZE * 2;
 
-#[unsafe(no_mangle)]
+// Serve the request from a class (or big mapping) that is already aligned, null means use the tag
+#[inline(always)]
+unsafe fn aligned_native(alignment: usize, size: usize, fallback_size: usize) -> *mut c_void {
+    let size = size.max(1);
+
+    // Every payload follows an `OxHeader`, so its alignment comes for free
+    if alignment <= align_of::<OxHeader>() {
+        return malloc(size);
+    }
+
+    if let Some(class) = match_aligned_class(size, alignment, fallback_size) {
+        return allocate_class(class);
+    }
+
+    if alignment <= 4096 && match_size_class(size).is_none() && size <= MAX_ALLOC_SIZE {
+        return allocate_cold_aligned(size) as *mut c_void;
+    }
+
+    null_mut()
+}
+
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn posix_memalign(
     memptr: *mut *mut c_void,
     alignment: usize,
@@ -22,6 +49,16 @@ pub unsafe extern "C" fn posix_memalign(
         return EINVAL;
     }
 
+    // memalign, aligned_alloc and valloc come through here
+    if tracing() {
+        let mut ret = 0;
+        trace_call(TraceOp::Memalign, null_mut(), size, alignment, || {
+            ret = posix_memalign(memptr, alignment, size);
+            if ret == 0 { *memptr } else { null_mut() }
+        });
+        return ret;
+    }
+
     let min = size_of::<*mut c_void>();
     if alignment < min || !alignment.is_power_of_two() {
         return EINVAL;
@@ -34,6 +71,12 @@ pub unsafe extern "C" fn posix_memalign(
         return NOMEM;
     };
 
+    let native = aligned_native(alignment, size, total_requested);
+    if likely(!native.is_null()) {
+        *memptr = native;
+        return 0;
+    }
+
     let mut raw = malloc(total_requested);
     if raw.is_null() {
         let malloc = malloc(total_requested);
@@ -57,7 +100,7 @@ pub unsafe extern "C" fn posix_memalign(
     0
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn memalign(alignment: size_t, size: size_t) -> *mut c_void {
     let mut ptr: *mut c_void = null_mut();
     let adjusted_alignment = if alignment < 8 { 8 } else { alignment };
@@ -73,7 +116,7 @@ pub unsafe extern "C" fn memalign(alignment: size_t, size: size_t) -> *mut c_voi
     }
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn aligned_alloc(alignment: size_t, size: size_t) -> *mut c_void {
     if alignment == 0 || !alignment.is_power_of_two() {
         return null_mut();
@@ -81,7 +124,7 @@ pub unsafe extern "C" fn aligned_alloc(alignment: size_t, size: size_t) -> *mut
     memalign(alignment, size)
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn valloc(size: size_t) -> *mut c_void {
     memalign(4096, size)
 }
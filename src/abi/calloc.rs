@@ -1,7 +1,12 @@
 use crate::{
-    HEADER_SIZE, OxHeader, OxidallocError,
+    FLAG_DIRTY, HEADER_SIZE, OxHeader, OxidallocError,
     abi::malloc::malloc,
-    internals::{__errno_location, hashmap::BIG_ALLOC_MAP, size_t},
+    internals::{
+        __errno_location,
+        hashmap::BIG_ALLOC_MAP,
+        size_t,
+        trace::{TraceOp, trace_call, tracing},
+    },
     slab::SIZE_CLASSES,
     sys::NOMEM,
 };
@@ -26,8 +31,15 @@ unsafe fn calc_and_get(size: Layout, nmem: usize) -> Option<(*mut c_void, usize)
     Some((ptr, effective_size))
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn calloc(nmemb: size_t, size: size_t) -> *mut c_void {
+    if tracing() {
+        let total = nmemb.saturating_mul(size);
+        return trace_call(TraceOp::Calloc, null_mut(), total, 0, || {
+            calloc(nmemb, size)
+        });
+    }
+
     let layout = match Layout::array::<u8>(size) {
         Ok(layout) => layout,
         Err(_) => {
@@ -44,24 +56,28 @@ pub unsafe extern "C" fn calloc(nmemb: size_t, size: size_t) -> *mut c_void {
         unsafe {
             let header = (ptr as *mut u8).sub(HEADER_SIZE) as *mut OxHeader;
 
-            let mut actual_size = (*header).class as usize;
-            if actual_size == 100 {
-                let payload_size = BIG_ALLOC_MAP
-                    .get(header as usize)
-                    .map(|meta| meta.size)
-                    .unwrap_or_else(|| {
-                        OxidallocError::AttackOrCorruption.log_and_abort(
-                            header as *mut c_void,
-                            "Missing big allocation metadata during calloc",
-                            None,
-                        )
-                    });
+            let class = (*header).class as usize;
+            if class == 100 {
+                // Fresh big allocations only get pages that are untouched or were dropped with
+                // DONTNEED, they read back as zero. Writing them would fault in the whole mapping.
+                let Some(meta) = BIG_ALLOC_MAP.get(header as usize) else {
+                    OxidallocError::AttackOrCorruption.report(
+                        header as *mut c_void,
+                        "Missing big allocation metadata during calloc",
+                        None,
+                    );
+                    return null_mut();
+                };
 
-                actual_size = payload_size;
-            } else {
-                actual_size = SIZE_CLASSES[actual_size]
+                if meta.flags & FLAG_DIRTY != 0 {
+                    std::ptr::write_bytes(ptr as *mut u8, 0, effective_size);
+                }
+
+                return ptr;
             }
 
+            let actual_size = SIZE_CLASSES[class];
+
             std::ptr::write_bytes(ptr as *mut u8, 0, actual_size.min(effective_size) as usize);
         }
     }
@@ -0,0 +1,316 @@
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_int, c_void},
+    ptr::{read_unaligned, write_unaligned},
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    MAX_NUMA_NODES, OX_FORCE_THP, OX_RELEASE, OX_TRIM_THRESHOLD, OxidallocError, TOTAL_ALLOCATED,
+    VERSION,
+    abi::{
+        error::{ErrorPolicy, error_policy, set_error_policy},
+        stats::heap_stats,
+    },
+    big_cache::BIG_CACHE,
+    internals::{
+        hashmap::BIG_ALLOC_MAP,
+        prof::{PROF_DROPPED, PROF_LIVE, PROF_SAMPLE, set_prof_sample},
+        size_t,
+    },
+    slab::{
+        NUM_SIZE_CLASSES, SIZE_CLASSES,
+        interconnect::ICC,
+        remote::{REMOTE_DRAINED, REMOTE_FREES},
+        rseq::RSEQ_ICC,
+        thread_local::{TLS, flush_thread_cache},
+    },
+    sys::{EINVAL, ENOENT, EPERM},
+    trim::{
+        gtrim::{GTrim, SLABS_RECLAIMED},
+        ptrim::PTRIM_FLUSHED,
+        thread::{LAST_PRESSURE_CHECK, PRESSURE_WAKEUPS},
+    },
+    va::{
+        bootstrap::{MIN_TRIM_THRESHOLD, boot_strap},
+        numa::{NUMA_NODES, node_stats},
+    },
+};
+
+const MAX_DEPTH: usize = 5;
+
+// Copy `value` out if the caller asked for it, the buffer must be exactly `size_of::<T>()`
+unsafe fn read_out<T: Copy>(oldp: *mut c_void, oldlenp: *mut size_t, value: T) -> c_int {
+    if oldp.is_null() || oldlenp.is_null() {
+        return 0;
+    }
+
+    if *oldlenp != size_of::<T>() {
+        *oldlenp = size_of::<T>();
+        return EINVAL;
+    }
+
+    write_unaligned(oldp as *mut T, value);
+    0
+}
+
+unsafe fn read_in<T: Copy>(newp: *mut c_void, newlen: size_t) -> Result<Option<T>, c_int> {
+    if newp.is_null() {
+        return Ok(None);
+    }
+
+    if newlen != size_of::<T>() {
+        return Err(EINVAL);
+    }
+
+    Ok(Some(read_unaligned(newp as *const T)))
+}
+
+unsafe fn read_only<T: Copy>(
+    oldp: *mut c_void,
+    oldlenp: *mut size_t,
+    newp: *mut c_void,
+    value: T,
+) -> c_int {
+    if !newp.is_null() {
+        return EPERM;
+    }
+
+    read_out(oldp, oldlenp, value)
+}
+
+unsafe fn ctl_class(
+    class: &str,
+    field: &str,
+    oldp: *mut c_void,
+    oldlenp: *mut size_t,
+    newp: *mut c_void,
+) -> c_int {
+    let class = match class.parse::<usize>() {
+        Ok(class) if class < NUM_SIZE_CLASSES => class,
+        _ => return ENOENT,
+    };
+
+    let value = match field {
+        "size" => SIZE_CLASSES[class],
+        "icc_usage" => ICC.get_size(class),
+        "tls_usage" => {
+            let tls = TLS;
+            if tls.is_null() {
+                0
+            } else {
+                (*tls).tls[class].usage
+            }
+        }
+        _ => return ENOENT,
+    };
+
+    read_only(oldp, oldlenp, newp, value)
+}
+
+unsafe fn ctl_numa(
+    node: &str,
+    field: &str,
+    oldp: *mut c_void,
+    oldlenp: *mut size_t,
+    newp: *mut c_void,
+) -> c_int {
+    let node = match node.parse::<usize>() {
+        Ok(node) if node < NUMA_NODES.min(MAX_NUMA_NODES) => node,
+        _ => return ENOENT,
+    };
+
+    let stats = node_stats(node);
+    let value = match field {
+        "local" => stats.local.load(Ordering::Relaxed),
+        "remote" => stats.remote.load(Ordering::Relaxed),
+        "bound" => stats.bound.load(Ordering::Relaxed),
+        _ => return ENOENT,
+    };
+
+    read_only(oldp, oldlenp, newp, value)
+}
+
+// 0 abort, 1 return NULL, 2 log and continue
+unsafe fn ctl_error_policy(
+    name: &str,
+    oldp: *mut c_void,
+    oldlenp: *mut size_t,
+    newp: *mut c_void,
+    newlen: size_t,
+) -> c_int {
+    let Some(err) = OxidallocError::ALL
+        .into_iter()
+        .find(|err| err.name() == name)
+    else {
+        return ENOENT;
+    };
+
+    let new = match read_in::<u8>(newp, newlen) {
+        Ok(Some(val)) => match ErrorPolicy::from_u8(val) {
+            Some(policy) => Some(policy),
+            None => return EINVAL,
+        },
+        Ok(None) => None,
+        Err(err) => return err,
+    };
+
+    let ret = read_out(oldp, oldlenp, error_policy(err) as u8);
+    if ret == 0
+        && let Some(policy) = new
+    {
+        set_error_policy(err, policy);
+    }
+    ret
+}
+
+// Hand the calling thread's cached blocks back to the ICC, a no-op for threads that never allocated
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn ox_thread_cache_flush() {
+    let tls = TLS;
+    if !tls.is_null() {
+        flush_thread_cache(&mut *tls);
+    }
+}
+
+// jemalloc style control entry point. The current value is written to `oldp` (when given),
+// then `newp` is applied. Returns 0 or an errno value: ENOENT for unknown names, EINVAL for
+// wrongly sized buffers, EPERM for writes to read-only names.
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn ox_ctl(
+    name: *const c_char,
+    oldp: *mut c_void,
+    oldlenp: *mut size_t,
+    newp: *mut c_void,
+    newlen: size_t,
+) -> c_int {
+    if name.is_null() {
+        return EINVAL;
+    }
+
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return ENOENT;
+    };
+
+    let mut parts = [""; MAX_DEPTH];
+    let mut depth = 0;
+    for part in name.split('.') {
+        if depth == MAX_DEPTH {
+            return ENOENT;
+        }
+        parts[depth] = part;
+        depth += 1;
+    }
+
+    boot_strap();
+
+    match parts[..depth] {
+        ["version"] => read_only(oldp, oldlenp, newp, VERSION),
+        ["trim", "threshold"] => {
+            let new = match read_in::<usize>(newp, newlen) {
+                Ok(new) => new,
+                Err(err) => return err,
+            };
+
+            let ret = read_out(oldp, oldlenp, OX_TRIM_THRESHOLD.load(Ordering::Relaxed));
+            if ret == 0
+                && let Some(val) = new
+            {
+                OX_TRIM_THRESHOLD.store(val.max(MIN_TRIM_THRESHOLD), Ordering::Relaxed);
+            }
+            ret
+        }
+        ["thp", "force"] => {
+            // Read as a byte, anything but 0 or 1 is not a valid `bool`
+            let new = match read_in::<u8>(newp, newlen) {
+                Ok(Some(val @ (0 | 1))) => Some(val == 1),
+                Ok(Some(_)) => return EINVAL,
+                Ok(None) => None,
+                Err(err) => return err,
+            };
+
+            let ret = read_out(oldp, oldlenp, OX_FORCE_THP.load(Ordering::Relaxed));
+            if ret == 0
+                && let Some(val) = new
+            {
+                OX_FORCE_THP.store(val, Ordering::Relaxed);
+            }
+            ret
+        }
+        // 0 dontneed, 1 free, 2 cold, 3 pageout
+        ["release", "policy"] => read_only(oldp, oldlenp, newp, OX_RELEASE as u8),
+        ["stats", "allocated"] => {
+            read_only(oldp, oldlenp, newp, TOTAL_ALLOCATED.load(Ordering::Relaxed))
+        }
+        ["stats", "in_use"] => read_only(oldp, oldlenp, newp, heap_stats().in_use),
+        ["stats", "big", "count"] => read_only(oldp, oldlenp, newp, BIG_ALLOC_MAP.len()),
+        ["stats", "big", "bytes"] => read_only(oldp, oldlenp, newp, BIG_ALLOC_MAP.bytes()),
+        ["stats", "big", "cached"] => read_only(oldp, oldlenp, newp, BIG_CACHE.bytes()),
+        ["stats", "remote", "frees"] => {
+            read_only(oldp, oldlenp, newp, REMOTE_FREES.load(Ordering::Relaxed))
+        }
+        ["stats", "remote", "drained"] => {
+            read_only(oldp, oldlenp, newp, REMOTE_DRAINED.load(Ordering::Relaxed))
+        }
+        ["stats", "pressure", "percent"] => read_only(
+            oldp,
+            oldlenp,
+            newp,
+            LAST_PRESSURE_CHECK.load(Ordering::Relaxed),
+        ),
+        ["stats", "pressure", "wakeups"] => read_only(
+            oldp,
+            oldlenp,
+            newp,
+            PRESSURE_WAKEUPS.load(Ordering::Relaxed),
+        ),
+        ["stats", "slabs", "reclaimed"] => {
+            read_only(oldp, oldlenp, newp, SLABS_RECLAIMED.load(Ordering::Relaxed))
+        }
+        ["stats", "ptrim", "flushed"] => {
+            read_only(oldp, oldlenp, newp, PTRIM_FLUSHED.load(Ordering::Relaxed))
+        }
+        ["stats", "icc", "rseq"] => {
+            ICC.ensure_cache();
+            read_only(oldp, oldlenp, newp, RSEQ_ICC)
+        }
+        ["stats", "class", class, field] => ctl_class(class, field, oldp, oldlenp, newp),
+        ["stats", "numa", "nodes"] => read_only(oldp, oldlenp, newp, NUMA_NODES),
+        ["stats", "numa", node, field] => ctl_numa(node, field, oldp, oldlenp, newp),
+        ["prof", "sample"] => {
+            let new = match read_in::<usize>(newp, newlen) {
+                Ok(new) => new,
+                Err(err) => return err,
+            };
+
+            let ret = read_out(oldp, oldlenp, PROF_SAMPLE.load(Ordering::Relaxed));
+            if ret == 0
+                && let Some(val) = new
+            {
+                set_prof_sample(val);
+            }
+            ret
+        }
+        ["prof", "live"] => read_only(oldp, oldlenp, newp, PROF_LIVE.load(Ordering::Relaxed)),
+        ["prof", "dropped"] => read_only(oldp, oldlenp, newp, PROF_DROPPED.load(Ordering::Relaxed)),
+        ["error", "policy", name] => ctl_error_policy(name, oldp, oldlenp, newp, newlen),
+        ["thread", "tcache", "flush"] => {
+            if !oldp.is_null() || !newp.is_null() {
+                return EPERM;
+            }
+
+            ox_thread_cache_flush();
+            0
+        }
+        // Optional `newp` is the trim pad, optional `oldp` receives the bytes released
+        ["arena", "trim"] => {
+            let pad = match read_in::<usize>(newp, newlen) {
+                Ok(pad) => pad.unwrap_or(0),
+                Err(err) => return err,
+            };
+
+            read_out(oldp, oldlenp, GTrim.trim(pad).1)
+        }
+        _ => ENOENT,
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    ffi::c_void,
+    os::raw::c_char,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicU8, Ordering},
+};
+
+use crate::{NUM_ERRORS, OxidallocError};
+
+// Called with the error code, the pointer involved (may be NULL) and a NUL terminated context
+// string that is only valid during the call. It runs inside the allocator: it must not allocate
+// or free, but it may log, dump state or exit.
+pub type OxErrorHandler = unsafe extern "C" fn(code: u32, ptr: *mut c_void, context: *const c_char);
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Abort = 0,
+    // Fail the operation without logging
+    ReturnNull = 1,
+    // Log the error, then fail the operation and keep running
+    Continue = 2,
+}
+
+impl ErrorPolicy {
+    pub const fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Abort),
+            1 => Some(Self::ReturnNull),
+            2 => Some(Self::Continue),
+            _ => None,
+        }
+    }
+}
+
+// Running out of memory is an ordinary ENOMEM, everything else aborts until told otherwise
+const fn default_policies() -> [AtomicU8; NUM_ERRORS] {
+    let mut table = [const { AtomicU8::new(ErrorPolicy::Abort as u8) }; NUM_ERRORS];
+    table[OxidallocError::OutOfMemory.index()] = AtomicU8::new(ErrorPolicy::ReturnNull as u8);
+    table[OxidallocError::VaBitmapExhausted.index()] = AtomicU8::new(ErrorPolicy::ReturnNull as u8);
+    table
+}
+
+static ERROR_POLICY: [AtomicU8; NUM_ERRORS] = default_policies();
+static ERROR_HANDLER: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+#[thread_local]
+static mut IN_HANDLER: bool = false;
+
+pub fn error_policy(err: OxidallocError) -> ErrorPolicy {
+    ErrorPolicy::from_u8(ERROR_POLICY[err.index()].load(Ordering::Relaxed))
+        .unwrap_or(ErrorPolicy::Abort)
+}
+
+pub fn set_error_policy(err: OxidallocError, policy: ErrorPolicy) {
+    ERROR_POLICY[err.index()].store(policy as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn call_error_handler(err: OxidallocError, ptr: *mut c_void, extra: &str) {
+    let handler = ERROR_HANDLER.load(Ordering::Acquire);
+    // An error raised from inside the handler goes straight to the policy
+    if handler.is_null() || unsafe { IN_HANDLER } {
+        return;
+    }
+
+    let mut context = [0u8; 256];
+    let len = extra.len().min(context.len() - 1);
+    context[..len].copy_from_slice(&extra.as_bytes()[..len]);
+
+    unsafe {
+        let handler = std::mem::transmute::<*mut c_void, OxErrorHandler>(handler);
+        IN_HANDLER = true;
+        handler(err as u32, ptr, context.as_ptr() as *const c_char);
+        IN_HANDLER = false;
+    }
+}
+
+// Installs `handler` for every allocator error and returns the previous one, NULL removes it.
+// The handler runs before the error's policy is applied, so it is also the last chance to act
+// on errors that abort.
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn ox_set_error_handler(
+    handler: Option<OxErrorHandler>,
+) -> Option<OxErrorHandler> {
+    let new = handler.map_or(null_mut(), |handler| handler as *mut c_void);
+    let old = ERROR_HANDLER.swap(new, Ordering::AcqRel);
+    if old.is_null() {
+        None
+    } else {
+        Some(std::mem::transmute::<*mut c_void, OxErrorHandler>(old))
+    }
+}
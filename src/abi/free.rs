@@ -1,3 +1,5 @@
+#[cfg(feature = "hardened-malloc")]
+use crate::slab::quarantine::Quarantine;
 use crate::{
     FREED_MAGIC, HEADER_SIZE, MAGIC, OX_ALIGN_TAG, OX_CURRENT_STAMP, OxHeader, OxidallocError,
     abi::{
@@ -5,8 +7,15 @@ use crate::{
         malloc::{HOT_READY, TOTAL_MALLOC_FREE},
     },
     big_allocation::big_free,
-    internals::size_t,
-    slab::{TLS_MAX_BLOCKS, global::GlobalHandler, thread_local::ThreadLocalEngine},
+    internals::{
+        prof::prof_free,
+        size_t,
+        trace::{TraceOp, trace_call, tracing},
+    },
+    slab::{
+        TLS_MAX_BLOCKS, global::GlobalHandler, remote::push_remote, thread_local::ThreadLocalEngine,
+    },
+    trim::ptrim::ptrim_if_marked,
     va::is_ours,
 };
 use std::{
@@ -45,34 +54,39 @@ macro_rules! free_main {
     }};
 }
 
+// False when the block must not be touched, the caller gives up on it
 #[inline(always)]
-pub unsafe fn validate_ptr_for_abi(header: *mut OxHeader) {
+pub unsafe fn validate_ptr_for_abi(header: *mut OxHeader) -> bool {
     let magic = read_volatile(&(*header).magic);
     if likely(magic == MAGIC) {
-        return;
+        return true;
     }
 
     if magic == FREED_MAGIC {
-        OxidallocError::DoubleFree.log_and_abort(
+        OxidallocError::DoubleFree.report(
             header as *mut c_void,
             "Pointer is tagged as in_use",
             None,
         );
+        return false;
     }
 
-    OxidallocError::AttackOrCorruption.log_and_abort(
-        null_mut() as *mut c_void,
-        "Attack or corruption detected; aborting process. External system access and RAM module checks recommended.",
+    OxidallocError::AttackOrCorruption.report(
+        header as *mut c_void,
+        "Attack or corruption detected. External system access and RAM module checks recommended.",
         None,
     );
+    false
 }
 
 #[inline(always)]
-unsafe fn free_internal(ptr: *mut c_void) {
+pub(crate) unsafe fn free_internal(ptr: *mut c_void) {
     let header_addr = (ptr as usize).wrapping_sub(HEADER_SIZE);
     let header = header_addr as *mut OxHeader;
 
-    validate_ptr_for_abi(header);
+    if unlikely(!validate_ptr_for_abi(header)) {
+        return;
+    }
 
     let class = (*header).class as usize;
     if unlikely(class == 100) {
@@ -80,12 +94,39 @@ unsafe fn free_internal(ptr: *mut c_void) {
         return;
     }
 
+    prof_free(ptr);
     (*header).magic = FREED_MAGIC;
     (*header).life_time = OX_CURRENT_STAMP;
 
     let thread = ThreadLocalEngine::get_or_init();
+    ptrim_if_marked(thread);
+
+    #[cfg(feature = "hardened-malloc")]
+    if Quarantine::admits(class) {
+        while let Some(old) = thread.quarantine.evict_for(class) {
+            release_block(thread, old);
+        }
+        thread.quarantine.push(header, class);
+        return;
+    }
+
+    release_block(thread, header);
+}
+
+// Hands a freed small block to its owner, the thread bin, or the shared caches once the bin is full
+#[inline(always)]
+pub(crate) unsafe fn release_block(thread: &mut ThreadLocalEngine, header: *mut OxHeader) {
+    let class = (*header).class as usize;
+    let owner = (*header).owner;
+    if unlikely(owner != thread.owner && owner != 0) && push_remote(owner, header) {
+        return;
+    }
     if thread.tls[class].usage >= TLS_MAX_BLOCKS[class] {
-        GlobalHandler.push_to_global(class, header, header, 1);
+        if cfg!(feature = "experimental-cpu-local-global") {
+            thread.spill_to_cpu(class, header);
+        } else {
+            GlobalHandler.push_to_global(class, header, header, 1);
+        }
         return;
     };
 
@@ -105,8 +146,16 @@ unsafe fn free_boot_segment(ptr: *mut c_void) {
     free_main!(ptr)
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if tracing() {
+        trace_call(TraceOp::Free, ptr, 0, 0, || {
+            free(ptr);
+            null_mut()
+        });
+        return;
+    }
+
     if likely(HOT_READY) {
         free_fast(ptr);
     } else {
@@ -114,12 +163,12 @@ pub unsafe extern "C" fn free(ptr: *mut c_void) {
     }
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn free_sized(ptr: *mut c_void, _: size_t) {
     free(ptr);
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn free_aligned_sized(ptr: *mut c_void, _: size_t, _: size_t) {
     free(ptr);
 }
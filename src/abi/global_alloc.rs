@@ -0,0 +1,103 @@
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    hint::likely,
+    os::raw::c_void,
+    ptr::{copy_nonoverlapping, null_mut, write_bytes},
+};
+
+use crate::{
+    OxHeader,
+    abi::{
+        align::posix_memalign,
+        calloc::calloc,
+        free::{free, free_internal},
+        malloc::{allocate_class, malloc},
+        realloc::realloc_internal,
+    },
+    slab::match_size_class,
+};
+
+// Slab blocks and big allocation payloads both start right after an `OxHeader`,
+// so everything up to its alignment is served without the aligned tag
+const NATURAL_ALIGN: usize = align_of::<OxHeader>();
+
+/// Rust global allocator entry point.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: oxidalloc::Oxidalloc = oxidalloc::Oxidalloc;
+/// ```
+///
+/// Build with `default-features = false` so the C symbols (`c-abi` feature) are not exported
+/// alongside it.
+pub struct Oxidalloc;
+
+#[cold]
+#[inline(never)]
+unsafe fn alloc_aligned(layout: Layout) -> *mut u8 {
+    let mut ptr: *mut c_void = null_mut();
+    if posix_memalign(&raw mut ptr, layout.align(), layout.size()) != 0 {
+        return null_mut();
+    }
+
+    ptr as *mut u8
+}
+
+unsafe impl GlobalAlloc for Oxidalloc {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if likely(layout.align() <= NATURAL_ALIGN) {
+            if let Some(class) = match_size_class(layout.size()) {
+                return allocate_class(class) as *mut u8;
+            }
+
+            return malloc(layout.size()) as *mut u8;
+        }
+
+        alloc_aligned(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Pointers with natural alignment never carry the aligned tag, skip the probe
+        if likely(layout.align() <= NATURAL_ALIGN) {
+            free_internal(ptr as *mut c_void);
+            return;
+        }
+
+        free(ptr as *mut c_void);
+    }
+
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // `calloc` knows which blocks are already zero
+        if likely(layout.align() <= NATURAL_ALIGN) {
+            return calloc(1, layout.size()) as *mut u8;
+        }
+
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            write_bytes(ptr, 0, layout.size());
+        }
+
+        ptr
+    }
+
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if likely(layout.align() <= NATURAL_ALIGN) {
+            return realloc_internal(ptr as *mut c_void, ptr as *mut c_void, 0, new_size)
+                as *mut u8;
+        }
+
+        // Over-aligned blocks must keep their alignment, realloc cannot promise that
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+
+        new_ptr
+    }
+}
@@ -6,16 +6,29 @@ use std::{
 };
 
 use crate::{
-    HEADER_SIZE, MAGIC, OX_ALIGN_TAG, OxHeader, OxidallocError,
+    Err, HEADER_SIZE, MAGIC, MAX_ALLOC_SIZE, OX_ALIGN_TAG, OX_BACKGROUND_THREAD, OX_CURRENT_STAMP,
+    OxHeader, OxidallocError,
     abi::fallback::malloc_usable_size_fallback,
-    big_allocation::big_malloc,
-    internals::{__errno_location, hashmap::BIG_ALLOC_MAP, size_t},
+    big_allocation::{big_malloc, big_malloc_aligned},
+    internals::{
+        __errno_location,
+        hashmap::BIG_ALLOC_MAP,
+        prof::{prof_tick, sample_malloc},
+        size_t,
+        trace::{TraceOp, trace_call, tracing},
+        unwind::frame_pointer,
+    },
     slab::{
-        NUM_SIZE_CLASSES, SIZE_CLASSES, bulk_allocation::bulk_fill, global::GlobalHandler,
-        match_size_class, thread_local::ThreadLocalEngine,
+        NUM_SIZE_CLASSES, SIZE_CLASSES, TLS_MAX_BLOCKS, bulk_allocation::bulk_fill,
+        global::GlobalHandler, match_size_class, remote::drain_remote,
+        thread_local::ThreadLocalEngine,
     },
     sys::NOMEM,
-    trim::{gtrim::GTrim, thread::spawn_gtrim_thread},
+    trim::{
+        gtrim::GTrim,
+        ptrim::ptrim_if_marked,
+        thread::{spawn_gtrim_thread, trim_on_slow_path},
+    },
     va::{bootstrap::boot_strap, is_ours},
 };
 
@@ -47,7 +60,7 @@ pub(crate) unsafe fn validate_ptr(ptr: *mut OxHeader) -> u64 {
     let magic = read_volatile(&(*ptr).magic);
     if unlikely(magic != MAGIC && magic != FREED_MAGIC) {
         OxidallocError::AttackOrCorruption.log_and_abort(
-            null_mut() as *mut c_void,
+            ptr as *mut c_void,
             "Attack or corruption detected; aborting process. External system access and RAM module checks recommended.",
             None,
         )
@@ -59,10 +72,23 @@ pub(crate) unsafe fn validate_ptr(ptr: *mut OxHeader) -> u64 {
 #[inline(never)]
 unsafe fn try_fill(thread: &mut ThreadLocalEngine, class: usize) -> *mut OxHeader {
     let mut output = null_mut();
+    trim_on_slow_path();
 
-    let batch = BATCH_HINTS[class]
+    // Blocks other threads freed on our behalf come back first
+    if drain_remote(thread) > 0 {
+        output = thread.pop_from_thread(class);
+        if !output.is_null() {
+            return output;
+        }
+    }
+
+    let mut batch = BATCH_HINTS[class]
         .load(Ordering::Relaxed)
         .clamp(BATCH_MIN, BATCH_MAX);
+    // The front cache takes half its capacity at once, the CPU shard keeps the rest
+    if cfg!(feature = "experimental-cpu-local-global") {
+        batch = batch.min(TLS_MAX_BLOCKS[class].div_ceil(2));
+    }
 
     let global_cache = GlobalHandler.pop_from_global(class, batch);
 
@@ -70,9 +96,12 @@ unsafe fn try_fill(thread: &mut ThreadLocalEngine, class: usize) -> *mut OxHeade
         let mut tail = global_cache;
         let mut real = 1;
 
-        // Loop through cache and found the last header and set linked list to null
+        // Loop through cache and found the last header and set linked list to null, the stamp keeps
+        // a refilled bin from looking cold to ptrim
+        (*tail).life_time = OX_CURRENT_STAMP;
         while real < batch && !(*tail).next.is_null() && is_ours((*tail).next as usize) {
             tail = (*tail).next;
+            (*tail).life_time = OX_CURRENT_STAMP;
             real += 1;
         }
         (*tail).next = null_mut();
@@ -97,8 +126,11 @@ unsafe fn try_fill(thread: &mut ThreadLocalEngine, class: usize) -> *mut OxHeade
                 bump_batch_hint(class, true);
                 break;
             }
-            Err(_) => match i {
-                2 => return null_mut(),
+            Err(err) => match i {
+                2 => {
+                    slab_exhausted(err);
+                    return null_mut();
+                }
                 _ => continue,
             },
         }
@@ -107,6 +139,17 @@ unsafe fn try_fill(thread: &mut ThreadLocalEngine, class: usize) -> *mut OxHeade
     output
 }
 
+// No slab could be carved, malloc fails with ENOMEM unless the error policy aborts
+#[cold]
+unsafe fn slab_exhausted(err: Err) {
+    let err = match err {
+        Err::OutOfReservation => OxidallocError::VaBitmapExhausted,
+        Err::OutOfMemory => OxidallocError::OutOfMemory,
+    };
+    err.report(null_mut(), "Cannot carve a new slab", None);
+    *__errno_location() = NOMEM;
+}
+
 #[inline(always)]
 fn bump_batch_hint(class: usize, up: bool) {
     let _ = BATCH_HINTS[class].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |val| {
@@ -123,6 +166,7 @@ fn bump_batch_hint(class: usize, up: bool) {
 #[inline(always)]
 unsafe fn allocate_hot(class: usize) -> *mut c_void {
     let thread = ThreadLocalEngine::get_or_init();
+    ptrim_if_marked(thread);
     let mut cache = thread.pop_from_thread(class);
 
     // Check if cache is null
@@ -153,6 +197,7 @@ unsafe fn allocate_hot(class: usize) -> *mut c_void {
         (*cache).next = null_mut();
     }
     (*cache).magic = MAGIC;
+    (*cache).owner = thread.owner;
 
     cache.add(1) as *mut c_void
 }
@@ -170,7 +215,9 @@ unsafe fn allocate_boot_segment(class: usize) -> *mut c_void {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            spawn_gtrim_thread();
+            if OX_BACKGROUND_THREAD {
+                spawn_gtrim_thread();
+            }
             HOT_READY = true;
         }
     }
@@ -206,20 +253,81 @@ unsafe fn allocate_boot_segment(class: usize) -> *mut c_void {
         (*cache).next = null_mut();
     }
     (*cache).magic = MAGIC;
+    (*cache).owner = thread.owner;
 
     cache.add(1) as *mut c_void
 }
 
+#[inline(always)]
+pub(crate) unsafe fn allocate_class(class: usize) -> *mut c_void {
+    if unlikely(prof_tick(SIZE_CLASSES[class])) {
+        return sample_class(class);
+    }
+
+    if likely(HOT_READY) {
+        allocate_hot(class)
+    } else {
+        allocate_boot_segment(class)
+    }
+}
+
 #[cold]
 #[inline(never)]
 pub unsafe fn allocate_cold(size: usize) -> *mut u8 {
     boot_strap();
 
-    big_malloc(size)
+    let ptr = big_malloc(size);
+    if ptr.is_null() {
+        *__errno_location() = NOMEM;
+    }
+    ptr
 }
 
-#[unsafe(no_mangle)]
+#[cold]
+#[inline(never)]
+pub unsafe fn allocate_cold_aligned(size: usize) -> *mut u8 {
+    boot_strap();
+
+    let ptr = big_malloc_aligned(size);
+    if ptr.is_null() {
+        *__errno_location() = NOMEM;
+    }
+    ptr
+}
+
+#[cold]
+#[inline(never)]
+unsafe fn sample_class(class: usize) -> *mut c_void {
+    sample_malloc(SIZE_CLASSES[class], frame_pointer(), || {
+        if HOT_READY {
+            allocate_hot(class)
+        } else {
+            allocate_boot_segment(class)
+        }
+    })
+}
+
+#[cold]
+#[inline(never)]
+unsafe fn sample_sized(size: usize) -> *mut c_void {
+    sample_malloc(size, frame_pointer(), || malloc_unsampled(size))
+}
+
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn malloc(size: size_t) -> *mut c_void {
+    if tracing() {
+        return trace_call(TraceOp::Malloc, null_mut(), size, 0, || malloc(size));
+    }
+
+    if unlikely(prof_tick(size)) {
+        return sample_sized(size);
+    }
+
+    malloc_unsampled(size)
+}
+
+#[inline(always)]
+unsafe fn malloc_unsampled(size: usize) -> *mut c_void {
     if likely(size <= 4096 && size > 0) {
         let index = (size - 1) >> 4;
         let class = unsafe { *crate::slab::SIZE_LUT.get_unchecked(index) as usize };
@@ -230,7 +338,7 @@ pub unsafe extern "C" fn malloc(size: size_t) -> *mut c_void {
         };
     }
 
-    if unlikely(size > 1024 * 1024 * 1024 * 3) {
+    if unlikely(size > MAX_ALLOC_SIZE) {
         *__errno_location() = NOMEM;
         return null_mut();
     }
@@ -246,7 +354,7 @@ pub unsafe extern "C" fn malloc(size: size_t) -> *mut c_void {
     return allocate_cold(size) as *mut c_void;
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> size_t {
     if ptr.is_null() {
         return 0;
@@ -273,17 +381,15 @@ pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> size_t {
 
     let class = (*header).class as usize;
     let raw_usable = if class == 100 {
-        let payload_size = BIG_ALLOC_MAP
-            .get(header as usize)
-            .map(|meta| meta.size)
-            .unwrap_or_else(|| {
-                OxidallocError::AttackOrCorruption.log_and_abort(
-                    header as *mut c_void,
-                    "Missing big allocation metadata during malloc_usable_size",
-                    None,
-                )
-            });
-        payload_size
+        let Some(meta) = BIG_ALLOC_MAP.get(header as usize) else {
+            OxidallocError::AttackOrCorruption.report(
+                header as *mut c_void,
+                "Missing big allocation metadata during malloc_usable_size",
+                None,
+            );
+            return 0;
+        };
+        meta.size
     } else {
         SIZE_CLASSES[class]
     };
@@ -291,7 +397,7 @@ pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> size_t {
     raw_usable.saturating_sub(offset) as size_t
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn malloc_trim(pad: size_t) -> c_int {
     let is_ok_g = GTrim.trim(pad);
 
@@ -1,6 +1,11 @@
 pub mod align;
 pub mod calloc;
+pub mod ctl;
+pub mod error;
 pub mod fallback;
 pub mod free;
+pub mod global_alloc;
 pub mod malloc;
+pub mod prof;
 pub mod realloc;
+pub mod stats;
@@ -0,0 +1,29 @@
+use std::os::raw::{c_char, c_int};
+
+use crate::{
+    internals::{__errno_location, pprof::write_heap_profile},
+    sys::{EINVAL, NOMEM},
+};
+
+// Writes the sampled heap (live and cumulative, see `internals/pprof.rs`) to `path` as a pprof
+// profile, replacing the file. Returns 0 or an errno value. Sampling is enabled with
+// `OX_PROF_SAMPLE` or `prof.sample`, without it the profile is empty.
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn ox_prof_dump(path: *const c_char) -> c_int {
+    if path.is_null() {
+        return EINVAL;
+    }
+
+    let fd = libc::open(
+        path,
+        libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+        0o644,
+    );
+    if fd < 0 {
+        return *__errno_location();
+    }
+
+    let written = write_heap_profile(fd);
+    libc::close(fd);
+    if written { 0 } else { NOMEM }
+}
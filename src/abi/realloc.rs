@@ -1,18 +1,21 @@
-use std::{os::raw::c_void, ptr::null_mut};
+use std::{os::raw::c_void, ptr::null_mut, sync::atomic::Ordering};
 
 use crate::{
-    HEADER_SIZE, OX_ALIGN_TAG, OxHeader, OxidallocError,
+    FLAG_GUARD_AFTER, FLAG_GUARD_BEFORE, FLAG_THP, HEADER_SIZE, MAX_ALLOC_SIZE, OX_ALIGN_TAG,
+    OxHeader, OxidallocError, TOTAL_ALLOCATED,
     abi::{
         fallback::realloc_fallback,
         free::{free, validate_ptr_for_abi},
         malloc::malloc,
     },
+    big_allocation::{big_realloc_move, guard_sizes, protect_guards},
     internals::{
         __errno_location,
         hashmap::{BIG_ALLOC_MAP, BigAllocMeta},
         size_t,
+        trace::{TraceOp, trace_call, tracing},
     },
-    slab::{ITERATIONS, SIZE_CLASSES, match_size_class},
+    slab::{ITERATIONS, SIZE_CLASSES, is_class_aligned, match_size_class},
     sys::{
         NOMEM,
         memory_system::{MadviseFlags, RMProtFlags, madvise, protect_memory},
@@ -23,15 +26,28 @@ use crate::{
 const OFFSET_SIZE: usize = size_of::<usize>();
 const TAG_SIZE: usize = OFFSET_SIZE * 2;
 
-#[unsafe(no_mangle)]
+// A block resized in place keeps its mapping, only the part of it counted as slab bytes changes.
+// Big allocations are counted by `BIG_ALLOC_MAP` instead.
+unsafe fn account_resize(old_class: u8, new_class: u8, old_total: usize, new_total: usize) {
+    let before = if old_class == 100 { 0 } else { old_total };
+    let after = if new_class == 100 { 0 } else { new_total };
+    if after > before {
+        TOTAL_ALLOCATED.fetch_add(after - before, Ordering::Relaxed);
+    } else {
+        TOTAL_ALLOCATED.fetch_sub(before - after, Ordering::Relaxed);
+    }
+}
+
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_void {
-    if ptr.is_null() {
-        return malloc(new_size);
+    if tracing() {
+        return trace_call(TraceOp::Realloc, ptr, new_size, 0, || {
+            realloc(ptr, new_size)
+        });
     }
 
-    if new_size > 1024 * 1024 * 1024 * 3 {
-        *__errno_location() = NOMEM;
-        return null_mut();
+    if ptr.is_null() {
+        return malloc(new_size);
     }
 
     if !is_ours(ptr as usize) {
@@ -56,22 +72,42 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
         }
     }
 
+    realloc_internal(ptr, raw_ptr, offset, new_size)
+}
+
+// `raw_ptr` is the real payload start and `offset` the distance to `ptr` (non-zero for tagged aligned pointers)
+#[inline(always)]
+pub(crate) unsafe fn realloc_internal(
+    ptr: *mut c_void,
+    raw_ptr: *mut c_void,
+    offset: usize,
+    new_size: usize,
+) -> *mut c_void {
+    if new_size > MAX_ALLOC_SIZE {
+        *__errno_location() = NOMEM;
+        return null_mut();
+    }
+
     let header = (raw_ptr as *mut OxHeader).sub(1);
 
-    validate_ptr_for_abi(header);
+    // The old block is left alone, as for any failed realloc
+    if !validate_ptr_for_abi(header) {
+        return null_mut();
+    }
 
     let raw_capacity;
+    let mut big_flags = 0;
     if (*header).class == 100 {
-        raw_capacity = BIG_ALLOC_MAP
-            .get(header as usize)
-            .map(|meta| meta.size)
-            .unwrap_or_else(|| {
-                OxidallocError::AttackOrCorruption.log_and_abort(
-                    header as *mut c_void,
-                    "Missing big allocation metadata during realloc",
-                    None,
-                )
-            });
+        let Some(meta) = BIG_ALLOC_MAP.get(header as usize) else {
+            OxidallocError::AttackOrCorruption.report(
+                header as *mut c_void,
+                "Missing big allocation metadata during realloc",
+                None,
+            );
+            return null_mut();
+        };
+        raw_capacity = meta.size;
+        big_flags = meta.flags;
     } else {
         raw_capacity = SIZE_CLASSES[(*header).class as usize];
     }
@@ -105,7 +141,22 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
         }
     }
 
-    if old_class == 100 || it == 1 {
+    // Only page-granular mappings that start at the header can be resized in place, and the
+    // payload must still meet the alignment of the class it is moved to. Guarded mappings stay big.
+    let (_, guard) = guard_sizes(big_flags);
+    let resizable = if old_class == 100 {
+        (header as usize) & 4095 == 0
+            && big_flags & FLAG_THP == 0
+            && (big_flags & (FLAG_GUARD_BEFORE | FLAG_GUARD_AFTER) == 0 || new_class.is_none())
+    } else {
+        it == 1
+    };
+    let keeps_align = new_class.is_none_or(|class| is_class_aligned(ptr as usize, class));
+    // Blocks of multi block classes are found through the slab they were carved from, which a
+    // block resized in place does not have
+    let single_block = new_class.is_none_or(|class| ITERATIONS[class] == 1);
+
+    if resizable && keeps_align && single_block {
         let is_big = old_class == 100;
         let is_big_new = new_class.unwrap_or(100) == 100;
 
@@ -135,6 +186,7 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
 
         if new_total == old_total {
             (*header).class = new_class;
+            account_resize(old_class, new_class, old_total, new_total);
             if old_class == 100 && new_class != 100 {
                 let _ = BIG_ALLOC_MAP.remove(header as usize);
             } else if new_class == 100 {
@@ -144,7 +196,7 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
                         size,
                         class: 100,
                         life_time: 0,
-                        flags: 0,
+                        flags: big_flags,
                     },
                 );
             }
@@ -167,9 +219,11 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
                     let _ =
                         protect_memory(freed_start as *mut c_void, freed_len, RMProtFlags::NONE);
 
-                    VA_MAP.free(freed_start, freed_len);
+                    // The first freed page becomes the new guard, the old one goes back instead
+                    VA_MAP.free(freed_start + guard, freed_len);
 
                     (*header).class = new_class;
+                    account_resize(old_class, new_class, old_total, new_total);
                     if old_class == 100 && new_class != 100 {
                         let _ = BIG_ALLOC_MAP.remove(header as usize);
                     } else if new_class == 100 {
@@ -179,7 +233,7 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
                                 size,
                                 class: 100,
                                 life_time: 0,
-                                flags: 0,
+                                flags: big_flags,
                             },
                         );
                     }
@@ -189,11 +243,13 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
             return ptr;
         }
 
+        // The guard moves to the end of the grown range, the old one becomes payload
         if let Some(actual_new_va_size) = VA_MAP.realloc_inplace(
             header as usize,
-            align_to(raw_capacity + HEADER_SIZE, 4096),
-            align_to(size + HEADER_SIZE, 4096),
+            align_to(raw_capacity + HEADER_SIZE, 4096) + guard,
+            align_to(size + HEADER_SIZE, 4096) + guard,
         ) {
+            let actual_new_va_size = actual_new_va_size - guard;
             let grow_start = align_to((header as usize) + old_total, 4096);
             let grow_len = actual_new_va_size - old_total;
 
@@ -205,6 +261,8 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
             ) {
                 Ok(_) => {
                     (*header).class = new_class;
+                    account_resize(old_class, new_class, old_total, actual_new_va_size);
+                    protect_guards(header as usize, actual_new_va_size, big_flags);
 
                     if old_class == 100 && new_class != 100 {
                         let _ = BIG_ALLOC_MAP.remove(header as usize);
@@ -215,7 +273,7 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
                                 size,
                                 class: 100,
                                 life_time: 0,
-                                flags: 0,
+                                flags: big_flags,
                             },
                         );
                     }
@@ -223,7 +281,7 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
                     return ptr;
                 }
                 Err(_) => {
-                    let rollback_start = (header as usize) + old_total;
+                    let rollback_start = (header as usize) + old_total + guard;
                     let rollback_len = actual_new_va_size - old_total;
 
                     if rollback_start & 4095 == 0 && rollback_len & 4095 == 0 {
@@ -232,6 +290,14 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
                 }
             }
         }
+
+        // No room behind the mapping, let the kernel move the pages instead of copying them
+        if is_big && is_big_new {
+            let moved = big_realloc_move(header, old_total, size, big_flags);
+            if !moved.is_null() {
+                return moved as *mut c_void;
+            }
+        }
     }
 
     let new_ptr = malloc(new_size);
@@ -250,7 +316,7 @@ pub unsafe extern "C" fn realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_v
     new_ptr
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
 pub unsafe extern "C" fn reallocarray(
     ptr: *mut c_void,
     nmemb: size_t,
@@ -0,0 +1,229 @@
+use std::{
+    fmt::Write,
+    os::raw::{c_int, c_void},
+    sync::atomic::Ordering,
+};
+
+use libc::FILE;
+
+use crate::{
+    TOTAL_ALLOCATED, TOTAL_IN_USE,
+    big_cache::BIG_CACHE,
+    internals::{
+        hashmap::BIG_ALLOC_MAP,
+        size_t,
+        writer::{StackWriter, fd_sink},
+    },
+    slab::{NUM_SIZE_CLASSES, SIZE_CLASSES, interconnect::ICC, thread_local::TLS},
+    sys::EINVAL,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mallinfo {
+    pub arena: c_int,
+    pub ordblks: c_int,
+    pub smblks: c_int,
+    pub hblks: c_int,
+    pub hblkhd: c_int,
+    pub usmblks: c_int,
+    pub fsmblks: c_int,
+    pub uordblks: c_int,
+    pub fordblks: c_int,
+    pub keepcost: c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mallinfo2 {
+    pub arena: size_t,
+    pub ordblks: size_t,
+    pub smblks: size_t,
+    pub hblks: size_t,
+    pub hblkhd: size_t,
+    pub usmblks: size_t,
+    pub fsmblks: size_t,
+    pub uordblks: size_t,
+    pub fordblks: size_t,
+    pub keepcost: size_t,
+}
+
+// Point-in-time view of the allocator, shared by every reporting entry point
+pub struct HeapStats {
+    // Bytes mapped for slabs
+    pub system: usize,
+    // Slab payload bytes held by the application
+    pub in_use: usize,
+    // Cached free blocks per class
+    pub free_blocks: [usize; NUM_SIZE_CLASSES],
+    pub free_count: usize,
+    pub free_bytes: usize,
+    pub big_count: usize,
+    pub big_bytes: usize,
+    // Freed big regions kept mapped for reuse
+    pub big_cached: usize,
+}
+
+// Other threads' bins cannot be read safely, blocks cached there count as in use
+pub unsafe fn heap_stats() -> HeapStats {
+    let tls = TLS;
+    let mut free_blocks = [0; NUM_SIZE_CLASSES];
+    let mut free_count = 0;
+    let mut free_bytes = 0;
+
+    for (class, free) in free_blocks.iter_mut().enumerate() {
+        let mut count = ICC.get_size(class);
+        if !tls.is_null() {
+            count += (*tls).tls[class].usage;
+            #[cfg(feature = "hardened-malloc")]
+            {
+                count += (*tls).quarantine.held(class);
+            }
+        }
+
+        *free = count;
+        free_count += count;
+        free_bytes += count * SIZE_CLASSES[class];
+    }
+
+    HeapStats {
+        system: TOTAL_ALLOCATED.load(Ordering::Relaxed),
+        in_use: TOTAL_IN_USE
+            .load(Ordering::Relaxed)
+            .saturating_sub(free_bytes),
+        free_blocks,
+        free_count,
+        free_bytes,
+        big_count: BIG_ALLOC_MAP.len(),
+        big_bytes: BIG_ALLOC_MAP.bytes(),
+        big_cached: BIG_CACHE.bytes(),
+    }
+}
+
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn mallinfo2() -> Mallinfo2 {
+    let stats = heap_stats();
+
+    Mallinfo2 {
+        arena: stats.system,
+        ordblks: stats.free_count,
+        smblks: 0,
+        hblks: stats.big_count,
+        hblkhd: stats.big_bytes,
+        usmblks: 0,
+        fsmblks: 0,
+        uordblks: stats.in_use,
+        fordblks: stats.system.saturating_sub(stats.in_use),
+        keepcost: stats.big_cached,
+    }
+}
+
+// Legacy `int` layout, values that do not fit are clamped to `c_int::MAX`
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn mallinfo() -> Mallinfo {
+    let info = mallinfo2();
+    let clamp = |val: usize| c_int::try_from(val).unwrap_or(c_int::MAX);
+
+    Mallinfo {
+        arena: clamp(info.arena),
+        ordblks: clamp(info.ordblks),
+        smblks: clamp(info.smblks),
+        hblks: clamp(info.hblks),
+        hblkhd: clamp(info.hblkhd),
+        usmblks: clamp(info.usmblks),
+        fsmblks: clamp(info.fsmblks),
+        uordblks: clamp(info.uordblks),
+        fordblks: clamp(info.fordblks),
+        keepcost: clamp(info.keepcost),
+    }
+}
+
+// Same layout as glibc, peaks are not tracked so the max lines report current values
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn malloc_stats() {
+    let stats = heap_stats();
+    let mut out = StackWriter::new(fd_sink(2));
+
+    let _ = write!(
+        out,
+        "Arena 0:\n\
+         system bytes     = {:>10}\n\
+         in use bytes     = {:>10}\n\
+         Total (incl. mmap):\n\
+         system bytes     = {:>10}\n\
+         in use bytes     = {:>10}\n\
+         max mmap regions = {:>10}\n\
+         max mmap bytes   = {:>10}\n",
+        stats.system,
+        stats.in_use,
+        stats.system + stats.big_bytes,
+        stats.in_use + stats.big_bytes,
+        stats.big_count,
+        stats.big_bytes,
+    );
+}
+
+unsafe fn write_malloc_info(out: &mut impl Write, stats: &HeapStats) -> std::fmt::Result {
+    writeln!(out, "<malloc version=\"1\">")?;
+    writeln!(out, "<heap nr=\"0\">")?;
+    writeln!(out, "<sizes>")?;
+
+    let mut from = 1;
+    for (&to, &count) in SIZE_CLASSES.iter().zip(stats.free_blocks.iter()) {
+        if count > 0 {
+            writeln!(
+                out,
+                "  <size from=\"{}\" to=\"{}\" total=\"{}\" count=\"{}\"/>",
+                from,
+                to,
+                count * to,
+                count
+            )?;
+        }
+        from = to + 1;
+    }
+
+    writeln!(out, "</sizes>")?;
+
+    for heap_total in [false, true] {
+        if heap_total {
+            writeln!(out, "</heap>")?;
+        }
+
+        writeln!(out, "<total type=\"fast\" count=\"0\" size=\"0\"/>")?;
+        writeln!(
+            out,
+            "<total type=\"rest\" count=\"{}\" size=\"{}\"/>",
+            stats.free_count, stats.free_bytes
+        )?;
+        if heap_total {
+            writeln!(
+                out,
+                "<total type=\"mmap\" count=\"{}\" size=\"{}\"/>",
+                stats.big_count, stats.big_bytes
+            )?;
+        }
+        writeln!(out, "<system type=\"current\" size=\"{}\"/>", stats.system)?;
+        writeln!(out, "<system type=\"max\" size=\"{}\"/>", stats.system)?;
+        writeln!(out, "<aspace type=\"total\" size=\"{}\"/>", stats.system)?;
+        writeln!(out, "<aspace type=\"mprotect\" size=\"{}\"/>", stats.system)?;
+    }
+
+    writeln!(out, "</malloc>")
+}
+
+// glibc XML layout with a single heap, one <size> line per size class with cached blocks
+#[cfg_attr(feature = "c-abi", unsafe(no_mangle))]
+pub unsafe extern "C" fn malloc_info(options: c_int, stream: *mut FILE) -> c_int {
+    if options != 0 || stream.is_null() {
+        return EINVAL;
+    }
+
+    let stats = heap_stats();
+    let mut out = StackWriter::new(|bytes: &[u8]| {
+        libc::fwrite(bytes.as_ptr() as *const c_void, 1, bytes.len(), stream);
+    });
+
+    let _ = write_malloc_info(&mut out, &stats);
+    0
+}
@@ -1,28 +1,124 @@
 use crate::{
-    FREED_MAGIC, HEADER_SIZE, MAGIC, OX_FORCE_THP, OxHeader, OxidallocError,
-    internals::hashmap::{BIG_ALLOC_MAP, BigAllocMeta},
+    FLAG_ALIGNED, FLAG_DIRTY, FLAG_GUARD_AFTER, FLAG_GUARD_BEFORE, FLAG_THP, FREED_MAGIC,
+    HEADER_SIZE, MAGIC, OX_FORCE_THP, OX_GUARD_BEFORE, OX_GUARD_PAGES, OX_RELEASE, OxHeader,
+    OxidallocError,
+    big_cache::BIG_CACHE,
+    internals::{
+        hashmap::{BIG_ALLOC_MAP, BigAllocMeta},
+        prof::{prof_free, prof_moved},
+    },
     sys::memory_system::{
-        MMapFlags, MProtFlags, MadviseFlags, MemoryFlags, RMProtFlags, madvise, mmap_memory,
-        protect_memory,
+        MMapFlags, MProtFlags, MRemapFlags, MadviseFlags, MemoryFlags, RMProtFlags, madvise,
+        mmap_memory, protect_memory, remap_memory, unmap_memory,
     },
-    va::{align_to, bitmap::VA_MAP},
+    trim::{release_pages, thread::trim_on_slow_path},
+    va::{align_to, bitmap::VA_MAP, numa::bind_to_current_node},
 };
 use std::{
     os::raw::c_void,
     ptr::{null_mut, write, write_bytes},
+    sync::atomic::Ordering,
 };
 
+const PAGE_SIZE: usize = 4096;
+
 pub unsafe fn big_malloc(size: usize) -> *mut u8 {
-    // Align size to the page size so we don't explode later
-    let aligned_total = if OX_FORCE_THP {
-        align_to(size + HEADER_SIZE, 1024 * 1024 * 2)
+    big_malloc_inner(size, HEADER_SIZE)
+}
+
+// Payload starts on the second page, the header sits at the end of the first one
+pub unsafe fn big_malloc_aligned(size: usize) -> *mut u8 {
+    big_malloc_inner(size, PAGE_SIZE)
+}
+
+// Start of the mapping a big allocation header lives in
+#[inline(always)]
+fn big_base(header: *mut OxHeader) -> usize {
+    (header as usize) & !(PAGE_SIZE - 1)
+}
+
+// `thp` is decided once at allocation time and kept in the metadata flags, `OX_FORCE_THP`
+// can change while the allocation is alive
+#[inline(always)]
+fn big_total(payload_end: usize, thp: bool) -> usize {
+    if thp {
+        align_to(payload_end, 1024 * 1024 * 2)
     } else {
-        align_to(size + HEADER_SIZE, 4096)
-    };
+        align_to(payload_end, PAGE_SIZE)
+    }
+}
+
+// Guard flags for a new big allocation, kept in its metadata like `FLAG_THP`
+#[inline(always)]
+unsafe fn guard_flags() -> u8 {
+    if !OX_GUARD_PAGES {
+        return 0;
+    }
+
+    FLAG_GUARD_AFTER
+        | if OX_GUARD_BEFORE {
+            FLAG_GUARD_BEFORE
+        } else {
+            0
+        }
+}
 
-    // Reserve virtual space first
-    let hint = match VA_MAP.alloc(aligned_total) {
-        Some(hint) => hint,
+// Bytes of guard before and after the mapping
+#[inline(always)]
+pub(crate) const fn guard_sizes(flags: u8) -> (usize, usize) {
+    (
+        if flags & FLAG_GUARD_BEFORE != 0 {
+            PAGE_SIZE
+        } else {
+            0
+        },
+        if flags & FLAG_GUARD_AFTER != 0 {
+            PAGE_SIZE
+        } else {
+            0
+        },
+    )
+}
+
+// Recycled VA may still be mapped read/write (reclaimed slabs stay readable), so guards are
+// always protected explicitly
+pub(crate) unsafe fn protect_guards(base: usize, total: usize, flags: u8) {
+    let (before, after) = guard_sizes(flags);
+    if before != 0 {
+        let _ = protect_memory((base - before) as *mut c_void, before, RMProtFlags::NONE);
+    }
+    if after != 0 {
+        let _ = protect_memory((base + total) as *mut c_void, after, RMProtFlags::NONE);
+    }
+}
+
+// Guards are never touched, handing their range back is enough
+pub(crate) unsafe fn free_guards(base: usize, total: usize, flags: u8) {
+    let (before, after) = guard_sizes(flags);
+    VA_MAP.free(base - before, before);
+    VA_MAP.free(base + total, after);
+}
+
+// `lead` is the distance from the mapping start to the payload
+unsafe fn big_malloc_inner(size: usize, lead: usize) -> *mut u8 {
+    // Align size to the page size so we don't explode later
+    let thp = OX_FORCE_THP.load(Ordering::Relaxed);
+    let aligned_total = big_total(size + lead, thp);
+    let aligned_flag = if lead == HEADER_SIZE { 0 } else { FLAG_ALIGNED };
+    let guard = guard_flags();
+    let (before, after) = guard_sizes(guard);
+
+    // A cached region is already mapped read/write, the payload runs to its end so `big_free`
+    // gives back the whole region. Cached regions have no guards.
+    if guard == 0
+        && let Some((base, total)) = BIG_CACHE.take(aligned_total)
+    {
+        return place_big(base, total - lead, lead, aligned_flag | FLAG_DIRTY);
+    }
+
+    // Reserve virtual space first, guards included
+    let hint = match VA_MAP.alloc(before + aligned_total + after) {
+        Some(span) => span + before,
         None => return null_mut(),
     };
 
@@ -33,7 +129,7 @@ pub unsafe fn big_malloc(size: usize) -> *mut u8 {
     )
     .is_err();
 
-    let actual_ptr = if is_err {
+    let base = if is_err {
         match mmap_memory(
             hint as *mut c_void,
             aligned_total,
@@ -44,28 +140,48 @@ pub unsafe fn big_malloc(size: usize) -> *mut u8 {
         ) {
             Ok(ptr) => ptr,
             Err(_) => {
-                VA_MAP.free(hint, aligned_total);
+                VA_MAP.free(hint - before, before + aligned_total + after);
                 return null_mut();
             }
         }
     } else {
         hint as *mut c_void
-    } as *mut OxHeader;
+    };
 
-    if aligned_total % (1024 * 1024 * 2) == 0 {
-        let _ = madvise(
-            actual_ptr as *mut c_void,
-            aligned_total,
-            MadviseFlags::HUGEPAGE,
-        );
+    protect_guards(hint, aligned_total, guard);
+
+    bind_to_current_node(base, aligned_total);
+
+    if aligned_total.is_multiple_of(1024 * 1024 * 2) {
+        let _ = madvise(base, aligned_total, MadviseFlags::HUGEPAGE);
     }
 
+    // Reserved VA may hold pages a released slab or big region left behind, only DONTNEED
+    // guarantees they read back as zero
+    let dirty = if !is_err && OX_RELEASE.keeps_contents() {
+        FLAG_DIRTY
+    } else {
+        0
+    };
+
+    place_big(
+        base as usize,
+        size,
+        lead,
+        aligned_flag | dirty | guard | if thp { FLAG_THP } else { 0 },
+    )
+}
+
+unsafe fn place_big(base: usize, size: usize, lead: usize, flags: u8) -> *mut u8 {
+    let actual_ptr = (base + lead - HEADER_SIZE) as *mut OxHeader;
+
     write(
         actual_ptr,
         OxHeader {
             next: null_mut(),
             class: 100,
             magic: MAGIC,
+            owner: 0,
             life_time: 0,
         },
     );
@@ -76,47 +192,132 @@ pub unsafe fn big_malloc(size: usize) -> *mut u8 {
             size,
             class: 100,
             life_time: 0,
-            flags: 0,
+            flags,
         },
     );
 
     (actual_ptr as *mut u8).add(HEADER_SIZE)
 }
 
+// Move a big allocation whose header starts its mapping into a fresh VA range of `new_total`
+// bytes. Only page tables move, the old range is reserved again and handed back to `VA_MAP`.
+// Returns null if nothing was moved.
+pub unsafe fn big_realloc_move(
+    header: *mut OxHeader,
+    old_total: usize,
+    size: usize,
+    flags: u8,
+) -> *mut u8 {
+    let new_total = big_total(size + HEADER_SIZE, false);
+    let (before, after) = guard_sizes(flags);
+
+    let target = match VA_MAP.alloc(before + new_total + after) {
+        Some(span) => span + before,
+        None => return null_mut(),
+    };
+
+    let moved = remap_memory(
+        header as *mut c_void,
+        old_total,
+        target as *mut c_void,
+        new_total,
+        MRemapFlags::MAYMOVE | MRemapFlags::FIXED,
+    );
+
+    if moved.is_err() {
+        VA_MAP.free(target - before, before + new_total + after);
+        return null_mut();
+    }
+
+    protect_guards(target, new_total, flags);
+
+    // mremap left a hole where the allocation was, reserve it again the way segments are. Someone
+    // else may have mapped into the hole meanwhile, so never replace anything.
+    let refill = mmap_memory(
+        header as *mut c_void,
+        old_total,
+        MMapFlags {
+            prot: MProtFlags::NONE,
+            map: MemoryFlags::PRIVATE | MemoryFlags::NORESERVE | MemoryFlags::FIXED_NOREPLACE,
+        },
+    );
+
+    let _ = BIG_ALLOC_MAP.remove(header as usize);
+    // Without a reservation behind it the old range must never be handed out again
+    match refill {
+        Ok(ptr) if ptr == header as *mut c_void => VA_MAP.free(header as usize, old_total),
+        Ok(ptr) => {
+            let _ = unmap_memory(ptr, old_total);
+        }
+        Err(_) => {}
+    }
+    free_guards(header as usize, old_total, flags);
+
+    if new_total.is_multiple_of(1024 * 1024 * 2) {
+        let _ = madvise(target as *mut c_void, new_total, MadviseFlags::HUGEPAGE);
+    }
+
+    let new_header = target as *mut OxHeader;
+    BIG_ALLOC_MAP.insert(
+        new_header as usize,
+        BigAllocMeta {
+            size,
+            class: 100,
+            life_time: 0,
+            flags,
+        },
+    );
+
+    let new_ptr = (new_header as *mut u8).add(HEADER_SIZE);
+    prof_moved(header.add(1) as *mut c_void, new_ptr as *mut c_void);
+    new_ptr
+}
+
 pub unsafe fn big_free(ptr: *mut OxHeader) {
+    trim_on_slow_path();
     let header = ptr.sub(1);
-    let payload_size = BIG_ALLOC_MAP
-        .remove(header as usize)
-        .map(|meta| meta.size)
-        .unwrap_or_else(|| {
-            OxidallocError::AttackOrCorruption.log_and_abort(
-                header as *mut c_void,
-                "Missing big allocation metadata during free",
-                None,
-            )
-        });
-
-    // Align size back to original size
-    let total_size = if OX_FORCE_THP {
-        align_to(payload_size + HEADER_SIZE, 1024 * 1024 * 2)
-    } else {
-        align_to(payload_size + HEADER_SIZE, 4096)
+    let Some(meta) = BIG_ALLOC_MAP.remove(header as usize) else {
+        OxidallocError::AttackOrCorruption.report(
+            header as *mut c_void,
+            "Missing big allocation metadata during free",
+            None,
+        );
+        return;
     };
+    prof_free(ptr as *mut c_void);
 
-    if total_size % (1024 * 1024 * 2) == 0 {
-        let _ = madvise(header as *mut c_void, total_size, MadviseFlags::NORMAL);
-    }
+    // Align size back to original size, the header is not always at the start of the mapping
+    let base = big_base(header);
+    let total_size = big_total(
+        header as usize + HEADER_SIZE + meta.size - base,
+        meta.flags & FLAG_THP != 0,
+    );
 
     // Make the header look free before we potentially lose write access.
     (*header).magic = FREED_MAGIC;
 
-    let is_failed = madvise(header as *mut c_void, total_size, MadviseFlags::DONTNEED);
+    if meta.flags & (FLAG_GUARD_BEFORE | FLAG_GUARD_AFTER) == 0 && BIG_CACHE.push(base, total_size)
+    {
+        return;
+    }
+
+    release_region(base, total_size);
+    free_guards(base, total_size, meta.flags);
+}
+
+// Hand a big mapping back to the kernel and its range back to `VA_MAP`
+pub(crate) unsafe fn release_region(base: usize, total_size: usize) {
+    if total_size.is_multiple_of(1024 * 1024 * 2) {
+        let _ = madvise(base as *mut c_void, total_size, MadviseFlags::NORMAL);
+    }
+
+    let is_failed = release_pages(base as *mut c_void, total_size);
     if is_failed.is_err() {
         // Security: Zero out the memory before freeing it so it wont leak the info
-        write_bytes(header as *mut u8, 0, total_size);
+        write_bytes(base as *mut u8, 0, total_size);
     }
 
-    let _ = protect_memory(header as *mut c_void, total_size, RMProtFlags::NONE);
+    let _ = protect_memory(base as *mut c_void, total_size, RMProtFlags::NONE);
 
-    VA_MAP.free(header as usize, total_size);
+    VA_MAP.free(base, total_size);
 }
@@ -0,0 +1,162 @@
+// Cache of freed big regions
+// A freed big allocation keeps its committed mapping here for a while, the next big allocation
+// of a similar size takes it back without any mmap/mprotect. The trim thread releases regions
+// older than the current decay allows, `GTrim` drops the whole cache under pressure.
+
+use std::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{OX_CURRENT_STAMP, big_allocation::release_region, internals::lock::SerialLock};
+
+// Regions from 2 MiB up to this size are cached, one bucket per power of two
+pub const BIG_CACHE_MAX_REGION: usize = 64 * 1024 * 1024;
+// Committed bytes the cache may hold at once
+pub const BIG_CACHE_MAX_BYTES: usize = 128 * 1024 * 1024;
+
+const MIN_SHIFT: u32 = 21;
+const BUCKETS: usize = (BIG_CACHE_MAX_REGION.ilog2() - MIN_SHIFT + 1) as usize;
+const SLOTS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct CachedRegion {
+    base: usize,
+    total: usize,
+    stamp: u32,
+}
+
+impl CachedRegion {
+    const EMPTY: Self = CachedRegion {
+        base: 0,
+        total: 0,
+        stamp: 0,
+    };
+}
+
+pub struct BigCache {
+    slots: UnsafeCell<[[CachedRegion; SLOTS]; BUCKETS]>,
+    bytes: AtomicUsize,
+    lock: SerialLock,
+}
+
+unsafe impl Sync for BigCache {}
+
+pub static BIG_CACHE: BigCache = BigCache::new();
+
+#[inline(always)]
+fn bucket_of(total: usize) -> usize {
+    total.ilog2().saturating_sub(MIN_SHIFT) as usize
+}
+
+impl BigCache {
+    const fn new() -> Self {
+        BigCache {
+            slots: UnsafeCell::new([[CachedRegion::EMPTY; SLOTS]; BUCKETS]),
+            bytes: AtomicUsize::new(0),
+            lock: SerialLock::new(),
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    // Keeps `[base, base + total)` mapped, false means the caller has to release it
+    pub unsafe fn push(&self, base: usize, total: usize) -> bool {
+        // Freed regions must stay inaccessible in hardened mode
+        if cfg!(feature = "hardened-malloc")
+            || !(1 << MIN_SHIFT..=BIG_CACHE_MAX_REGION).contains(&total)
+        {
+            return false;
+        }
+
+        let _guard = self.lock.lock();
+        if self.bytes.load(Ordering::Relaxed) + total > BIG_CACHE_MAX_BYTES {
+            return false;
+        }
+
+        let bucket = &mut (*self.slots.get())[bucket_of(total)];
+        for slot in bucket {
+            if slot.base == 0 {
+                *slot = CachedRegion {
+                    base,
+                    total,
+                    stamp: OX_CURRENT_STAMP,
+                };
+                self.bytes.fetch_add(total, Ordering::Relaxed);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Smallest cached region that fits `total` without wasting more than an eighth of it
+    pub unsafe fn take(&self, total: usize) -> Option<(usize, usize)> {
+        if total > BIG_CACHE_MAX_REGION || self.bytes() == 0 {
+            return None;
+        }
+
+        let limit = total + total / 8;
+        let first = bucket_of(total);
+
+        let _guard = self.lock.lock();
+        let slots = &mut *self.slots.get();
+        let mut best: Option<&mut CachedRegion> = None;
+
+        for bucket in &mut slots[first..(first + 2).min(BUCKETS)] {
+            for slot in bucket {
+                if slot.base != 0
+                    && slot.total >= total
+                    && slot.total <= limit
+                    && best.as_ref().is_none_or(|best| slot.total < best.total)
+                {
+                    best = Some(slot);
+                }
+            }
+        }
+
+        let slot = best?;
+        let region = (slot.base, slot.total);
+        *slot = CachedRegion::EMPTY;
+        self.bytes.fetch_sub(region.1, Ordering::Relaxed);
+
+        Some(region)
+    }
+
+    // Releases every region cached for at least `max_age` seconds, returns the bytes released
+    pub unsafe fn purge(&self, max_age: u32) -> usize {
+        if self.bytes() == 0 {
+            return 0;
+        }
+
+        let mut expired = [CachedRegion::EMPTY; BUCKETS * SLOTS];
+        let mut count = 0;
+
+        {
+            let _guard = self.lock.lock();
+            for slot in (*self.slots.get()).iter_mut().flatten() {
+                if slot.base != 0 && OX_CURRENT_STAMP.saturating_sub(slot.stamp) >= max_age {
+                    self.bytes.fetch_sub(slot.total, Ordering::Relaxed);
+                    expired[count] = *slot;
+                    count += 1;
+                    *slot = CachedRegion::EMPTY;
+                }
+            }
+        }
+
+        // The syscalls happen outside the lock
+        let mut released = 0;
+        for region in &expired[..count] {
+            release_region(region.base, region.total);
+            released += region.total;
+        }
+
+        released
+    }
+
+    pub fn reset_on_fork(&self) {
+        self.lock.reset_on_fork();
+    }
+}
@@ -0,0 +1,324 @@
+// `OX_CONF` / `/etc/oxidalloc.conf` parsing
+// Entries are `key:value` (or `key=value`) separated by commas or newlines, `#` starts a comment
+// that runs to the end of the line. Everything works on borrowed bytes, nothing here
+// may allocate since it runs inside the first `malloc`.
+
+use std::fmt::Write;
+
+use crate::{
+    internals::{
+        env::get_env_bytes,
+        writer::{StackWriter, fd_sink},
+    },
+    trim::{ReleasePolicy, TimeDecay},
+};
+
+pub const CONF_ENV: &[u8] = b"OX_CONF";
+pub const CONF_PATH: &[u8] = b"/etc/oxidalloc.conf\0";
+const CONF_FILE_MAX: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfValue {
+    Size(usize),
+    Bool(bool),
+    Decay(TimeDecay),
+    Release(ReleasePolicy),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfKind {
+    Size,
+    Bool,
+    Decay,
+    Release,
+}
+
+const KEYS: [(&[u8], ConfKind); 11] = [
+    (b"trim_threshold", ConfKind::Size),
+    (b"thp", ConfKind::Bool),
+    (b"max_reservation", ConfKind::Size),
+    (b"background_thread", ConfKind::Bool),
+    (b"decay", ConfKind::Decay),
+    (b"rseq", ConfKind::Bool),
+    (b"release", ConfKind::Release),
+    (b"guard_pages", ConfKind::Bool),
+    (b"guard_before", ConfKind::Bool),
+    (b"prof_sample", ConfKind::Size),
+    (b"leak_report", ConfKind::Bool),
+];
+
+// Plain number with an optional binary K/M/G/T suffix, a trailing `B` is allowed
+pub fn parse_size(val: &[u8]) -> Option<usize> {
+    let digits = val.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+
+    let mut out = 0usize;
+    for &b in &val[..digits] {
+        out = out.checked_mul(10)?.checked_add((b - b'0') as usize)?;
+    }
+
+    let suffix = match &val[digits..] {
+        [b'B' | b'b'] | [] => None,
+        [unit, b'B' | b'b'] | [unit] => Some(unit.to_ascii_uppercase()),
+        _ => return None,
+    };
+
+    let shift = match suffix {
+        None => 0,
+        Some(b'K') => 10,
+        Some(b'M') => 20,
+        Some(b'G') => 30,
+        Some(b'T') => 40,
+        Some(_) => return None,
+    };
+
+    out.checked_mul(1 << shift)
+}
+
+pub fn parse_bool(val: &[u8]) -> Option<bool> {
+    const TRUE: [&[u8]; 4] = [b"1", b"true", b"yes", b"on"];
+    const FALSE: [&[u8]; 4] = [b"0", b"false", b"no", b"off"];
+
+    if TRUE.iter().any(|name| val.eq_ignore_ascii_case(name)) {
+        Some(true)
+    } else if FALSE.iter().any(|name| val.eq_ignore_ascii_case(name)) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+pub fn parse_decay(val: &[u8]) -> Option<TimeDecay> {
+    const NAMES: [(&[u8], TimeDecay); 4] = [
+        (b"normal", TimeDecay::Normal),
+        (b"medium", TimeDecay::Medium),
+        (b"high", TimeDecay::High),
+        (b"aggressive", TimeDecay::Aggressive),
+    ];
+
+    NAMES
+        .iter()
+        .find(|(name, _)| val.eq_ignore_ascii_case(name))
+        .map(|&(_, decay)| decay)
+}
+
+pub fn parse_release(val: &[u8]) -> Option<ReleasePolicy> {
+    const NAMES: [(&[u8], ReleasePolicy); 4] = [
+        (b"dontneed", ReleasePolicy::DontNeed),
+        (b"free", ReleasePolicy::Free),
+        (b"cold", ReleasePolicy::Cold),
+        (b"pageout", ReleasePolicy::PageOut),
+    ];
+
+    NAMES
+        .iter()
+        .find(|(name, _)| val.eq_ignore_ascii_case(name))
+        .map(|&(_, policy)| policy)
+}
+
+fn parse_value(kind: ConfKind, val: &[u8]) -> Option<ConfValue> {
+    match kind {
+        ConfKind::Size => parse_size(val).map(ConfValue::Size),
+        ConfKind::Bool => parse_bool(val).map(ConfValue::Bool),
+        ConfKind::Decay => parse_decay(val).map(ConfValue::Decay),
+        ConfKind::Release => parse_release(val).map(ConfValue::Release),
+    }
+}
+
+#[inline]
+const fn is_separator(b: u8) -> bool {
+    matches!(b, b',' | b'\n')
+}
+
+// Calls `apply` for every valid entry and `reject` for unknown keys or unparsable values
+pub fn parse_conf(
+    input: &[u8],
+    mut apply: impl FnMut(&'static [u8], ConfValue),
+    mut reject: impl FnMut(&[u8]),
+) {
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        if rest[0] == b'#' {
+            let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            rest = &rest[end..];
+            continue;
+        }
+
+        if is_separator(rest[0]) {
+            rest = &rest[1..];
+            continue;
+        }
+
+        let end = rest
+            .iter()
+            .position(|&b| is_separator(b) || b == b'#')
+            .unwrap_or(rest.len());
+        let entry = rest[..end].trim_ascii();
+        rest = &rest[end..];
+        if entry.is_empty() {
+            continue;
+        }
+
+        let Some(split) = entry.iter().position(|&b| b == b':' || b == b'=') else {
+            reject(entry);
+            continue;
+        };
+        let (key, val) = (entry[..split].trim_ascii(), entry[split + 1..].trim_ascii());
+
+        let parsed = KEYS
+            .iter()
+            .find(|(name, _)| *name == key)
+            .and_then(|&(name, kind)| parse_value(kind, val).map(|val| (name, val)));
+
+        match parsed {
+            Some((name, val)) => apply(name, val),
+            None => reject(entry),
+        }
+    }
+}
+
+// Reads at most `buf.len()` bytes of `path` (NUL terminated) into `buf`
+pub(crate) unsafe fn read_file<'a>(path: &[u8], buf: &'a mut [u8]) -> &'a [u8] {
+    let fd = libc::open(path.as_ptr().cast(), libc::O_RDONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return &[];
+    }
+
+    let mut len = 0;
+    while len < buf.len() {
+        let ret = libc::read(fd, buf[len..].as_mut_ptr().cast(), buf.len() - len);
+        if ret <= 0 {
+            break;
+        }
+        len += ret as usize;
+    }
+
+    libc::close(fd);
+    &buf[..len]
+}
+
+// Applies the config file first and `OX_CONF` second, so the environment wins. Every rejected
+// entry ends up in one warning line on stderr.
+pub unsafe fn load_conf(mut apply: impl FnMut(&'static [u8], ConfValue)) {
+    let mut file_buf = [0u8; CONF_FILE_MAX];
+    let file = read_file(CONF_PATH, &mut file_buf);
+    let env = get_env_bytes(CONF_ENV).unwrap_or(&[]);
+
+    let mut out = StackWriter::new(fd_sink(2));
+    let mut rejected = 0;
+
+    for input in [file, env] {
+        parse_conf(input, &mut apply, |entry| {
+            let prefix = if rejected == 0 {
+                "[OXIDALLOC WARNING] Ignoring unknown or invalid config entries: "
+            } else {
+                ", "
+            };
+            let _ = out.write_str(prefix);
+            for chunk in entry.utf8_chunks() {
+                let _ = out.write_str(chunk.valid());
+            }
+            rejected += 1;
+        });
+    }
+
+    if rejected > 0 {
+        let _ = out.write_str("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What `apply` was called with
+    type Applied = Vec<(&'static [u8], ConfValue)>;
+
+    fn collect(input: &[u8]) -> (Applied, Vec<Vec<u8>>) {
+        let mut applied = Vec::new();
+        let mut rejected = Vec::new();
+        parse_conf(
+            input,
+            |key, val| applied.push((key, val)),
+            |entry| rejected.push(entry.to_vec()),
+        );
+        (applied, rejected)
+    }
+
+    #[test]
+    fn sizes_with_suffixes() {
+        assert_eq!(parse_size(b"4096"), Some(4096));
+        assert_eq!(parse_size(b"64K"), Some(64 << 10));
+        assert_eq!(parse_size(b"64m"), Some(64 << 20));
+        assert_eq!(parse_size(b"2GB"), Some(2 << 30));
+        assert_eq!(parse_size(b"1T"), Some(1 << 40));
+        assert_eq!(parse_size(b""), None);
+        assert_eq!(parse_size(b"M"), None);
+        assert_eq!(parse_size(b"12X"), None);
+        assert_eq!(parse_size(b"99999999999999999999"), None);
+        assert_eq!(parse_size(b"99999999999T"), None);
+    }
+
+    #[test]
+    fn booleans_and_enums() {
+        assert_eq!(parse_bool(b"1"), Some(true));
+        assert_eq!(parse_bool(b"On"), Some(true));
+        assert_eq!(parse_bool(b"false"), Some(false));
+        assert_eq!(parse_bool(b"2"), None);
+        assert_eq!(parse_decay(b"Aggressive"), Some(TimeDecay::Aggressive));
+        assert_eq!(parse_decay(b"slow"), None);
+        assert_eq!(parse_release(b"FREE"), Some(ReleasePolicy::Free));
+        assert_eq!(parse_release(b"pageout"), Some(ReleasePolicy::PageOut));
+        assert_eq!(parse_release(b"lazy"), None);
+    }
+
+    #[test]
+    fn full_string() {
+        let (applied, rejected) = collect(
+            b"trim_threshold:64M,thp:1,max_reservation:1T,background_thread:0,guard_pages:on",
+        );
+        assert_eq!(
+            applied,
+            vec![
+                (&b"trim_threshold"[..], ConfValue::Size(64 << 20)),
+                (&b"thp"[..], ConfValue::Bool(true)),
+                (&b"max_reservation"[..], ConfValue::Size(1 << 40)),
+                (&b"background_thread"[..], ConfValue::Bool(false)),
+                (&b"guard_pages"[..], ConfValue::Bool(true)),
+            ]
+        );
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn file_layout_and_rejects() {
+        let (applied, rejected) = collect(
+            b"# oxidalloc\n\
+              thp = 0\n\
+              decay=high # trailing comment\n\
+              release = cold\n\
+              colour:blue\n\
+              trim_threshold:lots,,\n\
+              stray\n",
+        );
+        assert_eq!(
+            applied,
+            vec![
+                (&b"thp"[..], ConfValue::Bool(false)),
+                (&b"decay"[..], ConfValue::Decay(TimeDecay::High)),
+                (&b"release"[..], ConfValue::Release(ReleasePolicy::Cold)),
+            ]
+        );
+        assert_eq!(
+            rejected,
+            vec![
+                b"colour:blue".to_vec(),
+                b"trim_threshold:lots".to_vec(),
+                b"stray".to_vec(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+// Crash report for fatal errors
+// `log_and_abort` writes it after the one line message, right before aborting. It has to work
+// from a corrupted heap and from signal handlers: text is formatted into a stack buffer and
+// written with `write`, no lock is taken, and memory that may be gone is read through
+// `process_vm_readv`, which fails instead of faulting.
+// `OX_CRASH_REPORT` picks where it goes: a file descriptor number, or a path opened for append.
+// Stderr by default. Every line is `key=value`, keys only ever get added.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    HEADER_SIZE, MetaData, OxHeader, OxidallocError,
+    internals::{
+        env::get_env_bytes,
+        unwind::{for_each_exec_map, frame_pointer, read_val, walk_frames},
+        writer::{StackWriter, fd_sink},
+    },
+    slab::{
+        NUM_SIZE_CLASSES, SIZE_CLASSES, first_block_offset, interconnect::ICC, slab_of,
+        thread_local::TLS,
+    },
+    va::{align_to, is_ours, numa::parse_num},
+};
+
+const CRASH_ENV: &[u8] = b"OX_CRASH_REPORT";
+pub const CRASH_REPORT_VERSION: u32 = 1;
+const MAX_FRAMES: usize = 64;
+const PATH_MAX: usize = 512;
+
+// Only the first fatal error is reported, a second one (or a fault while reporting) just aborts
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+// Callers pass either the header or the payload, the header is the one with a known class
+unsafe fn find_header(ptr: usize) -> Option<(usize, OxHeader)> {
+    [ptr, ptr.wrapping_sub(HEADER_SIZE)]
+        .into_iter()
+        .filter(|&addr| addr != 0 && is_ours(addr))
+        .find_map(|addr| {
+            let header = read_val::<OxHeader>(addr)?;
+            let class = header.class as usize;
+            (class < NUM_SIZE_CLASSES || class == 100).then_some((addr, header))
+        })
+}
+
+unsafe fn open_report() -> (i32, bool) {
+    let Some(val) = get_env_bytes(CRASH_ENV) else {
+        return (2, false);
+    };
+
+    if let Some(fd) = parse_num(val) {
+        return (fd as i32, false);
+    }
+
+    let mut path = [0u8; PATH_MAX];
+    if val.is_empty() || val.len() >= PATH_MAX {
+        return (2, false);
+    }
+    path[..val.len()].copy_from_slice(val);
+
+    let fd = libc::open(
+        path.as_ptr().cast(),
+        libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND | libc::O_CLOEXEC,
+        0o600,
+    );
+    if fd < 0 { (2, false) } else { (fd, true) }
+}
+
+fn write_header(out: &mut impl Write, addr: usize, header: &OxHeader) -> std::fmt::Result {
+    let class = header.class as usize;
+    writeln!(out, "header.addr={addr:#x}")?;
+    write!(out, "header.bytes=")?;
+    let bytes = unsafe {
+        std::slice::from_raw_parts((header as *const OxHeader).cast::<u8>(), HEADER_SIZE)
+    };
+    for byte in bytes {
+        write!(out, "{byte:02x}")?;
+    }
+    writeln!(out)?;
+    writeln!(out, "header.class={class}")?;
+    if class < NUM_SIZE_CLASSES {
+        writeln!(out, "header.size={}", SIZE_CLASSES[class])?;
+    } else {
+        writeln!(out, "header.big=1")?;
+    }
+    writeln!(out, "header.magic={:#x}", header.magic)?;
+    writeln!(out, "header.owner={}", header.owner)?;
+    writeln!(out, "header.life_time={}", header.life_time)?;
+    writeln!(out, "header.next={:p}", header.next)
+}
+
+unsafe fn write_slab(out: &mut impl Write, addr: usize, class: usize) -> std::fmt::Result {
+    let slab = slab_of(addr as *mut OxHeader, class) as usize;
+    writeln!(out, "slab.addr={slab:#x}")?;
+    let Some(meta) = read_val::<MetaData>(slab) else {
+        return writeln!(out, "slab.readable=0");
+    };
+
+    writeln!(out, "slab.start={:#x}", meta.start)?;
+    writeln!(out, "slab.end={:#x}", meta.end)?;
+    writeln!(out, "slab.next={:#x}", meta.next)?;
+    writeln!(out, "slab.owner={}", meta.owner)?;
+    writeln!(out, "slab.live={}", meta.live)?;
+    writeln!(out, "slab.epoch={}", meta.epoch)?;
+
+    let first = meta.start + first_block_offset(class);
+    let block = align_to(SIZE_CLASSES[class] + HEADER_SIZE, 16);
+    if meta.start == slab && addr >= first {
+        writeln!(out, "slab.block_index={}", (addr - first) / block)?;
+        writeln!(out, "slab.block_offset={}", (addr - first) % block)?;
+    }
+    Ok(())
+}
+
+unsafe fn write_thread(out: &mut impl Write) -> std::fmt::Result {
+    writeln!(out, "thread.tid={}", libc::gettid())?;
+    let tls = TLS;
+    if tls.is_null() {
+        return writeln!(out, "thread.cache=0");
+    }
+
+    writeln!(out, "thread.owner={}", (*tls).owner)?;
+    for (class, bin) in (*tls).tls.iter().enumerate() {
+        if bin.usage > 0 {
+            writeln!(out, "tls.class.{class}.usage={}", bin.usage)?;
+            writeln!(out, "tls.class.{class}.head={:p}", bin.head)?;
+        }
+    }
+    Ok(())
+}
+
+// The faulting class and its neighbours, over all shards and in the shard of this CPU
+unsafe fn write_icc(out: &mut impl Write, class: Option<usize>) -> std::fmt::Result {
+    let icc = &raw const ICC;
+    if (*icc).usage.is_null() || (*icc).list.is_null() {
+        return writeln!(out, "icc.ready=0");
+    }
+
+    let cpu = (*icc).get_cpu_id().unwrap_or(0);
+    writeln!(out, "icc.ncpu={}", (*icc).ncpu)?;
+    writeln!(out, "icc.cpu={cpu}")?;
+
+    let Some(class) = class.filter(|&class| class < NUM_SIZE_CLASSES) else {
+        return Ok(());
+    };
+    for near in class.saturating_sub(1)..=(class + 1).min(NUM_SIZE_CLASSES - 1) {
+        let usage = &*(*icc).usage.add(cpu);
+        let list = &*(*icc).list.add(cpu);
+        writeln!(out, "icc.class.{near}.total={}", (*icc).get_size(near))?;
+        writeln!(
+            out,
+            "icc.class.{near}.cpu_usage={}",
+            usage[near].load(Ordering::Relaxed)
+        )?;
+        writeln!(
+            out,
+            "icc.class.{near}.cpu_head={:p}",
+            list[near].load(Ordering::Relaxed)
+        )?;
+    }
+    Ok(())
+}
+
+// Return addresses from the frame pointer chain
+unsafe fn write_frames(out: &mut impl Write) -> std::fmt::Result {
+    let mut ret = Ok(());
+    let mut index = 0;
+    let frames = walk_frames(frame_pointer(), MAX_FRAMES, |addr| {
+        if ret.is_ok() {
+            ret = writeln!(out, "frame.{index}={addr:#x}");
+        }
+        index += 1;
+    });
+    ret?;
+    writeln!(out, "frames={frames}")
+}
+
+// Executable mappings, to symbolize the frames offline
+unsafe fn write_maps(out: &mut impl Write) -> std::fmt::Result {
+    let mut ret = Ok(());
+    for_each_exec_map(|line| {
+        if ret.is_ok()
+            && let Ok(line) = str::from_utf8(line)
+        {
+            ret = writeln!(out, "map={line}");
+        }
+    });
+    ret
+}
+
+unsafe fn write_report(
+    out: &mut impl Write,
+    err: OxidallocError,
+    ptr: usize,
+    extra: &str,
+    errno: Option<i32>,
+) -> std::fmt::Result {
+    writeln!(out, "crash.version={CRASH_REPORT_VERSION}")?;
+    writeln!(out, "error={err:?}")?;
+    writeln!(out, "code={:#x}", err as u32)?;
+    writeln!(out, "context={extra}")?;
+    if let Some(errno) = errno {
+        writeln!(out, "errno={errno}")?;
+    }
+    writeln!(out, "pid={}", libc::getpid())?;
+    writeln!(out, "ptr={ptr:#x}")?;
+
+    let header = find_header(ptr);
+    if let Some((addr, header)) = &header {
+        write_header(out, *addr, header)?;
+        if (header.class as usize) < NUM_SIZE_CLASSES {
+            write_slab(out, *addr, header.class as usize)?;
+        }
+    }
+
+    write_thread(out)?;
+    write_icc(out, header.map(|(_, header)| header.class as usize))?;
+    write_frames(out)?;
+    write_maps(out)?;
+    writeln!(out, "crash.end=1")
+}
+
+// `ptr` is only an address here, it is never dereferenced directly
+pub unsafe fn write_crash_report(err: OxidallocError, ptr: usize, extra: &str, errno: Option<i32>) {
+    if REPORTING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let (fd, owned) = open_report();
+    {
+        let mut out = StackWriter::new(fd_sink(fd));
+        let _ = write_report(&mut out, err, ptr, extra, errno);
+    }
+    if owned {
+        libc::close(fd);
+    }
+}
@@ -28,6 +28,18 @@ unsafe fn getenv_raw(key: &[u8]) -> Option<*const u8> {
     None
 }
 
+// Raw value of `key` up to its NUL terminator
+pub unsafe fn get_env_bytes(key: &[u8]) -> Option<&'static [u8]> {
+    let val = getenv_raw(key)?;
+    let mut len = 0;
+
+    while *val.add(len) != 0 {
+        len += 1;
+    }
+
+    Some(std::slice::from_raw_parts(val, len))
+}
+
 pub unsafe fn get_env_usize(key: &[u8]) -> Option<usize> {
     let val = getenv_raw(key)?;
     let mut out = 0usize;
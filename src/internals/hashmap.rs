@@ -39,6 +39,7 @@ pub struct BigAllocMap {
     ptr: AtomicPtr<Entry>,
     cap: AtomicUsize,
     len: AtomicUsize,
+    bytes: AtomicUsize,
     tombstones: AtomicUsize,
     lock: SerialLock,
 }
@@ -51,6 +52,7 @@ impl BigAllocMap {
             ptr: AtomicPtr::new(null_mut()),
             cap: AtomicUsize::new(0),
             len: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
             tombstones: AtomicUsize::new(0),
             lock: SerialLock::new(),
         }
@@ -77,6 +79,7 @@ impl BigAllocMap {
                 (*target).meta = meta;
                 (*target).state = STATE_OCCUPIED;
                 self.len.fetch_add(1, Ordering::Relaxed);
+                self.bytes.fetch_add(meta.size, Ordering::Relaxed);
                 if first_tomb >= 0 {
                     self.tombstones.fetch_sub(1, Ordering::Relaxed);
                 }
@@ -85,6 +88,8 @@ impl BigAllocMap {
             if state == STATE_TOMBSTONE && first_tomb < 0 {
                 first_tomb = idx as isize;
             } else if state == STATE_OCCUPIED && (*entry).key == key {
+                self.bytes.fetch_sub((*entry).meta.size, Ordering::Relaxed);
+                self.bytes.fetch_add(meta.size, Ordering::Relaxed);
                 (*entry).meta = meta;
                 return;
             }
@@ -97,6 +102,7 @@ impl BigAllocMap {
             (*target).meta = meta;
             (*target).state = STATE_OCCUPIED;
             self.len.fetch_add(1, Ordering::Relaxed);
+            self.bytes.fetch_add(meta.size, Ordering::Relaxed);
             self.tombstones.fetch_sub(1, Ordering::Relaxed);
             return;
         }
@@ -149,6 +155,7 @@ impl BigAllocMap {
             if state == STATE_OCCUPIED && (*entry).key == key {
                 (*entry).state = STATE_TOMBSTONE;
                 self.len.fetch_sub(1, Ordering::Relaxed);
+                self.bytes.fetch_sub((*entry).meta.size, Ordering::Relaxed);
                 self.tombstones.fetch_add(1, Ordering::Relaxed);
                 return Some((*entry).meta);
             }
@@ -157,6 +164,20 @@ impl BigAllocMap {
         None
     }
 
+    // Number of live big allocations
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Payload bytes of all live big allocations
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
     unsafe fn ensure_capacity(&self) {
         let cap = self.cap.load(Ordering::Relaxed);
         if cap == 0 {
@@ -243,13 +264,14 @@ impl BigAllocMap {
     }
 }
 
+// MurmurHash3 finalizer, also hashes the profiler's tables
 #[inline(always)]
-fn hash_key(key: usize) -> usize {
+pub(crate) const fn hash_key(key: usize) -> usize {
     let mut x = key as u64;
     x ^= x >> 33;
-    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
     x ^= x >> 33;
-    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
     x ^= x >> 33;
     x as usize
 }
@@ -0,0 +1,155 @@
+// Leak report at exit, in the spirit of LeakSanitizer
+// With `OX_LEAK_REPORT=1` (`leak_report`) the heap profiler records every allocation, or one every
+// `OX_PROF_SAMPLE` bytes when a rate is set, and a destructor prints what is still live when the
+// process exits: by call stack (biggest first), then by size class. Nothing is scanned for
+// reachability, so buffers libc keeps for the whole process are listed too. The report goes to a
+// copy of stderr taken at boot, coreutils and friends close fd 2 in their own exit handlers.
+// The destructor sits in `.fini_array` instead of going through `atexit`, which can call malloc
+// while `boot_strap` still holds its once guard. It only takes the profiler lock, which
+// `fork_child` resets, so a child that exits after fork reports without deadlocking.
+
+use std::{
+    cmp::Reverse,
+    fmt::{self, Write},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use crate::{
+    OX_LEAK_REPORT,
+    internals::{
+        prof::{
+            PROF_DROPPED, PROF_SAMPLE, StackEntry, prof_for_each_live, prof_snapshot, scale_sample,
+        },
+        unwind::{Map, with_exec_maps},
+        writer::{StackWriter, fd_sink},
+    },
+    slab::{NUM_SIZE_CLASSES, SIZE_CLASSES, match_size_class},
+};
+
+// Live samples and bytes per size class, big allocations in the last slot
+type ClassCounts = [(u64, u64); NUM_SIZE_CLASSES + 1];
+
+static LEAK_FD: AtomicI32 = AtomicI32::new(2);
+
+#[used]
+#[unsafe(link_section = ".fini_array")]
+static LEAK_HOOK: extern "C" fn() = leak_at_exit;
+
+extern "C" fn leak_at_exit() {
+    unsafe {
+        if OX_LEAK_REPORT {
+            write_leak_report(LEAK_FD.load(Ordering::Relaxed));
+        }
+    }
+}
+
+// From `boot_strap`, once the report is turned on
+pub(crate) unsafe fn keep_stderr() {
+    let fd = libc::fcntl(2, libc::F_DUPFD_CLOEXEC, 3);
+    if fd >= 0 {
+        LEAK_FD.store(fd, Ordering::Relaxed);
+    }
+}
+
+// Nothing is written when no sample is live
+pub(crate) unsafe fn write_leak_report(fd: i32) {
+    let rate = PROF_SAMPLE.load(Ordering::Relaxed);
+
+    let mut classes: ClassCounts = [(0, 0); NUM_SIZE_CLASSES + 1];
+    prof_for_each_live(|size| {
+        let class = match_size_class(size.max(1)).unwrap_or(NUM_SIZE_CLASSES);
+        classes[class].0 += 1;
+        classes[class].1 += size as u64;
+    });
+    if classes.iter().all(|&(count, _)| count == 0) {
+        return;
+    }
+
+    let _ = prof_snapshot(|stacks| {
+        stacks.sort_unstable_by_key(|stack| Reverse(stack.live_bytes));
+        let stacks = &*stacks;
+
+        let report = |maps: &[Map]| {
+            let mut out = StackWriter::new(fd_sink(fd));
+            let _ = write_report(&mut out, rate, stacks, maps, &classes);
+        };
+        // Without the mappings the frames are still worth printing
+        with_exec_maps(report).unwrap_or_else(|| report(&[]));
+    });
+}
+
+fn write_report<F: FnMut(&[u8])>(
+    out: &mut StackWriter<F>,
+    rate: usize,
+    stacks: &[StackEntry],
+    maps: &[Map],
+    classes: &ClassCounts,
+) -> fmt::Result {
+    let pid = unsafe { libc::getpid() };
+    if rate == 1 {
+        writeln!(out, "=={pid}== oxidalloc: memory still live at exit")?;
+    } else {
+        writeln!(
+            out,
+            "=={pid}== oxidalloc: memory still live at exit (sampled every {rate} bytes, estimated)"
+        )?;
+    }
+
+    let (mut total_count, mut total_bytes) = (0, 0);
+    for stack in stacks.iter().filter(|stack| stack.live_count != 0) {
+        let (count, bytes) = scale_sample(stack.live_count, stack.live_bytes, rate);
+        total_count += count;
+        total_bytes += bytes;
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Leak of {bytes} byte(s) in {count} object(s) allocated from:"
+        )?;
+        for (index, &ret) in stack.frames[..stack.depth].iter().enumerate() {
+            // The call instruction, not the one after it
+            let addr = ret as u64 - 1;
+            write!(out, "    #{index} {addr:#x}")?;
+            if let Some(map) = maps.iter().find(|map| map.contains(addr)) {
+                out.write_str(" (")?;
+                out.write_bytes(map.name());
+                write!(out, "+{:#x})", addr - map.start + map.offset)?;
+            }
+            writeln!(out)?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "By size class:")?;
+    for (class, &(count, bytes)) in classes.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+
+        let (count, bytes) = scale_sample(count, bytes, rate);
+        if class < NUM_SIZE_CLASSES {
+            writeln!(
+                out,
+                "    class {class} ({} bytes): {count} object(s), {bytes} byte(s)",
+                SIZE_CLASSES[class]
+            )?;
+        } else {
+            writeln!(out, "    big: {count} object(s), {bytes} byte(s)")?;
+        }
+    }
+
+    let dropped = PROF_DROPPED.load(Ordering::Relaxed);
+    if dropped != 0 {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{dropped} allocation(s) were not tracked, the profiler tables were full"
+        )?;
+    }
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "SUMMARY: oxidalloc: {total_bytes} byte(s) leaked in {total_count} allocation(s)."
+    )
+}
@@ -1,10 +1,18 @@
 use std::os::raw::c_int;
 
+pub mod conf;
+pub mod crash;
 pub mod env;
 pub mod hashmap;
+pub mod leak;
 pub mod lock;
 pub mod once;
 pub mod oncelock;
+pub mod pprof;
+pub mod prof;
+pub mod trace;
+pub mod unwind;
+pub mod writer;
 
 unsafe extern "C" {
     pub fn __errno_location() -> *mut c_int;
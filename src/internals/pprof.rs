@@ -0,0 +1,236 @@
+// `profile.proto` writer for the sampled heap, readable by `pprof` and `go tool pprof`
+// One profile carries both views as four sample types: `alloc_objects`/`alloc_space`
+// (cumulative) and `inuse_objects`/`inuse_space` (live, the default). Values are scaled by the
+// sampling probability like Go's heap profiles, with the rate in effect at dump time. Locations
+// are return addresses minus one, the executable mappings let pprof symbolize them from the
+// binaries on disk. The output is plain protobuf, pprof does not require gzip.
+
+use std::sync::atomic::Ordering;
+
+use crate::{
+    internals::{
+        prof::{PROF_MAX_FRAMES, PROF_SAMPLE, StackEntry, prof_snapshot, scale_sample},
+        unwind::{Map, with_exec_maps},
+        writer::{StackWriter, fd_sink},
+    },
+    sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, mmap_memory, unmap_memory},
+};
+
+const STRINGS: [&str; 8] = [
+    "",
+    "alloc_objects",
+    "count",
+    "alloc_space",
+    "bytes",
+    "inuse_objects",
+    "inuse_space",
+    "space",
+];
+
+// Fixed size message body, nested messages are built here before their length is known
+struct Msg<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Msg<N> {
+    const fn new() -> Self {
+        Msg {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn varint(&mut self, mut val: u64) {
+        while val >= 0x80 {
+            self.raw(&[val as u8 | 0x80]);
+            val >>= 7;
+        }
+        self.raw(&[val as u8]);
+    }
+
+    // Zero is the default and is left out
+    fn uint(&mut self, field: u64, val: u64) {
+        if val != 0 {
+            self.varint(field << 3);
+            self.varint(val);
+        }
+    }
+
+    fn len_field(&mut self, field: u64, bytes: &[u8]) {
+        self.varint((field << 3) | 2);
+        self.varint(bytes.len() as u64);
+        self.raw(bytes);
+    }
+}
+
+// A whole field of the top level `Profile`
+fn emit<F: FnMut(&[u8])>(out: &mut StackWriter<F>, field: u64, body: &[u8]) {
+    let mut head = Msg::<20>::new();
+    head.varint((field << 3) | 2);
+    head.varint(body.len() as u64);
+    out.write_bytes(head.bytes());
+    out.write_bytes(body);
+}
+
+fn emit_uint<F: FnMut(&[u8])>(out: &mut StackWriter<F>, field: u64, val: u64) {
+    let mut msg = Msg::<20>::new();
+    msg.uint(field, val);
+    out.write_bytes(msg.bytes());
+}
+
+fn value_type(kind: u64, unit: u64) -> Msg<8> {
+    let mut msg = Msg::new();
+    msg.uint(1, kind);
+    msg.uint(2, unit);
+    msg
+}
+
+unsafe fn scratch<T>(count: usize) -> Option<*mut T> {
+    mmap_memory(
+        std::ptr::null_mut(),
+        count.max(1) * size_of::<T>(),
+        MMapFlags {
+            prot: MProtFlags::READ | MProtFlags::WRITE,
+            map: MemoryFlags::PRIVATE | MemoryFlags::NORESERVE,
+        },
+    )
+    .ok()
+    .map(|ptr| ptr as *mut T)
+}
+
+unsafe fn release<T>(ptr: *mut T, count: usize) {
+    let _ = unmap_memory(ptr.cast(), count.max(1) * size_of::<T>());
+}
+
+// Location ids by address, open addressing over a power of two table. Returns the id and
+// whether it is new.
+unsafe fn location_id(
+    table: *mut (u64, u64),
+    cap: usize,
+    next: &mut u64,
+    addr: u64,
+) -> (u64, bool) {
+    let mut idx = (addr.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 20) as usize & (cap - 1);
+    loop {
+        let slot = &mut *table.add(idx);
+        if slot.1 == 0 {
+            *next += 1;
+            *slot = (addr, *next);
+            return (*next, true);
+        }
+        if slot.0 == addr {
+            return (slot.1, false);
+        }
+        idx = (idx + 1) & (cap - 1);
+    }
+}
+
+unsafe fn write_stacks(fd: i32, stacks: &[StackEntry], maps: &[Map]) -> bool {
+    let rate = PROF_SAMPLE.load(Ordering::Relaxed);
+    let frames = stacks.iter().map(|stack| stack.depth).sum::<usize>();
+    let cap = (frames * 2).next_power_of_two().max(64);
+
+    let Some(table) = scratch::<(u64, u64)>(cap) else {
+        return false;
+    };
+    // Unique addresses in id order
+    let Some(addrs) = scratch::<u64>(frames) else {
+        release(table, cap);
+        return false;
+    };
+
+    let mut out = StackWriter::new(fd_sink(fd));
+
+    for (kind, unit) in [(1, 2), (3, 4), (5, 2), (6, 4)] {
+        emit(&mut out, 1, value_type(kind, unit).bytes());
+    }
+
+    let mut next_id = 0;
+    for stack in stacks {
+        let mut ids = Msg::<{ 10 * PROF_MAX_FRAMES }>::new();
+        for &ret in &stack.frames[..stack.depth] {
+            let addr = ret as u64 - 1;
+            let (id, new) = location_id(table, cap, &mut next_id, addr);
+            if new {
+                addrs.add(id as usize - 1).write(addr);
+            }
+            ids.varint(id);
+        }
+
+        let (alloc_count, alloc_bytes) = scale_sample(stack.alloc_count, stack.alloc_bytes, rate);
+        let (live_count, live_bytes) = scale_sample(stack.live_count, stack.live_bytes, rate);
+        let mut values = Msg::<40>::new();
+        for val in [alloc_count, alloc_bytes, live_count, live_bytes] {
+            values.varint(val);
+        }
+
+        let mut sample = Msg::<{ 10 * PROF_MAX_FRAMES + 64 }>::new();
+        sample.len_field(1, ids.bytes());
+        sample.len_field(2, values.bytes());
+        emit(&mut out, 2, sample.bytes());
+    }
+
+    for (i, map) in maps.iter().enumerate() {
+        let mut msg = Msg::<64>::new();
+        msg.uint(1, i as u64 + 1);
+        msg.uint(2, map.start);
+        msg.uint(3, map.end);
+        msg.uint(4, map.offset);
+        msg.uint(5, (STRINGS.len() + i) as u64);
+        emit(&mut out, 3, msg.bytes());
+    }
+
+    for id in 1..=next_id {
+        let addr = *addrs.add(id as usize - 1);
+        let mapping = maps
+            .iter()
+            .position(|map| map.contains(addr))
+            .map_or(0, |i| i as u64 + 1);
+
+        let mut msg = Msg::<32>::new();
+        msg.uint(1, id);
+        msg.uint(2, mapping);
+        msg.uint(3, addr);
+        emit(&mut out, 4, msg.bytes());
+    }
+
+    for name in STRINGS {
+        emit(&mut out, 6, name.as_bytes());
+    }
+    for map in maps {
+        emit(&mut out, 6, map.name());
+    }
+
+    let mut now = std::mem::zeroed::<libc::timespec>();
+    libc::clock_gettime(libc::CLOCK_REALTIME, &raw mut now);
+    emit_uint(
+        &mut out,
+        9,
+        now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64,
+    );
+    emit(&mut out, 11, value_type(7, 4).bytes());
+    emit_uint(&mut out, 12, rate as u64);
+    emit_uint(&mut out, 14, 6);
+    drop(out);
+
+    release(table, cap);
+    release(addrs, frames);
+    true
+}
+
+// False when there was no memory to build the profile
+pub(crate) unsafe fn write_heap_profile(fd: i32) -> bool {
+    prof_snapshot(|stacks| with_exec_maps(|maps| write_stacks(fd, stacks, maps)))
+        .flatten()
+        .unwrap_or(false)
+}
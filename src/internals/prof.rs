@@ -0,0 +1,427 @@
+// Sampling heap profiler, in the style of jemalloc `prof` and TCMalloc
+// Every thread counts down the bytes it asks malloc for, the allocation that crosses zero takes
+// the cold path and its frame pointer stack is recorded. Intervals are drawn from an exponential
+// distribution with mean `rate` (`OX_PROF_SAMPLE`, 0 turns sampling off), so an allocation of
+// `size` bytes is sampled with probability 1 - exp(-size / rate) and totals can be scaled back.
+// A rate of 1 records every allocation, the leak report (`internals/leak.rs`) runs on that.
+// Stacks and live samples sit in fixed tables mapped on the first sample and never unmapped, a
+// sample that does not fit is dropped. Frees only take the lock when the pointer hashes to a
+// `filter` bucket that holds a live sample.
+
+use std::{
+    hint::{likely, unlikely},
+    os::raw::c_void,
+    ptr::{copy_nonoverlapping, null_mut},
+    sync::atomic::{AtomicPtr, AtomicU16, AtomicUsize, Ordering},
+};
+
+use crate::{
+    internals::{hashmap::hash_key, lock::SerialLock, unwind::walk_own_frames},
+    sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, mmap_memory, unmap_memory},
+    va::{
+        bootstrap::{boot_strap, init_alloc_random},
+        rng::Rng,
+    },
+};
+
+pub const PROF_MAX_FRAMES: usize = 32;
+const STACK_CAP: usize = 1 << 13;
+// Exhaustive tracking for the leak report fills this one, it is only touched where used
+const LIVE_CAP: usize = 1 << 20;
+const FILTER_CAP: usize = 1 << 16;
+// Tables stop taking entries at 70%, probes stay short
+const LOAD_NUM: usize = 7;
+const LOAD_DEN: usize = 10;
+// With sampling off a thread still looks at the rate again after this many bytes
+const RECHECK_BYTES: usize = 1024 * 1024;
+
+// Mean bytes between samples
+pub static PROF_SAMPLE: AtomicUsize = AtomicUsize::new(0);
+pub static PROF_LIVE: AtomicUsize = AtomicUsize::new(0);
+pub static PROF_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+#[thread_local]
+static mut PROF_LEFT: usize = 0;
+#[thread_local]
+static mut PROF_RNG: Rng = Rng::new(0);
+#[thread_local]
+static mut PROF_SEEDED: bool = false;
+// Allocations made while recording (libc resolving a symbol on first use) are not sampled
+#[thread_local]
+static mut IN_RECORD: bool = false;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct StackEntry {
+    pub hash: usize,
+    // 0 marks a free slot
+    pub depth: usize,
+    pub frames: [usize; PROF_MAX_FRAMES],
+    pub alloc_count: u64,
+    pub alloc_bytes: u64,
+    pub live_count: u64,
+    pub live_bytes: u64,
+}
+
+#[repr(C)]
+struct LiveEntry {
+    // 0 marks a free slot
+    ptr: usize,
+    size: usize,
+    stack: usize,
+}
+
+struct Tables {
+    stacks: *mut StackEntry,
+    live: *mut LiveEntry,
+    nstacks: usize,
+}
+
+static PROF_LOCK: SerialLock = SerialLock::new();
+static mut TABLES: Tables = Tables {
+    stacks: null_mut(),
+    live: null_mut(),
+    nstacks: 0,
+};
+// Written under the lock, read without it on every free
+static FILTER: AtomicPtr<AtomicU16> = AtomicPtr::new(null_mut());
+
+fn hash_frames(frames: &[usize]) -> usize {
+    frames
+        .iter()
+        .fold(frames.len(), |hash, &frame| hash_key(hash ^ frame))
+}
+
+// True when this allocation has to go through `sample_malloc`
+#[inline(always)]
+pub(crate) unsafe fn prof_tick(size: usize) -> bool {
+    if likely(size < PROF_LEFT) {
+        PROF_LEFT -= size;
+        return false;
+    }
+    true
+}
+
+fn next_interval(rng: &mut Rng, rate: usize) -> usize {
+    // 53 random bits, u in (0, 1]
+    let u = ((rng.next_usize() >> 11) + 1) as f64 / (1u64 << 53) as f64;
+    (-u.ln() * rate as f64) as usize
+}
+
+// Draws the next countdown, false when sampling is off
+unsafe fn rearm() -> bool {
+    boot_strap();
+
+    let rate = PROF_SAMPLE.load(Ordering::Relaxed);
+    if rate == 0 || IN_RECORD {
+        PROF_LEFT = RECHECK_BYTES;
+        return false;
+    }
+
+    if !PROF_SEEDED {
+        PROF_RNG = Rng::new(init_alloc_random());
+        PROF_SEEDED = true;
+    }
+    PROF_LEFT = if rate == 1 {
+        0
+    } else {
+        next_interval(&mut PROF_RNG, rate)
+    };
+    true
+}
+
+// Go's `scaleHeapSample`: each object of the average size was sampled with probability
+// 1 - exp(-avg / rate)
+pub(crate) fn scale_sample(count: u64, bytes: u64, rate: usize) -> (u64, u64) {
+    if count == 0 || bytes == 0 || rate <= 1 {
+        return (count, bytes);
+    }
+
+    let avg = bytes as f64 / count as f64;
+    let factor = 1.0 / (1.0 - (-avg / rate as f64).exp());
+    (
+        (count as f64 * factor) as u64,
+        (bytes as f64 * factor) as u64,
+    )
+}
+
+// A new rate applies to the calling thread right away, the others pick it up on their next
+// sample (or recheck)
+pub fn set_prof_sample(rate: usize) {
+    PROF_SAMPLE.store(rate, Ordering::Relaxed);
+    unsafe { PROF_LEFT = 0 };
+}
+
+// The cold path behind `prof_tick`. `fp` is the frame of the allocator entry point, so the leaf
+// frame of every stack is the malloc (or calloc, realloc...) call itself.
+pub(crate) unsafe fn sample_malloc(
+    size: usize,
+    fp: usize,
+    alloc: impl FnOnce() -> *mut c_void,
+) -> *mut c_void {
+    let sampled = rearm();
+    let ptr = alloc();
+    if sampled && !ptr.is_null() {
+        IN_RECORD = true;
+        record(ptr as usize, size, fp);
+        IN_RECORD = false;
+    }
+    ptr
+}
+
+unsafe fn map_table<T>(cap: usize) -> Option<*mut T> {
+    mmap_memory(
+        null_mut(),
+        cap * size_of::<T>(),
+        MMapFlags {
+            prot: MProtFlags::READ | MProtFlags::WRITE,
+            map: MemoryFlags::PRIVATE | MemoryFlags::NORESERVE,
+        },
+    )
+    .ok()
+    .map(|ptr| ptr as *mut T)
+}
+
+// Under the lock
+unsafe fn ensure_tables() -> bool {
+    if !TABLES.stacks.is_null() {
+        return true;
+    }
+
+    let (Some(stacks), Some(live), Some(filter)) = (
+        map_table::<StackEntry>(STACK_CAP),
+        map_table::<LiveEntry>(LIVE_CAP),
+        map_table::<AtomicU16>(FILTER_CAP),
+    ) else {
+        return false;
+    };
+
+    TABLES.stacks = stacks;
+    TABLES.live = live;
+    FILTER.store(filter, Ordering::Release);
+    true
+}
+
+#[inline(always)]
+fn bucket(ptr: usize) -> usize {
+    hash_key(ptr) & (FILTER_CAP - 1)
+}
+
+// Under the lock
+unsafe fn find_stack(frames: &[usize]) -> Option<usize> {
+    let hash = hash_frames(frames);
+    let mut idx = hash & (STACK_CAP - 1);
+
+    for _ in 0..STACK_CAP {
+        let entry = &mut *TABLES.stacks.add(idx);
+        if entry.depth == 0 {
+            if (TABLES.nstacks + 1) * LOAD_DEN > STACK_CAP * LOAD_NUM {
+                return None;
+            }
+            entry.hash = hash;
+            entry.depth = frames.len();
+            entry.frames[..frames.len()].copy_from_slice(frames);
+            TABLES.nstacks += 1;
+            return Some(idx);
+        }
+        if entry.hash == hash && entry.frames[..entry.depth] == *frames {
+            return Some(idx);
+        }
+        idx = (idx + 1) & (STACK_CAP - 1);
+    }
+    None
+}
+
+// Under the lock
+unsafe fn insert_live(ptr: usize, size: usize, stack: usize) -> bool {
+    if (PROF_LIVE.load(Ordering::Relaxed) + 1) * LOAD_DEN > LIVE_CAP * LOAD_NUM {
+        return false;
+    }
+
+    let mut idx = hash_key(ptr) & (LIVE_CAP - 1);
+    loop {
+        let entry = &mut *TABLES.live.add(idx);
+        // A pointer handed out again was freed without us seeing it, its old sample goes
+        if entry.ptr == ptr {
+            forget_stack(entry);
+        }
+        if entry.ptr == 0 || entry.ptr == ptr {
+            if entry.ptr == 0 {
+                PROF_LIVE.fetch_add(1, Ordering::Relaxed);
+                (*FILTER.load(Ordering::Relaxed).add(bucket(ptr))).fetch_add(1, Ordering::Relaxed);
+            }
+            *entry = LiveEntry { ptr, size, stack };
+            return true;
+        }
+        idx = (idx + 1) & (LIVE_CAP - 1);
+    }
+}
+
+unsafe fn forget_stack(entry: &LiveEntry) {
+    let stack = &mut *TABLES.stacks.add(entry.stack);
+    stack.live_count -= 1;
+    stack.live_bytes -= entry.size as u64;
+}
+
+// Under the lock. Linear probing without tombstones: later entries of the run shift back into
+// the hole.
+unsafe fn remove_live(ptr: usize) -> Option<LiveEntry> {
+    let mut idx = hash_key(ptr) & (LIVE_CAP - 1);
+    loop {
+        let entry = TABLES.live.add(idx);
+        if (*entry).ptr == 0 {
+            return None;
+        }
+        if (*entry).ptr == ptr {
+            break;
+        }
+        idx = (idx + 1) & (LIVE_CAP - 1);
+    }
+
+    let removed = TABLES.live.add(idx).read();
+    let mut hole = idx;
+    let mut next = (idx + 1) & (LIVE_CAP - 1);
+    loop {
+        let entry = TABLES.live.add(next);
+        if (*entry).ptr == 0 {
+            break;
+        }
+
+        let origin = hash_key((*entry).ptr) & (LIVE_CAP - 1);
+        // Moves back unless its origin lies between the hole and where it sits now
+        let stays = if hole <= next {
+            hole < origin && origin <= next
+        } else {
+            hole < origin || origin <= next
+        };
+        if !stays {
+            copy_nonoverlapping(entry, TABLES.live.add(hole), 1);
+            hole = next;
+        }
+        next = (next + 1) & (LIVE_CAP - 1);
+    }
+    (*TABLES.live.add(hole)).ptr = 0;
+
+    PROF_LIVE.fetch_sub(1, Ordering::Relaxed);
+    (*FILTER.load(Ordering::Relaxed).add(bucket(ptr))).fetch_sub(1, Ordering::Relaxed);
+    Some(removed)
+}
+
+#[cold]
+#[inline(never)]
+unsafe fn record(ptr: usize, size: usize, fp: usize) {
+    let mut frames = [0usize; PROF_MAX_FRAMES];
+    let mut depth = 0;
+    walk_own_frames(fp, PROF_MAX_FRAMES, |ret| {
+        frames[depth] = ret;
+        depth += 1;
+    });
+    if depth == 0 {
+        PROF_DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let _guard = PROF_LOCK.lock();
+    let stack = if ensure_tables() {
+        find_stack(&frames[..depth])
+    } else {
+        None
+    };
+    let Some(stack) = stack else {
+        PROF_DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+
+    let entry = &mut *TABLES.stacks.add(stack);
+    entry.alloc_count += 1;
+    entry.alloc_bytes += size as u64;
+    if insert_live(ptr, size, stack) {
+        entry.live_count += 1;
+        entry.live_bytes += size as u64;
+    } else {
+        PROF_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[inline(always)]
+fn maybe_sampled(ptr: usize) -> bool {
+    let filter = FILTER.load(Ordering::Relaxed);
+    unlikely(!filter.is_null())
+        && unsafe { (*filter.add(bucket(ptr))).load(Ordering::Relaxed) } != 0
+}
+
+// Drops the sample of a payload pointer that is being freed
+#[inline(always)]
+pub(crate) unsafe fn prof_free(ptr: *mut c_void) {
+    if maybe_sampled(ptr as usize) {
+        forget(ptr as usize);
+    }
+}
+
+#[cold]
+#[inline(never)]
+unsafe fn forget(ptr: usize) {
+    let _guard = PROF_LOCK.lock();
+    if let Some(entry) = remove_live(ptr) {
+        forget_stack(&entry);
+    }
+}
+
+// A sampled allocation moved to `new` without going through malloc and free
+pub(crate) unsafe fn prof_moved(old: *mut c_void, new: *mut c_void) {
+    if !maybe_sampled(old as usize) {
+        return;
+    }
+
+    let _guard = PROF_LOCK.lock();
+    if let Some(entry) = remove_live(old as usize)
+        && !insert_live(new as usize, entry.size, entry.stack)
+    {
+        forget_stack(&entry);
+        PROF_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Runs `f` on a copy of every recorded stack, taken under the lock so `f` may take its time
+pub(crate) unsafe fn prof_snapshot<R>(f: impl FnOnce(&mut [StackEntry]) -> R) -> Option<R> {
+    let copy;
+    let mut count = 0;
+    {
+        let _guard = PROF_LOCK.lock();
+        if TABLES.stacks.is_null() {
+            return Some(f(&mut []));
+        }
+
+        copy = map_table::<StackEntry>(TABLES.nstacks.max(1))?;
+        for idx in 0..STACK_CAP {
+            let entry = TABLES.stacks.add(idx);
+            if (*entry).depth != 0 {
+                copy_nonoverlapping(entry, copy.add(count), 1);
+                count += 1;
+            }
+        }
+    }
+
+    let ret = f(std::slice::from_raw_parts_mut(copy, count));
+    let _ = unmap_memory(copy as *mut c_void, count.max(1) * size_of::<StackEntry>());
+    Some(ret)
+}
+
+// Calls `f` with the size of every live sample, under the lock
+pub(crate) unsafe fn prof_for_each_live(mut f: impl FnMut(usize)) {
+    let _guard = PROF_LOCK.lock();
+    if TABLES.live.is_null() {
+        return;
+    }
+
+    for idx in 0..LIVE_CAP {
+        let entry = &*TABLES.live.add(idx);
+        if entry.ptr != 0 {
+            f(entry.size);
+        }
+    }
+}
+
+pub(crate) fn reset_fork_prof() {
+    PROF_LOCK.reset_on_fork();
+}
@@ -0,0 +1,350 @@
+// Allocation trace, read back by the `ox-replay` binary
+// With `OX_TRACE=<path>` every malloc, calloc, realloc, posix_memalign (and the memalign family on
+// top of it) and free is logged as a fixed size `TraceRecord`. Calls made from inside another
+// traced call (realloc moving through malloc and free) are not logged again.
+// Each thread owns a ring of records and is its only writer, so logging takes no lock. A full
+// ring is appended to the file by its owner, the rest are flushed when their thread exits and
+// from a `.fini_array` destructor at process exit. Flushes of one ring are serialized by its
+// `flushing` flag, writes go through `O_APPEND` in whole records. Rings are never unmapped, an
+// exited thread's ring is picked up by the next new thread.
+// `%p` in the path becomes the pid, so programs that exec children with the same environment get
+// a file per process. A forked child keeps writing to its parent's file with its own thread ids.
+
+use std::{
+    hint::{spin_loop, unlikely},
+    os::raw::c_void,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::{
+    OX_TRACE,
+    internals::{env::get_env_bytes, writer::fd_sink},
+    sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, mmap_memory},
+    va::bootstrap::boot_strap,
+};
+
+pub const TRACE_MAGIC: [u8; 8] = *b"OXTRACE\0";
+pub const TRACE_VERSION: u32 = 1;
+// Magic, version and record size
+pub const TRACE_HEADER_SIZE: usize = 16;
+const RING_CAP: usize = 4096;
+const PATH_MAX: usize = 512;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    Malloc = 1,
+    Calloc = 2,
+    Realloc = 3,
+    Memalign = 4,
+    Free = 5,
+}
+
+impl TraceOp {
+    pub fn from_raw(op: u32) -> Option<Self> {
+        Some(match op {
+            1 => TraceOp::Malloc,
+            2 => TraceOp::Calloc,
+            3 => TraceOp::Realloc,
+            4 => TraceOp::Memalign,
+            5 => TraceOp::Free,
+            _ => return None,
+        })
+    }
+}
+
+// Native endian, as written by the traced process
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    // CLOCK_MONOTONIC
+    pub nanos: u64,
+    // Pointer passed in (realloc, free), 0 otherwise
+    pub ptr: u64,
+    // Pointer handed out, 0 for free and failed calls
+    pub ret: u64,
+    // Total bytes for calloc
+    pub size: u64,
+    // Memalign family only
+    pub align: u64,
+    pub tid: u32,
+    pub op: u32,
+}
+
+// The records of a trace file, None when the header does not match this build
+pub fn parse_trace(bytes: &[u8]) -> Option<impl Iterator<Item = TraceRecord> + '_> {
+    let header = bytes.get(..TRACE_HEADER_SIZE)?;
+    let version = u32::from_ne_bytes(header[8..12].try_into().ok()?);
+    let record_size = u32::from_ne_bytes(header[12..16].try_into().ok()?);
+    if header[..8] != TRACE_MAGIC
+        || version != TRACE_VERSION
+        || record_size as usize != size_of::<TraceRecord>()
+    {
+        return None;
+    }
+
+    Some(
+        bytes[TRACE_HEADER_SIZE..]
+            .chunks_exact(size_of::<TraceRecord>())
+            .map(|chunk| unsafe { chunk.as_ptr().cast::<TraceRecord>().read_unaligned() }),
+    )
+}
+
+#[repr(C)]
+struct Ring {
+    records: [TraceRecord; RING_CAP],
+    // Only the owner moves `head`, `tail` moves under `flushing`
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    flushing: AtomicBool,
+    in_use: AtomicBool,
+    // Registry of every ring, never unlinked
+    next: *mut Ring,
+}
+
+static TRACE_FD: AtomicI32 = AtomicI32::new(-1);
+static RINGS: AtomicPtr<Ring> = AtomicPtr::new(null_mut());
+
+#[thread_local]
+static mut RING: *mut Ring = null_mut();
+#[thread_local]
+static mut TID: u32 = 0;
+#[thread_local]
+static mut IN_TRACE: bool = false;
+
+#[used]
+#[unsafe(link_section = ".fini_array")]
+static TRACE_HOOK: extern "C" fn() = trace_at_exit;
+
+// True when the caller has to go through `trace_call`
+#[inline(always)]
+pub(crate) fn tracing() -> bool {
+    unsafe { unlikely(OX_TRACE) && !IN_TRACE }
+}
+
+// Runs the untraced call and logs it. `call` gets back into the same entry point, which lets it
+// through because `IN_TRACE` is set. The first call of the process also lands here before boot,
+// it boots first so `OX_TRACE` is known before anything is logged.
+#[cold]
+#[inline(never)]
+pub(crate) unsafe fn trace_call(
+    op: TraceOp,
+    ptr: *mut c_void,
+    size: usize,
+    align: usize,
+    call: impl FnOnce() -> *mut c_void,
+) -> *mut c_void {
+    IN_TRACE = true;
+    boot_strap();
+    let ret = call();
+    IN_TRACE = false;
+    if !OX_TRACE {
+        return ret;
+    }
+
+    let mut now = std::mem::zeroed::<libc::timespec>();
+    libc::clock_gettime(libc::CLOCK_MONOTONIC, &raw mut now);
+    if TID == 0 {
+        TID = libc::gettid() as u32;
+    }
+
+    log(TraceRecord {
+        nanos: now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64,
+        ptr: ptr as u64,
+        ret: ret as u64,
+        size: size as u64,
+        align: align as u64,
+        tid: TID,
+        op: op as u32,
+    });
+    ret
+}
+
+unsafe fn log(record: TraceRecord) {
+    if RING.is_null() {
+        RING = acquire_ring();
+        if RING.is_null() {
+            return;
+        }
+    }
+
+    let ring = RING;
+    let head = (*ring).head.load(Ordering::Relaxed);
+    if head - (*ring).tail.load(Ordering::Acquire) == RING_CAP {
+        flush(ring);
+    }
+
+    (&raw mut (*ring).records[head % RING_CAP]).write(record);
+    (*ring).head.store(head + 1, Ordering::Release);
+}
+
+// A ring left behind by an exited thread, or a new one
+unsafe fn acquire_ring() -> *mut Ring {
+    let mut ring = RINGS.load(Ordering::Acquire);
+    while !ring.is_null() {
+        if (*ring)
+            .in_use
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return ring;
+        }
+        ring = (*ring).next;
+    }
+
+    let Ok(mem) = mmap_memory(
+        null_mut(),
+        size_of::<Ring>(),
+        MMapFlags {
+            prot: MProtFlags::READ | MProtFlags::WRITE,
+            map: MemoryFlags::PRIVATE,
+        },
+    ) else {
+        return null_mut();
+    };
+
+    // Zeroed memory is an empty ring, only `in_use` has to be set
+    let ring = mem as *mut Ring;
+    (*ring).in_use.store(true, Ordering::Relaxed);
+    let mut head = RINGS.load(Ordering::Relaxed);
+    loop {
+        (*ring).next = head;
+        match RINGS.compare_exchange_weak(head, ring, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return ring,
+            Err(now) => head = now,
+        }
+    }
+}
+
+unsafe fn records_bytes(ring: *mut Ring, from: usize, to: usize) -> &'static [u8] {
+    let records = (&raw const (*ring).records).cast::<TraceRecord>().add(from);
+    std::slice::from_raw_parts(records.cast(), (to - from) * size_of::<TraceRecord>())
+}
+
+unsafe fn flush(ring: *mut Ring) {
+    while (*ring)
+        .flushing
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+
+    let tail = (*ring).tail.load(Ordering::Relaxed);
+    let head = (*ring).head.load(Ordering::Acquire);
+    if head != tail {
+        let mut sink = fd_sink(TRACE_FD.load(Ordering::Relaxed));
+        let (start, end) = (tail % RING_CAP, head % RING_CAP);
+        if start < end {
+            sink(records_bytes(ring, start, end));
+        } else {
+            sink(records_bytes(ring, start, RING_CAP));
+            sink(records_bytes(ring, 0, end));
+        }
+        (*ring).tail.store(head, Ordering::Release);
+    }
+
+    (*ring).flushing.store(false, Ordering::Release);
+}
+
+// From the thread cache destructor
+pub(crate) unsafe fn trace_thread_exit() {
+    if RING.is_null() {
+        return;
+    }
+
+    flush(RING);
+    (*RING).in_use.store(false, Ordering::Release);
+    RING = null_mut();
+}
+
+extern "C" fn trace_at_exit() {
+    unsafe {
+        if !OX_TRACE {
+            return;
+        }
+
+        let mut ring = RINGS.load(Ordering::Acquire);
+        while !ring.is_null() {
+            flush(ring);
+            ring = (*ring).next;
+        }
+    }
+}
+
+// `path` with `%p` replaced by the pid, NUL terminated
+fn expand_path(path: &[u8], out: &mut [u8; PATH_MAX]) -> Option<()> {
+    let mut digits = [0u8; 10];
+    let mut pid = unsafe { libc::getpid() } as u32;
+    let mut ndigits = 0;
+    loop {
+        digits[ndigits] = b'0' + (pid % 10) as u8;
+        ndigits += 1;
+        pid /= 10;
+        if pid == 0 {
+            break;
+        }
+    }
+    digits[..ndigits].reverse();
+
+    let mut len = 0;
+    let mut rest = path;
+    while let Some((&byte, tail)) = rest.split_first() {
+        let piece: &[u8] = if byte == b'%' && tail.first() == Some(&b'p') {
+            rest = &tail[1..];
+            &digits[..ndigits]
+        } else {
+            rest = tail;
+            std::slice::from_ref(&byte)
+        };
+        out.get_mut(len..len + piece.len())?.copy_from_slice(piece);
+        len += piece.len();
+    }
+    *out.get_mut(len)? = 0;
+    Some(())
+}
+
+// From `boot_strap`. Tracing stays off when the file cannot be opened.
+pub(crate) unsafe fn init_trace() {
+    OX_TRACE = false;
+    let Some(path) = get_env_bytes(b"OX_TRACE") else {
+        return;
+    };
+    let mut buf = [0u8; PATH_MAX];
+    if path.is_empty() || expand_path(path, &mut buf).is_none() {
+        return;
+    }
+
+    let fd = libc::open(
+        buf.as_ptr().cast(),
+        libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_APPEND | libc::O_CLOEXEC,
+        0o644,
+    );
+    if fd < 0 {
+        return;
+    }
+
+    let mut header = [0u8; TRACE_HEADER_SIZE];
+    header[..8].copy_from_slice(&TRACE_MAGIC);
+    header[8..12].copy_from_slice(&TRACE_VERSION.to_ne_bytes());
+    header[12..16].copy_from_slice(&(size_of::<TraceRecord>() as u32).to_ne_bytes());
+    fd_sink(fd)(&header);
+
+    TRACE_FD.store(fd, Ordering::Relaxed);
+    OX_TRACE = true;
+}
+
+// The child's copies of unflushed records belong to the parent, which still writes them out. Only
+// the forking thread survives, it keeps its ring.
+pub(crate) unsafe fn reset_fork_trace() {
+    TID = 0;
+    let mut ring = RINGS.load(Ordering::Acquire);
+    while !ring.is_null() {
+        (*ring).flushing.store(false, Ordering::Relaxed);
+        let head = (*ring).head.load(Ordering::Relaxed);
+        (*ring).tail.store(head, Ordering::Relaxed);
+        (*ring).in_use.store(ring == RING, Ordering::Relaxed);
+        ring = (*ring).next;
+    }
+}
@@ -0,0 +1,241 @@
+// Frame pointer stack walks and the executable mappings needed to symbolize them offline.
+// Used by crash reports and the heap profiler, so nothing here takes a lock. Crash reports read
+// the stack through `process_vm_readv`, which fails instead of faulting when a frame without a
+// frame pointer leaves garbage in the chain. The profiler walks on every sample and reads frames
+// directly, bounded by the thread's own stack.
+
+use std::{mem::MaybeUninit, os::raw::c_void, ptr::null_mut};
+
+use crate::sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, mmap_memory, unmap_memory};
+
+const MAX_MAPS: usize = 256;
+const NAME_MAX: usize = 256;
+
+// Low and high end of the stack mapping this thread last sampled on
+#[thread_local]
+static mut STACK: (u64, u64) = (0, 0);
+
+pub(crate) unsafe fn read_raw(addr: usize, buf: &mut [u8]) -> bool {
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: addr as *mut c_void,
+        iov_len: buf.len(),
+    };
+    libc::process_vm_readv(libc::getpid(), &raw const local, 1, &raw const remote, 1, 0)
+        == buf.len() as isize
+}
+
+// Plain data only, the bytes are taken as they are
+pub(crate) unsafe fn read_val<T>(addr: usize) -> Option<T> {
+    let mut val = MaybeUninit::<T>::uninit();
+    let buf = std::slice::from_raw_parts_mut(val.as_mut_ptr().cast::<u8>(), size_of::<T>());
+    if read_raw(addr, buf) {
+        Some(val.assume_init())
+    } else {
+        None
+    }
+}
+
+#[inline(always)]
+pub(crate) fn frame_pointer() -> usize {
+    let fp: usize;
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        std::arch::asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        std::arch::asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        fp = 0;
+    }
+    fp
+}
+
+// Calls `f` with up to `max` return addresses from the chain starting at `fp`, the library is
+// built with frame pointers. Returns the number of frames walked.
+pub(crate) unsafe fn walk_frames(fp: usize, max: usize, f: impl FnMut(usize)) -> usize {
+    walk(fp, max, |fp| read_val::<[usize; 2]>(fp), f)
+}
+
+// The mapping holding `fp`, looked up again only when `fp` left it (first sample of a thread, a
+// main stack that grew down, a signal stack). Not `pthread_getattr_np`, which allocates with the
+// thread's lock held and may well be the caller.
+unsafe fn stack_bounds(fp: u64) -> (u64, u64) {
+    let (low, high) = STACK;
+    if low <= fp && fp < high {
+        return STACK;
+    }
+
+    STACK = (0, 0);
+    for_each_map(|line| {
+        if let Some(map) = parse_map(line)
+            && map.contains(fp)
+        {
+            STACK = (map.start, map.end);
+        }
+    });
+    STACK
+}
+
+// `walk_frames` over the calling thread's own stack without a syscall per frame. Every frame of
+// the chain lies between `fp` and the top of the stack, all of which is mapped.
+pub(crate) unsafe fn walk_own_frames(fp: usize, max: usize, f: impl FnMut(usize)) -> usize {
+    let (low, high) = stack_bounds(fp as u64);
+    let read = |fp: usize| {
+        (low <= fp as u64 && (fp + size_of::<[usize; 2]>()) as u64 <= high)
+            .then(|| (fp as *const [usize; 2]).read())
+    };
+    walk(fp, max, read, f)
+}
+
+unsafe fn walk(
+    mut fp: usize,
+    max: usize,
+    read: impl Fn(usize) -> Option<[usize; 2]>,
+    mut f: impl FnMut(usize),
+) -> usize {
+    let mut frames = 0;
+
+    while frames < max && fp != 0 && fp.is_multiple_of(size_of::<usize>()) {
+        let Some([next, ret]) = read(fp) else {
+            break;
+        };
+        if ret == 0 {
+            break;
+        }
+
+        f(ret);
+        frames += 1;
+
+        // The stack grows down, a caller's frame is always higher
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+    frames
+}
+
+// Calls `f` with every `r-xp` line of `/proc/self/maps`, without the newline
+pub(crate) unsafe fn for_each_exec_map(mut f: impl FnMut(&[u8])) {
+    for_each_map(|line| {
+        if line.windows(6).any(|w| w == b" r-xp ") {
+            f(line);
+        }
+    });
+}
+
+unsafe fn for_each_map(mut f: impl FnMut(&[u8])) {
+    let fd = libc::open(
+        c"/proc/self/maps".as_ptr(),
+        libc::O_RDONLY | libc::O_CLOEXEC,
+    );
+    if fd < 0 {
+        return;
+    }
+
+    let mut buf = [0u8; 4096];
+    let mut len = 0;
+    loop {
+        let n = libc::read(fd, buf[len..].as_mut_ptr().cast(), buf.len() - len);
+        if n <= 0 {
+            break;
+        }
+        len += n as usize;
+
+        let mut start = 0;
+        while let Some(end) = buf[start..len].iter().position(|&b| b == b'\n') {
+            f(&buf[start..start + end]);
+            start += end + 1;
+        }
+
+        // A line longer than the buffer is dropped
+        if start == 0 && len == buf.len() {
+            len = 0;
+        } else {
+            buf.copy_within(start..len, 0);
+            len -= start;
+        }
+    }
+
+    libc::close(fd);
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Map {
+    pub start: u64,
+    pub end: u64,
+    pub offset: u64,
+    name: [u8; NAME_MAX],
+    name_len: usize,
+}
+
+impl Map {
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+// `start-end perms offset dev inode path`
+fn parse_map(line: &[u8]) -> Option<Map> {
+    let hex = |field: &[u8]| -> Option<u64> {
+        let text = str::from_utf8(field).ok()?;
+        u64::from_str_radix(text, 16).ok()
+    };
+
+    let mut fields = line.splitn(6, |&b| b == b' ');
+    let range = fields.next()?;
+    let dash = range.iter().position(|&b| b == b'-')?;
+    let (start, end) = (hex(&range[..dash])?, hex(&range[dash + 1..])?);
+    let offset = hex(fields.nth(1)?)?;
+    let path = fields.nth(2).unwrap_or(&[]).trim_ascii();
+
+    let mut map = Map {
+        start,
+        end,
+        offset,
+        name: [0; NAME_MAX],
+        name_len: path.len().min(NAME_MAX),
+    };
+    map.name[..map.name_len].copy_from_slice(&path[..map.name_len]);
+    Some(map)
+}
+
+// Runs `f` on the parsed executable mappings (the first 256), kept in a scratch mmap. None when
+// there was no memory for it.
+pub(crate) unsafe fn with_exec_maps<R>(f: impl FnOnce(&[Map]) -> R) -> Option<R> {
+    let size = MAX_MAPS * size_of::<Map>();
+    let maps = mmap_memory(
+        null_mut(),
+        size,
+        MMapFlags {
+            prot: MProtFlags::READ | MProtFlags::WRITE,
+            map: MemoryFlags::PRIVATE | MemoryFlags::NORESERVE,
+        },
+    )
+    .ok()? as *mut Map;
+
+    let mut count = 0;
+    for_each_exec_map(|line| {
+        if count < MAX_MAPS
+            && let Some(map) = parse_map(line)
+        {
+            maps.add(count).write(map);
+            count += 1;
+        }
+    });
+
+    let ret = f(std::slice::from_raw_parts(maps, count));
+    let _ = unmap_memory(maps.cast(), size);
+    Some(ret)
+}
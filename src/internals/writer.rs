@@ -0,0 +1,68 @@
+use std::fmt;
+
+const BUF_SIZE: usize = 512;
+
+// Formats into a fixed stack buffer and hands full chunks to `sink`, so reporting code
+// never has to allocate from the allocator it is reporting on
+pub struct StackWriter<F: FnMut(&[u8])> {
+    buf: [u8; BUF_SIZE],
+    len: usize,
+    sink: F,
+}
+
+impl<F: FnMut(&[u8])> StackWriter<F> {
+    pub const fn new(sink: F) -> Self {
+        StackWriter {
+            buf: [0; BUF_SIZE],
+            len: 0,
+            sink,
+        }
+    }
+
+    // Raw bytes, for binary output
+    pub fn write_bytes(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.len == BUF_SIZE {
+                self.flush();
+            }
+
+            let n = bytes.len().min(BUF_SIZE - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.len > 0 {
+            (self.sink)(&self.buf[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+impl<F: FnMut(&[u8])> fmt::Write for StackWriter<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8])> Drop for StackWriter<F> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Sink for a raw file descriptor, short writes are retried and errors dropped
+pub fn fd_sink(fd: i32) -> impl FnMut(&[u8]) {
+    move |mut bytes: &[u8]| {
+        while !bytes.is_empty() {
+            let ret = unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) };
+            if ret <= 0 {
+                return;
+            }
+            bytes = &bytes[ret as usize..];
+        }
+    }
+}
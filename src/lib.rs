@@ -12,19 +12,33 @@
 )]
 #![feature(thread_local)]
 #![feature(likely_unlikely)]
+#![feature(linkage)]
 
-use std::{fmt::Debug, sync::atomic::AtomicUsize, time::Instant, usize};
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, AtomicUsize},
+    time::Instant,
+    usize,
+};
 
-use crate::internals::oncelock::OnceLock;
+use crate::{
+    abi::error::{ErrorPolicy, call_error_handler, error_policy},
+    internals::{crash::write_crash_report, oncelock::OnceLock},
+    trim::ReleasePolicy,
+};
 
 pub mod abi;
 pub mod big_allocation;
+pub mod big_cache;
 pub mod internals;
 pub mod slab;
 pub mod sys;
 pub mod trim;
 pub mod va;
 
+pub use abi::global_alloc::Oxidalloc;
+
+#[derive(Clone, Copy, Debug)]
 pub enum Err {
     OutOfReservation,
     OutOfMemory,
@@ -38,6 +52,14 @@ pub const EROCMEF: i32 = 41; // harmless let it stay
 pub const VERSION: u32 = 0xABA01;
 pub const OX_ALIGN_TAG: usize = usize::from_le_bytes(*b"OXIDALGN");
 pub const FLAG_ALIGNED: u8 = 2;
+pub const FLAG_THP: u8 = 4;
+// Big allocation served from a reused mapping, its pages are not zero
+pub const FLAG_DIRTY: u8 = 8;
+// A `PROT_NONE` page sits right before / after the big mapping
+pub const FLAG_GUARD_BEFORE: u8 = 16;
+pub const FLAG_GUARD_AFTER: u8 = 32;
+// Same limit as glibc, anything bigger cannot be indexed with `ptrdiff_t`
+pub const MAX_ALLOC_SIZE: usize = isize::MAX as usize;
 
 #[cfg(feature = "hardened-malloc")]
 pub static mut MAGIC: u64 = 0x01B01698BF0BEEF;
@@ -52,11 +74,23 @@ pub static mut FREED_MAGIC: u8 = 1;
 pub static OX_GLOBAL_STAMP: OnceLock<Instant> = OnceLock::new();
 pub static mut OX_CURRENT_STAMP: u32 = 0;
 
+// Bytes mapped for slabs
 pub static TOTAL_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+// Payload bytes carved out of slabs into blocks
 pub static TOTAL_IN_USE: AtomicUsize = AtomicUsize::new(0);
 pub static AVERAGE_BLOCK_TIMES_GLOBAL: AtomicUsize = AtomicUsize::new(3);
 pub static OX_TRIM_THRESHOLD: AtomicUsize = AtomicUsize::new(1024 * 1024 * 10);
-pub static mut OX_FORCE_THP: bool = false;
+// Flipped at runtime through `thp.force`
+pub static OX_FORCE_THP: AtomicBool = AtomicBool::new(false);
+pub static mut OX_BACKGROUND_THREAD: bool = true;
+pub static mut OX_RSEQ: bool = true;
+pub static mut OX_GUARD_PAGES: bool = cfg!(feature = "hardened-malloc");
+pub static mut OX_GUARD_BEFORE: bool = false;
+pub static mut OX_LEAK_REPORT: bool = false;
+// Set until boot so the call that boots goes through `trace_call`, `init_trace` decides it
+pub static mut OX_TRACE: bool = true;
+// Set once at boot, calloc relies on it not changing under live allocations
+pub static mut OX_RELEASE: ReleasePolicy = ReleasePolicy::DontNeed;
 pub static OX_MAX_RESERVATION: AtomicUsize = AtomicUsize::new(1024 * 1024 * 1024 * 16);
 
 pub fn get_clock() -> &'static Instant {
@@ -74,29 +108,36 @@ pub struct MetaData {
     pub start: usize,
     pub end: usize,
     pub next: usize,
+    pub owner: u16,
+    // Blocks GTrim has not found in the ICC during census `epoch`
+    pub live: u16,
+    pub epoch: u32,
 }
 
 #[derive(Debug, Clone)]
-#[cfg(not(feature = "hardened-linked-list"))]
+#[cfg(not(feature = "hardened-malloc"))]
 #[repr(C, align(16))]
 pub struct OxHeader {
     pub next: *mut OxHeader,
     pub class: u8,
     pub magic: u8,
+    pub owner: u16,
     pub life_time: u32,
 }
 
 #[derive(Debug, Clone)]
-#[cfg(feature = "hardened-linked-list")]
+#[cfg(feature = "hardened-malloc")]
 #[repr(C, align(16))]
 pub struct OxHeader {
     pub magic: u64,
     pub next: *mut OxHeader,
     pub class: u8,
+    pub owner: u16,
     pub life_time: u32,
 }
 
 #[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum OxidallocError {
     DoubleFree = 0x1000,
     MemoryCorruption = 0x1001,
@@ -113,6 +154,8 @@ pub enum OxidallocError {
     ICCFailedToInitialize = 0x100C,
 }
 
+pub const NUM_ERRORS: usize = 13;
+
 impl Debug for OxidallocError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -134,15 +177,77 @@ impl Debug for OxidallocError {
 }
 
 impl OxidallocError {
-    pub fn log_and_abort(&self, ptr: *mut std::ffi::c_void, extra: &str, errno: Option<i32>) -> ! {
+    pub const ALL: [Self; NUM_ERRORS] = [
+        Self::DoubleFree,
+        Self::MemoryCorruption,
+        Self::InvalidSize,
+        Self::OutOfMemory,
+        Self::VaBitmapExhausted,
+        Self::VAIinitFailed,
+        Self::PThreadCacheFailed,
+        Self::TooMuchQuarantine,
+        Self::DoubleQuarantine,
+        Self::ReservationExceeded,
+        Self::SecurityViolation,
+        Self::AttackOrCorruption,
+        Self::ICCFailedToInitialize,
+    ];
+
+    pub const fn index(self) -> usize {
+        self as usize - Self::DoubleFree as usize
+    }
+
+    // Name used by `ox_ctl("error.policy.<name>")`
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::DoubleFree => "double_free",
+            Self::MemoryCorruption => "memory_corruption",
+            Self::InvalidSize => "invalid_size",
+            Self::OutOfMemory => "out_of_memory",
+            Self::VaBitmapExhausted => "va_exhausted",
+            Self::VAIinitFailed => "va_init_failed",
+            Self::PThreadCacheFailed => "thread_cache_failed",
+            Self::TooMuchQuarantine => "too_much_quarantine",
+            Self::DoubleQuarantine => "double_quarantine",
+            Self::ReservationExceeded => "reservation_exceeded",
+            Self::SecurityViolation => "security_violation",
+            Self::AttackOrCorruption => "attack_or_corruption",
+            Self::ICCFailedToInitialize => "icc_init_failed",
+        }
+    }
+
+    fn log(&self, level: &str, ptr: *mut std::ffi::c_void, extra: &str, errno: Option<i32>) {
         if let Some(errno) = errno {
             eprintln!(
-                "[OXIDALLOC FATAL] {:?} at ptr={:p} | {} | errno({})",
-                self, ptr, extra, errno
+                "[OXIDALLOC {}] {:?} at ptr={:p} | {} | errno({})",
+                level, self, ptr, extra, errno
             );
         } else {
-            eprintln!("[OXIDALLOC FATAL] {:?} at ptr={:p} | {}", self, ptr, extra);
+            eprintln!(
+                "[OXIDALLOC {}] {:?} at ptr={:p} | {}",
+                level, self, ptr, extra
+            );
         }
+    }
+
+    // For errors nothing can recover from, the error handler still runs first
+    pub fn log_and_abort(&self, ptr: *mut std::ffi::c_void, extra: &str, errno: Option<i32>) -> ! {
+        call_error_handler(*self, ptr, extra);
+        self.log("FATAL", ptr, extra, errno);
+        unsafe { write_crash_report(*self, ptr as usize, extra, errno) };
         std::process::abort();
     }
+
+    // For errors the caller can back out of: returns unless the policy set for this error is
+    // `Abort`, the caller then fails the operation (NULL, ENOMEM, or a skipped free)
+    pub fn report(self, ptr: *mut std::ffi::c_void, extra: &str, errno: Option<i32>) {
+        match error_policy(self) {
+            ErrorPolicy::Abort => self.log_and_abort(ptr, extra, errno),
+            ErrorPolicy::ReturnNull => call_error_handler(self, ptr, extra),
+            ErrorPolicy::Continue => {
+                call_error_handler(self, ptr, extra);
+                self.log("ERROR", ptr, extra, errno);
+            }
+        }
+    }
 }
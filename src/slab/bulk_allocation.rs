@@ -1,20 +1,56 @@
 use std::{
     os::raw::c_void,
     ptr::{null_mut, write},
+    sync::atomic::Ordering,
 };
 
 use crate::{
-    Err, FREED_MAGIC, HEADER_SIZE, MetaData, OX_CURRENT_STAMP, OxHeader, OxidallocError,
+    Err, FREED_MAGIC, HEADER_SIZE, MetaData, OX_CURRENT_STAMP, OxHeader, TOTAL_ALLOCATED,
+    TOTAL_IN_USE,
     slab::{
-        ITERATIONS, NUM_SIZE_CLASSES, SIZE_CLASSES, TLS_MAX_BLOCKS, global::GlobalHandler,
-        thread_local::ThreadLocalEngine,
+        NUM_SIZE_CLASSES, SIZE_CLASSES, SLAB_ALIGN, TLS_MAX_BLOCKS, first_block_offset,
+        global::GlobalHandler, slab_size, thread_local::ThreadLocalEngine,
     },
     sys::memory_system::{MMapFlags, MProtFlags, MadviseFlags, MemoryFlags, madvise, mmap_memory},
-    va::{align_to, bitmap::VA_MAP},
+    va::{align_to, bitmap::VA_MAP, numa::bind_to_current_node},
 };
+#[cfg(feature = "hardened-malloc")]
+use crate::{
+    internals::once::Once,
+    va::{bootstrap::init_alloc_random, rng::Rng},
+};
+
+// Blocks carved together are linked in a random order within windows of this many, so the
+// layout of a fresh slab does not follow the order the blocks are handed out
+#[cfg(feature = "hardened-malloc")]
+const CARVE_WINDOW: usize = 64;
+
+#[cfg(feature = "hardened-malloc")]
+#[thread_local]
+static mut CARVE_RNG: Rng = Rng::new(0);
+#[cfg(feature = "hardened-malloc")]
+#[thread_local]
+static CARVE_RNG_ONCE: Once = Once::new();
+
+// Fisher-Yates over the first `len` slots of `window`
+#[cfg(feature = "hardened-malloc")]
+#[allow(unsafe_op_in_unsafe_fn)]
+unsafe fn shuffle_window(window: &mut [u8; CARVE_WINDOW], len: usize) {
+    CARVE_RNG_ONCE.call_once(|| {
+        CARVE_RNG = Rng::new(init_alloc_random());
+    });
+
+    for (i, slot) in window.iter_mut().enumerate().take(len) {
+        *slot = i as u8;
+    }
+    for i in (1..len).rev() {
+        let j = CARVE_RNG.next_usize() % (i + 1);
+        window.swap(i, j);
+    }
+}
 
 #[inline(always)]
-unsafe fn remaining_blocks(metadata: *mut MetaData, block_size: usize) -> usize {
+pub unsafe fn remaining_blocks(metadata: *mut MetaData, block_size: usize) -> usize {
     let remaining_bytes = (*metadata).end.saturating_sub((*metadata).next);
     remaining_bytes / block_size
 }
@@ -26,6 +62,7 @@ unsafe fn init_blocks(
     block_size: usize,
     max_blocks: usize,
     current_stamp: u32,
+    owner: u16,
 ) -> (*mut OxHeader, *mut OxHeader, usize) {
     let remaining = remaining_blocks(metadata, block_size);
     if remaining == 0 {
@@ -36,8 +73,19 @@ unsafe fn init_blocks(
     let base = (*metadata).next;
     let mut head = null_mut();
     let mut tail = null_mut();
+    #[cfg(feature = "hardened-malloc")]
+    let mut window = [0u8; CARVE_WINDOW];
 
     for i in (0..count).rev() {
+        // Walking backwards, a new window starts at the last block or at the top of a window
+        #[cfg(feature = "hardened-malloc")]
+        let i = {
+            let start = i - i % CARVE_WINDOW;
+            if i + 1 == count || i % CARVE_WINDOW == CARVE_WINDOW - 1 {
+                shuffle_window(&mut window, (count - start).min(CARVE_WINDOW));
+            }
+            start + window[i - start] as usize
+        };
         let current_header = (base + i * block_size) as *mut OxHeader;
 
         write(
@@ -46,6 +94,7 @@ unsafe fn init_blocks(
                 next: head,
                 class,
                 magic: FREED_MAGIC,
+                owner,
                 life_time: current_stamp,
             },
         );
@@ -57,6 +106,7 @@ unsafe fn init_blocks(
     }
 
     (*metadata).next = base + (count * block_size);
+    TOTAL_IN_USE.fetch_add(count * SIZE_CLASSES[class as usize], Ordering::Relaxed);
 
     (head, tail, count)
 }
@@ -65,7 +115,6 @@ unsafe fn init_blocks(
 pub unsafe fn bulk_fill(thread: &mut ThreadLocalEngine, class: usize) -> Result<(), Err> {
     let payload_size = SIZE_CLASSES[class];
     let block_size = align_to(payload_size + HEADER_SIZE, 16);
-    let num_blocks = ITERATIONS[class];
     let blocks_per_4k = 4096 / block_size;
     let max_init = if blocks_per_4k >= 48 {
         48
@@ -76,8 +125,14 @@ pub unsafe fn bulk_fill(thread: &mut ThreadLocalEngine, class: usize) -> Result<
 
     let pending = thread.pending[class];
     if !pending.is_null() {
-        let (head, tail, count) =
-            init_blocks(class as u8, pending, block_size, max_init, current_stamp);
+        let (head, tail, count) = init_blocks(
+            class as u8,
+            pending,
+            block_size,
+            max_init,
+            current_stamp,
+            thread.owner,
+        );
         if count > 0 {
             thread.push_to_thread_tailed(class, head, tail, count);
             if remaining_blocks(pending, block_size) == 0 {
@@ -88,15 +143,12 @@ pub unsafe fn bulk_fill(thread: &mut ThreadLocalEngine, class: usize) -> Result<
         thread.pending[class] = null_mut();
     }
 
-    let total = size_of::<MetaData>() + (block_size * num_blocks);
+    let first_block = first_block_offset(class);
+    let total = slab_size(class);
 
-    let hint = VA_MAP.alloc(total).unwrap_or_else(|| {
-        OxidallocError::VaBitmapExhausted.log_and_abort(
-            null_mut(),
-            "VA bitmap exhausted | This is expected",
-            None,
-        )
-    });
+    let Some(hint) = VA_MAP.alloc_aligned(total, SLAB_ALIGN[class]) else {
+        return Err(Err::OutOfReservation);
+    };
 
     let mem = mmap_memory(
         hint as *mut c_void,
@@ -110,6 +162,8 @@ pub unsafe fn bulk_fill(thread: &mut ThreadLocalEngine, class: usize) -> Result<
         VA_MAP.free(hint, total);
         Err::OutOfMemory
     })?;
+    bind_to_current_node(mem, total);
+    TOTAL_ALLOCATED.fetch_add(total, Ordering::Relaxed);
 
     let metadata = mem as *mut MetaData;
     write(
@@ -117,7 +171,10 @@ pub unsafe fn bulk_fill(thread: &mut ThreadLocalEngine, class: usize) -> Result<
         MetaData {
             start: mem as usize,
             end: (mem as usize) + total,
-            next: (mem as usize) + size_of::<MetaData>(),
+            next: (mem as usize) + first_block,
+            owner: thread.owner,
+            live: 0,
+            epoch: 0,
         },
     );
 
@@ -129,8 +186,14 @@ pub unsafe fn bulk_fill(thread: &mut ThreadLocalEngine, class: usize) -> Result<
         );
     }
 
-    let (head, tail, count) =
-        init_blocks(class as u8, metadata, block_size, max_init, current_stamp);
+    let (head, tail, count) = init_blocks(
+        class as u8,
+        metadata,
+        block_size,
+        max_init,
+        current_stamp,
+        thread.owner,
+    );
     if count == 0 {
         return Err(Err::OutOfMemory);
     }
@@ -163,8 +226,14 @@ pub unsafe fn drain_pending(thread: &mut ThreadLocalEngine, class: usize) {
     let remaining = remaining_blocks(pending, block_size);
 
     if remaining > 0 {
-        let (head, tail, count) =
-            init_blocks(class as u8, pending, block_size, remaining, current_stamp);
+        let (head, tail, count) = init_blocks(
+            class as u8,
+            pending,
+            block_size,
+            remaining,
+            current_stamp,
+            0,
+        );
         if count > 0 {
             GlobalHandler.push_to_global(class, head, tail, count);
         }
@@ -2,7 +2,6 @@ use crate::{OxHeader, slab::interconnect::ICC};
 
 // ----------------------------------
 
-#[cfg(feature = "hardened-linked-list")]
 pub(crate) unsafe fn reset_global_locks() {
     ICC.reset_on_fork();
 }
@@ -67,6 +66,7 @@ mod tests {
                         next: null_mut(),
                         class: class as u8,
                         magic: 0x42,
+                        owner: 0,
                         life_time: 0,
                     };
                     batch_size * 2
@@ -1,5 +1,7 @@
-// This implementation does not intended to be used with RSEQ
-// Not every kernel supports RSEQ, so we need to handle with sched_getcpu() for kernel compatibility
+// Per-CPU shards of batches
+// With rseq (see `rseq.rs`) a thread pushes to and pops from the shard of its CPU inside a
+// restartable critical section, steals lock the victim shard. Without it every shard is a
+// Treiber stack picked with sched_getcpu(), ABA is caught with a tag in the low bits of the head.
 
 use std::{
     hint::unlikely,
@@ -15,9 +17,21 @@ use crate::internals::lock::GlobalLock;
 use crate::{
     OxHeader, OxidallocError,
     internals::once::Once,
-    slab::{NUM_SIZE_CLASSES, thread_local::prefetch, xor_ptr_general},
-    sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, get_cpu_count, mmap_memory},
-    va::bootstrap::NUMA_KEY,
+    slab::{
+        NUM_SIZE_CLASSES,
+        rseq::{
+            RSEQ_ICC, ShardLock, current_rseq, init_rseq_icc, percpu_pop, percpu_push, rseq_cpu,
+        },
+        thread_local::prefetch,
+        xor_ptr_general,
+    },
+    sys::memory_system::{
+        MMapFlags, MProtFlags, MemoryFlags, get_cpu_count, get_cpu_span, mmap_memory,
+    },
+    va::{
+        bootstrap::NUMA_KEY,
+        numa::{NUMA_ACTIVE, cpu_node, node_stats, steal_order},
+    },
 };
 
 #[cfg(feature = "debug")]
@@ -78,6 +92,8 @@ pub struct InterConnectCache {
     pub usage: *mut [AtomicUsize; NUM_SIZE_CLASSES],
     #[cfg(feature = "hardened-linked-list")]
     pub locks: *mut GlobalLock,
+    // Only taken in rseq mode
+    pub shard_locks: *mut ShardLock,
     pub ncpu: usize,
     pub once: Once,
 }
@@ -109,6 +125,7 @@ impl InterConnectCache {
             usage: null_mut(),
             #[cfg(feature = "hardened-linked-list")]
             locks: null_mut(),
+            shard_locks: null_mut(),
             ncpu: 0,
             once: Once::new(),
         }
@@ -116,7 +133,13 @@ impl InterConnectCache {
 
     pub unsafe fn ensure_cache(&mut self) {
         self.once.call_once(|| {
-            let thread_count = get_cpu_count();
+            init_rseq_icc();
+            // A rseq shard belongs to exactly one CPU id, so ids are not folded there
+            let thread_count = if RSEQ_ICC {
+                get_cpu_span()
+            } else {
+                get_cpu_count()
+            };
             let list = mmap!(
                 null_mut(),
                 size_of::<[AtomicPtr<OxHeader>; NUM_SIZE_CLASSES]>() * thread_count
@@ -127,6 +150,7 @@ impl InterConnectCache {
             );
             #[cfg(feature = "hardened-linked-list")]
             let locks = mmap!(null_mut(), size_of::<GlobalLock>() * thread_count);
+            let shard_locks = mmap!(null_mut(), size_of::<ShardLock>() * thread_count);
 
             self.list = list as *mut [AtomicPtr<OxHeader>; NUM_SIZE_CLASSES];
             self.usage = usage as *mut [AtomicUsize; NUM_SIZE_CLASSES];
@@ -134,16 +158,18 @@ impl InterConnectCache {
             {
                 self.locks = locks as *mut GlobalLock
             };
+            self.shard_locks = shard_locks as *mut ShardLock;
             self.ncpu = thread_count;
         });
     }
 
+    // CPU ids can go past the CPU count (sparse ids, restricted affinity), fold them into a shard
     pub unsafe fn get_cpu_id(&self) -> Result<usize, i32> {
         let id = sched_getcpu();
         if unlikely(id < 0) {
             Err(id)
         } else {
-            Ok(id as usize)
+            Ok(id as usize % self.ncpu.max(1))
         }
     }
 
@@ -155,6 +181,11 @@ impl InterConnectCache {
         batch_size: usize,
     ) -> bool {
         self.ensure_cache();
+        if RSEQ_ICC {
+            self.push_rseq(class, head, tail, batch_size);
+            return true;
+        }
+
         let thread_id = self.get_cpu_id().unwrap_or(0);
         #[cfg(feature = "hardened-linked-list")]
         let lock = &*self.locks.add(thread_id);
@@ -192,6 +223,45 @@ impl InterConnectCache {
         true
     }
 
+    // Own shard inside a critical section, locked when this thread cannot run one
+    unsafe fn push_rseq(
+        &mut self,
+        class: usize,
+        head: *mut OxHeader,
+        tail: *mut OxHeader,
+        batch_size: usize,
+    ) {
+        let rseq = current_rseq();
+        while !rseq.is_null() {
+            let cpu = rseq_cpu(rseq);
+            if cpu >= self.ncpu {
+                break;
+            }
+
+            let lock = &*self.shard_locks.add(cpu);
+            if (*lock.as_ptr()).load(Ordering::Relaxed) {
+                break;
+            }
+
+            let list = &(*self.list.add(cpu))[class];
+            if percpu_push(rseq, cpu, lock.as_ptr(), list, head, tail) {
+                (*self.usage.add(cpu))[class].fetch_add(batch_size, Ordering::Relaxed);
+                return;
+            }
+        }
+
+        let shard = self.get_cpu_id().unwrap_or(0);
+        let lock = &*self.shard_locks.add(shard);
+        let list = &(*self.list.add(shard))[class];
+
+        lock.lock(shard);
+        (*tail).next = list.load(Ordering::Relaxed);
+        list.store(head, Ordering::Relaxed);
+        lock.unlock();
+
+        (*self.usage.add(shard))[class].fetch_add(batch_size, Ordering::Relaxed);
+    }
+
     pub unsafe fn get_size(&self, class: usize) -> usize {
         let mut total = 0;
         for i in 0..self.ncpu {
@@ -202,9 +272,18 @@ impl InterConnectCache {
     }
 
     pub unsafe fn try_pop(&mut self, class: usize, batch_size: usize) -> *mut OxHeader {
+        self.ensure_cache();
+        if RSEQ_ICC && let Some(block) = self.pop_rseq(class, batch_size) {
+            return block;
+        }
+
         let cpu = self.get_cpu_id().unwrap_or(0);
         let ncpu = self.ncpu;
 
+        if NUMA_ACTIVE {
+            return self.try_pop_numa(class, batch_size, cpu);
+        }
+
         if let Some(popped) = self.pop(class, batch_size, cpu) {
             return popped;
         }
@@ -220,6 +299,101 @@ impl InterConnectCache {
         null_mut()
     }
 
+    // Own shard first, then the rest of the node, then other nodes
+    #[cold]
+    unsafe fn try_pop_numa(
+        &mut self,
+        class: usize,
+        batch_size: usize,
+        cpu: usize,
+    ) -> *mut OxHeader {
+        let stats = node_stats(cpu_node(cpu));
+
+        for (victim, remote) in steal_order(cpu, self.ncpu, |cpu| cpu_node(cpu)) {
+            if let Some(block) = self.pop(class, batch_size, victim) {
+                if remote {
+                    stats.remote.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    stats.local.fetch_add(1, Ordering::Relaxed);
+                }
+                return block;
+            }
+        }
+
+        null_mut()
+    }
+
+    // Own shard inside a critical section, None sends the caller to the locked path
+    unsafe fn pop_rseq(&mut self, class: usize, batch_size: usize) -> Option<*mut OxHeader> {
+        let rseq = current_rseq();
+        if rseq.is_null() {
+            return None;
+        }
+
+        loop {
+            let cpu = rseq_cpu(rseq);
+            if cpu >= self.ncpu {
+                return None;
+            }
+
+            let usage = &(*self.usage.add(cpu))[class];
+            let lock = &*self.shard_locks.add(cpu);
+            if usage.load(Ordering::Relaxed) == 0 || (*lock.as_ptr()).load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let list = &(*self.list.add(cpu))[class];
+            let Some((head, tail, count)) = percpu_pop(rseq, cpu, lock.as_ptr(), list, batch_size)
+            else {
+                continue;
+            };
+            if count == 0 {
+                return None;
+            }
+
+            usage.fetch_sub(count, Ordering::Relaxed);
+            (*tail).next = null_mut();
+            if NUMA_ACTIVE {
+                node_stats(cpu_node(cpu))
+                    .local
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+
+            return Some(head);
+        }
+    }
+
+    unsafe fn pop_locked(
+        &mut self,
+        class: usize,
+        batch_size: usize,
+        shard: usize,
+    ) -> Option<*mut OxHeader> {
+        let lock = &*self.shard_locks.add(shard);
+        let list = &(*self.list.add(shard))[class];
+
+        lock.lock(shard);
+        let head = list.load(Ordering::Relaxed);
+        if head.is_null() {
+            lock.unlock();
+            return None;
+        }
+
+        let mut tail = head;
+        let mut count = 1;
+        while count < batch_size && !(*tail).next.is_null() {
+            tail = (*tail).next;
+            count += 1;
+        }
+        list.store((*tail).next, Ordering::Relaxed);
+        lock.unlock();
+
+        (*self.usage.add(shard))[class].fetch_sub(count, Ordering::Relaxed);
+        (*tail).next = null_mut();
+
+        Some(head)
+    }
+
     pub unsafe fn pop(
         &mut self,
         class: usize,
@@ -235,6 +409,10 @@ impl InterConnectCache {
             return None;
         }
 
+        if RSEQ_ICC {
+            return self.pop_locked(class, batch_size, thread_id);
+        }
+
         #[cfg(feature = "debug")]
         INTER.fetch_add(1, Ordering::Relaxed);
         let list = &*self.list.add(thread_id);
@@ -304,15 +482,15 @@ impl InterConnectCache {
         }
     }
 
-    #[cfg(feature = "hardened-linked-list")]
     pub unsafe fn reset_on_fork(&mut self) {
-        if self.locks.is_null() {
+        if self.shard_locks.is_null() {
             return;
         }
 
         for i in 0..self.ncpu {
-            let lock = &*self.locks.add(i);
-            lock.reset_on_fork();
+            (*self.shard_locks.add(i)).reset_on_fork();
+            #[cfg(feature = "hardened-linked-list")]
+            (*self.locks.add(i)).reset_on_fork();
         }
     }
 }
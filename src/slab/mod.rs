@@ -1,11 +1,17 @@
 use std::hint::unlikely;
 
-use crate::{HEADER_SIZE, OxHeader, internals::oncelock::OnceLock, va::align_to};
+use crate::{
+    HEADER_SIZE, MetaData, OxHeader,
+    internals::oncelock::OnceLock,
+    va::{align_to, bitmap::BLOCK_SIZE},
+};
 
 pub mod bulk_allocation;
 pub mod global;
 pub mod interconnect;
 pub mod quarantine;
+pub mod remote;
+pub mod rseq;
 pub mod thread_local;
 
 pub const SIZE_CLASSES: [usize; 34] = [
@@ -42,6 +48,9 @@ pub const ITERATIONS: [usize; 34] = [
 const TLS_BIG_CLASS_BYTES: usize = 1024 * 64;
 const TLS_MEDIUM_CLASS_BYTES: usize = 1024 * 96;
 const TLS_SMALL_CLASS_BYTES: usize = 1024 * 128;
+// Per-CPU mode keeps only a small front cache per thread, the CPU's ICC shard holds the rest
+const TLS_FRONT_CLASS_BYTES: usize = 1024 * 4;
+const TLS_FRONT_MAX_BLOCKS: usize = 32;
 pub const TLS_MAX_BLOCKS: [usize; NUM_SIZE_CLASSES] = {
     let mut arr = [0; NUM_SIZE_CLASSES];
     let mut i = 0;
@@ -51,6 +60,13 @@ pub const TLS_MAX_BLOCKS: [usize; NUM_SIZE_CLASSES] = {
         let block_size = align_to(payload + HEADER_SIZE, 16);
         let mut blocks = if block_size > 1024 * 1024 * 16 {
             0
+        } else if cfg!(feature = "experimental-cpu-local-global") {
+            let front = TLS_FRONT_CLASS_BYTES / block_size;
+            if front > TLS_FRONT_MAX_BLOCKS {
+                TLS_FRONT_MAX_BLOCKS
+            } else {
+                front
+            }
         } else {
             if payload < 256 {
                 TLS_SMALL_CLASS_BYTES / block_size
@@ -72,6 +88,51 @@ pub const TLS_MAX_BLOCKS: [usize; NUM_SIZE_CLASSES] = {
     arr
 };
 
+// Bytes mapped for one slab of a class, metadata included
+pub const fn slab_size(class: usize) -> usize {
+    first_block_offset(class) + align_to(SIZE_CLASSES[class] + HEADER_SIZE, 16) * ITERATIONS[class]
+}
+
+// Multi block slabs start on a power of two past their size, so any block finds its `MetaData` by
+// masking. Single block slabs are resized in place by realloc and are never looked up.
+pub const SLAB_ALIGN: [usize; NUM_SIZE_CLASSES] = {
+    let mut arr = [0; NUM_SIZE_CLASSES];
+    let mut i = 0;
+
+    while i < NUM_SIZE_CLASSES {
+        arr[i] = if ITERATIONS[i] == 1 {
+            BLOCK_SIZE
+        } else {
+            slab_size(i).next_power_of_two()
+        };
+        i += 1;
+    }
+
+    arr
+};
+
+// Payload alignment every block of a class is guaranteed to have.
+// Multi block slabs get it from the stride, single block slabs can put their only payload on
+// any boundary the payload size is a multiple of without costing an extra page.
+pub const CLASS_ALIGN: [usize; NUM_SIZE_CLASSES] = {
+    let mut arr = [0; NUM_SIZE_CLASSES];
+    let mut i = 0;
+
+    while i < NUM_SIZE_CLASSES {
+        let stride = if ITERATIONS[i] == 1 {
+            SIZE_CLASSES[i]
+        } else {
+            align_to(SIZE_CLASSES[i] + HEADER_SIZE, 16)
+        };
+
+        let align = 1 << stride.trailing_zeros();
+        arr[i] = if align > 4096 { 4096 } else { align };
+        i += 1;
+    }
+
+    arr
+};
+
 pub static SIZE_LUT: [u8; 256] = {
     let mut lut = [0u8; 256];
     let mut i = 0;
@@ -112,6 +173,31 @@ pub fn match_size_class(size: usize) -> Option<usize> {
     slow_path_match(size)
 }
 
+// Offset of the first header inside a fresh slab, so the first payload lands on `CLASS_ALIGN`
+#[inline(always)]
+pub const fn first_block_offset(class: usize) -> usize {
+    align_to(size_of::<MetaData>() + HEADER_SIZE, CLASS_ALIGN[class]) - HEADER_SIZE
+}
+
+#[inline(always)]
+pub fn slab_of(header: *mut OxHeader, class: usize) -> *mut MetaData {
+    ((header as usize) & !(SLAB_ALIGN[class] - 1)) as *mut MetaData
+}
+
+#[inline(always)]
+pub fn is_class_aligned(addr: usize, class: usize) -> bool {
+    addr & (CLASS_ALIGN[class] - 1) == 0
+}
+
+// Smallest class that fits `size` and natively meets `align`. Classes bigger than the one a
+// tagged `fallback_size` request would use are skipped, native alignment must never cost more.
+pub fn match_aligned_class(size: usize, align: usize, fallback_size: usize) -> Option<usize> {
+    let first = match_size_class(size)?;
+    let limit = match_size_class(fallback_size).unwrap_or(NUM_SIZE_CLASSES - 1);
+
+    (first..=limit).find(|&class| CLASS_ALIGN[class] >= align)
+}
+
 #[inline(always)]
 fn slow_path_match(size: usize) -> Option<usize> {
     for i in 0..NUM_SIZE_CLASSES {
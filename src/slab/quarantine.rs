@@ -1,39 +1,123 @@
-use std::{
-    ptr::null_mut,
-    sync::atomic::{AtomicUsize, Ordering},
+// Use-after-free quarantine for hardened builds
+// Freed small blocks wait in a per-thread FIFO before they go back to a bin. The payload is
+// poisoned on the way in and checked on the way out, so a write through a dangling pointer is
+// caught before the block has a new owner. The FIFO is bounded by slots and by payload bytes,
+// classes bigger than the byte budget skip it.
+
+use std::{fmt::Write, os::raw::c_void};
+
+use crate::{
+    HEADER_SIZE, OxHeader, OxidallocError,
+    internals::writer::StackWriter,
+    slab::{NUM_SIZE_CLASSES, SIZE_CLASSES},
 };
 
-use crate::{OxidallocError, va::bootstrap::GLOBAL_RANDOM};
+pub const QUARANTINE_SLOTS: usize = 1024;
+pub const QUARANTINE_BYTES: usize = 512 * 1024;
+pub const POISON: u8 = 0xDB;
 
-pub static MAX_QUARANTINE: usize = 1024;
+pub struct Quarantine {
+    slots: [*mut OxHeader; QUARANTINE_SLOTS],
+    // Index of the oldest block
+    head: usize,
+    len: usize,
+    bytes: usize,
+    held: [usize; NUM_SIZE_CLASSES],
+}
 
-pub static QUARANTINE: AtomicUsize = AtomicUsize::new(0);
-pub static TOTAL_QUARANTINED: AtomicUsize = AtomicUsize::new(0);
+impl Quarantine {
+    pub const fn new() -> Self {
+        Quarantine {
+            slots: [std::ptr::null_mut(); QUARANTINE_SLOTS],
+            head: 0,
+            len: 0,
+            bytes: 0,
+            held: [0; NUM_SIZE_CLASSES],
+        }
+    }
 
-#[cold]
-#[allow(unsafe_op_in_unsafe_fn)]
-pub unsafe fn quarantine(ptr: usize) -> bool {
-    let ptr = ptr ^ GLOBAL_RANDOM;
-    let guard = QUARANTINE.load(Ordering::Relaxed);
-    TOTAL_QUARANTINED.fetch_add(1, Ordering::Relaxed);
-
-    if guard == ptr {
-        OxidallocError::DoubleQuarantine.log_and_abort(
-            null_mut(),
-            "Double quarantine detected, aborting process",
-            None,
-        )
+    #[inline(always)]
+    pub fn admits(class: usize) -> bool {
+        SIZE_CLASSES[class] <= QUARANTINE_BYTES
+    }
+
+    // Blocks of `class` waiting here, counted as free by the stats
+    pub const fn held(&self, class: usize) -> usize {
+        self.held[class]
+    }
+
+    // Oldest block if there is no room for another block of `class`
+    #[inline(always)]
+    pub unsafe fn evict_for(&mut self, class: usize) -> Option<*mut OxHeader> {
+        if self.len < QUARANTINE_SLOTS && self.bytes + SIZE_CLASSES[class] <= QUARANTINE_BYTES {
+            return None;
+        }
+
+        self.pop()
+    }
+
+    // Caller makes room with `evict_for` first
+    #[inline(always)]
+    pub unsafe fn push(&mut self, header: *mut OxHeader, class: usize) {
+        let size = SIZE_CLASSES[class];
+        payload(header).write_bytes(POISON, size);
+
+        self.slots[(self.head + self.len) % QUARANTINE_SLOTS] = header;
+        self.len += 1;
+        self.bytes += size;
+        self.held[class] += 1;
     }
 
-    if TOTAL_QUARANTINED.load(Ordering::Relaxed) < MAX_QUARANTINE {
-        QUARANTINE.swap(ptr, Ordering::AcqRel);
-    } else {
-        OxidallocError::TooMuchQuarantine.log_and_abort(
-            null_mut(),
-            "Too much quarantine detected, aborting process",
-            None,
-        )
+    // Takes the oldest block out after checking its poison. A block written after free is
+    // reported and leaked when the error policy lets the process go on.
+    pub unsafe fn pop(&mut self) -> Option<*mut OxHeader> {
+        while self.len > 0 {
+            let header = self.slots[self.head];
+            self.head = (self.head + 1) % QUARANTINE_SLOTS;
+            self.len -= 1;
+
+            let class = (*header).class as usize;
+            let size = SIZE_CLASSES[class];
+            self.bytes -= size;
+            self.held[class] -= 1;
+
+            let bytes = std::slice::from_raw_parts(payload(header), size);
+            match bytes.iter().position(|&b| b != POISON) {
+                Some(offset) => report_write_after_free(header, class, offset),
+                None => return Some(header),
+            }
+        }
+
+        None
+    }
+}
+
+#[inline(always)]
+unsafe fn payload(header: *mut OxHeader) -> *mut u8 {
+    (header as *mut u8).add(HEADER_SIZE)
+}
+
+#[cold]
+#[inline(never)]
+unsafe fn report_write_after_free(header: *mut OxHeader, class: usize, offset: usize) {
+    let mut msg = [0u8; 128];
+    let mut len = 0;
+    {
+        let mut out = StackWriter::new(|bytes: &[u8]| {
+            let n = bytes.len().min(msg.len() - len);
+            msg[len..len + n].copy_from_slice(&bytes[..n]);
+            len += n;
+        });
+        let _ = write!(
+            out,
+            "Write after free to a quarantined block: class {class} ({} bytes), offset {offset}",
+            SIZE_CLASSES[class]
+        );
     }
 
-    false
+    OxidallocError::MemoryCorruption.report(
+        payload(header) as *mut c_void,
+        str::from_utf8(&msg[..len]).unwrap_or("Write after free to a quarantined block"),
+        None,
+    );
 }
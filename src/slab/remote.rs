@@ -0,0 +1,148 @@
+// Remote free queues
+// Blocks remember the thread that allocated them (`OxHeader::owner`). When another thread frees
+// such a block it is pushed onto the owner's remote list instead of the freeing thread's cache,
+// the owner takes the whole list back with a single swap on its next `try_fill`.
+
+use std::{
+    hint::unlikely,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::{
+    OxHeader,
+    slab::{
+        TLS_MAX_BLOCKS, global::GlobalHandler, thread_local::ThreadLocalEngine, xor_ptr_general,
+    },
+    va::bootstrap::NUMA_KEY,
+};
+
+// Slot 0 means "no owner", threads past the limit simply never receive remote frees
+pub const MAX_REMOTE_OWNERS: usize = 4096;
+
+static OWNER_ALIVE: [AtomicBool; MAX_REMOTE_OWNERS] =
+    [const { AtomicBool::new(false) }; MAX_REMOTE_OWNERS];
+static REMOTE_LISTS: [AtomicPtr<OxHeader>; MAX_REMOTE_OWNERS] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_REMOTE_OWNERS];
+static OWNER_HINT: AtomicUsize = AtomicUsize::new(1);
+
+// Blocks freed by a thread other than their owner
+pub static REMOTE_FREES: AtomicUsize = AtomicUsize::new(0);
+// Blocks taken back by their owner
+pub static REMOTE_DRAINED: AtomicUsize = AtomicUsize::new(0);
+// Non-empty remote lists taken back by their owner
+pub static REMOTE_DRAINS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn acquire_owner_id() -> u16 {
+    let start = OWNER_HINT.load(Ordering::Relaxed);
+
+    for i in 0..MAX_REMOTE_OWNERS - 1 {
+        let id = 1 + (start - 1 + i) % (MAX_REMOTE_OWNERS - 1);
+        if OWNER_ALIVE[id]
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            OWNER_HINT.store(id + 1, Ordering::Relaxed);
+            return id as u16;
+        }
+    }
+
+    0
+}
+
+#[inline(always)]
+pub fn owner_alive(id: usize) -> bool {
+    OWNER_ALIVE[id].load(Ordering::Relaxed)
+}
+
+// Anything pushed after the final drain stays on the list until the slot is reused
+pub unsafe fn release_owner_id(id: u16) {
+    if id == 0 {
+        return;
+    }
+
+    OWNER_ALIVE[id as usize].store(false, Ordering::Release);
+    flush_to_global(id);
+}
+
+#[inline(always)]
+pub unsafe fn push_remote(owner: u16, header: *mut OxHeader) -> bool {
+    let owner = owner as usize;
+    if unlikely(owner >= MAX_REMOTE_OWNERS || !OWNER_ALIVE[owner].load(Ordering::Acquire)) {
+        return false;
+    }
+
+    let list = &REMOTE_LISTS[owner];
+    let mut current = list.load(Ordering::Relaxed);
+    loop {
+        (*header).next = current;
+
+        match list.compare_exchange_weak(
+            current,
+            xor_ptr_general(header, NUMA_KEY),
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+
+    REMOTE_FREES.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+#[inline(always)]
+unsafe fn take_list(id: u16) -> *mut OxHeader {
+    if REMOTE_LISTS[id as usize].load(Ordering::Relaxed).is_null() {
+        return null_mut();
+    }
+
+    let list = REMOTE_LISTS[id as usize].swap(null_mut(), Ordering::Acquire);
+    xor_ptr_general(list, NUMA_KEY)
+}
+
+// Move the owner's remote list into its own bins, overflow goes to the ICC
+pub unsafe fn drain_remote(thread: &mut ThreadLocalEngine) -> usize {
+    let mut block = take_list(thread.owner);
+    if block.is_null() {
+        return 0;
+    }
+
+    let mut count = 0;
+    while !block.is_null() {
+        let next = xor_ptr_general((*block).next, NUMA_KEY);
+        let class = (*block).class as usize;
+
+        if thread.tls[class].usage >= TLS_MAX_BLOCKS[class] {
+            GlobalHandler.push_to_global(class, block, block, 1);
+        } else {
+            thread.push_to_thread(class, block);
+        }
+
+        block = next;
+        count += 1;
+    }
+
+    REMOTE_DRAINS.fetch_add(1, Ordering::Relaxed);
+    REMOTE_DRAINED.fetch_add(count, Ordering::Relaxed);
+    count
+}
+
+unsafe fn flush_to_global(id: u16) {
+    let mut block = take_list(id);
+    while !block.is_null() {
+        let next = xor_ptr_general((*block).next, NUMA_KEY);
+        GlobalHandler.push_to_global((*block).class as usize, block, block, 1);
+        block = next;
+    }
+}
+
+// Only the forking thread survives, every other slot is dead in the child
+pub(crate) unsafe fn reset_fork_owners(current: u16) {
+    for (id, alive) in OWNER_ALIVE.iter().enumerate().skip(1) {
+        if id != current as usize && alive.load(Ordering::Relaxed) {
+            release_owner_id(id as u16);
+        }
+    }
+}
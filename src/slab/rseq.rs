@@ -0,0 +1,315 @@
+// Restartable sequences for the ICC
+// Every thread gets an rseq area in `init_tls`: glibc's when it registered one (2.35+), our own
+// otherwise. The kernel restarts a critical section on preemption, migration or signal delivery,
+// so a section that only touches the shard of the CPU it runs on can use plain loads and stores.
+// Everything else touching a shard (steals, threads without rseq) takes the shard lock and fences
+// the shard's CPU with membarrier, which restarts a section already running there.
+// The sections only exist on x86_64, other targets and hardened lists keep the CAS path.
+
+use std::{
+    hint::spin_loop,
+    os::raw::c_void,
+    ptr::{null_mut, read_volatile},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+use crate::{
+    OX_RSEQ, OxHeader, OxidallocError,
+    slab::thread_local::TLS,
+    sys::memory_system::{reg_rseq, reg_rseq_fence, rseq_fence},
+};
+
+pub const RSEQ_CAPABLE: bool = cfg!(all(
+    target_arch = "x86_64",
+    not(feature = "hardened-linked-list")
+));
+// Also hardcoded in front of every abort handler below
+pub const RSEQ_SIG: u32 = 0x5305_3053;
+
+// ICC shards are driven by rseq, decided once when the ICC is set up
+pub static mut RSEQ_ICC: bool = false;
+
+// `struct rseq` from the kernel ABI
+#[repr(C, align(32))]
+pub struct Rseq {
+    pub cpu_id_start: u32,
+    pub cpu_id: u32,
+    pub rseq_cs: u64,
+    pub flags: u32,
+    pub node_id: u32,
+    pub mm_cid: u32,
+}
+
+#[thread_local]
+static mut RSEQ_AREA: Rseq = Rseq {
+    cpu_id_start: 0,
+    // RSEQ_CPU_ID_UNINITIALIZED
+    cpu_id: u32::MAX,
+    rseq_cs: 0,
+    flags: 0,
+    node_id: 0,
+    mm_cid: 0,
+};
+
+// Set by glibc when it registered the area itself, absent on other libcs
+#[cfg(target_arch = "x86_64")]
+unsafe extern "C" {
+    #[linkage = "extern_weak"]
+    static __rseq_offset: *const isize;
+    #[linkage = "extern_weak"]
+    static __rseq_size: *const u32;
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn glibc_area() -> *mut Rseq {
+    if __rseq_size.is_null() || __rseq_offset.is_null() || *__rseq_size == 0 {
+        return null_mut();
+    }
+
+    let tp: usize;
+    core::arch::asm!(
+        "mov {}, qword ptr fs:[0]",
+        out(reg) tp,
+        options(nostack, readonly, preserves_flags)
+    );
+
+    tp.wrapping_add_signed(*__rseq_offset) as *mut Rseq
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn glibc_area() -> *mut Rseq {
+    null_mut()
+}
+
+// Area of the calling thread, null when rseq is unusable
+pub unsafe fn register_thread() -> *mut Rseq {
+    if !RSEQ_CAPABLE {
+        return null_mut();
+    }
+
+    let mut area = glibc_area();
+    if area.is_null()
+        && reg_rseq(
+            (&raw mut RSEQ_AREA).cast::<c_void>(),
+            size_of::<Rseq>(),
+            RSEQ_SIG,
+        )
+        .is_ok()
+    {
+        area = &raw mut RSEQ_AREA;
+    }
+
+    // The kernel fills in a valid CPU once the area is registered
+    if area.is_null() || read_volatile(&raw const (*area).cpu_id).cast_signed() < 0 {
+        return null_mut();
+    }
+
+    area
+}
+
+#[inline(always)]
+pub unsafe fn current_rseq() -> *mut Rseq {
+    let tls = TLS;
+    if tls.is_null() {
+        null_mut()
+    } else {
+        (*tls).rseq
+    }
+}
+
+// Needs rseq on the calling thread and a kernel that can fence it (4.18+), seccomp may block either
+pub unsafe fn init_rseq_icc() {
+    if !RSEQ_CAPABLE || !OX_RSEQ {
+        return;
+    }
+
+    let rseq = current_rseq();
+    RSEQ_ICC = !rseq.is_null() && reg_rseq_fence().is_ok() && rseq_fence(rseq_cpu(rseq)).is_ok();
+}
+
+#[inline(always)]
+pub unsafe fn rseq_cpu(rseq: *mut Rseq) -> usize {
+    read_volatile(&raw const (*rseq).cpu_id) as usize
+}
+
+#[repr(C, align(64))]
+pub struct ShardLock(AtomicBool);
+
+impl ShardLock {
+    // Also waits out any critical section running on `cpu`
+    pub unsafe fn lock(&self, cpu: usize) {
+        while self
+            .0
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+
+        if let Err(errno) = rseq_fence(cpu) {
+            OxidallocError::ICCFailedToInitialize.log_and_abort(
+                null_mut() as *mut c_void,
+                "membarrier failed on a registered process",
+                Some(errno),
+            );
+        }
+    }
+
+    #[inline(always)]
+    pub fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+
+    #[inline(always)]
+    pub const fn as_ptr(&self) -> *const AtomicBool {
+        &raw const self.0
+    }
+
+    pub fn reset_on_fork(&self) {
+        self.unlock();
+    }
+}
+
+// Links `new_head..=tail` in front of `head` if the thread is still on `cpu` and the shard is not
+// locked. False when the section was aborted.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub unsafe fn percpu_push(
+    rseq: *mut Rseq,
+    cpu: usize,
+    lock: *const AtomicBool,
+    head: *const AtomicPtr<OxHeader>,
+    new_head: *mut OxHeader,
+    tail: *mut OxHeader,
+) -> bool {
+    let done: usize;
+    core::arch::asm!(
+        ".pushsection __rseq_cs, \"aw\"",
+        ".balign 32",
+        "9:",
+        ".long 0, 0",
+        ".quad 2f, 3f - 2f, 4f",
+        ".popsection",
+        "lea {tmp}, [rip + 9b]",
+        "mov qword ptr [{rseq} + 8], {tmp}",
+        "2:",
+        "cmp dword ptr [{rseq} + 4], {cpu:e}",
+        "jne 4f",
+        "cmp byte ptr [{lock}], 0",
+        "jne 4f",
+        "mov {tmp}, qword ptr [{head}]",
+        "mov qword ptr [{tail} + {next}], {tmp}",
+        "mov qword ptr [{head}], {new_head}",
+        "3:",
+        "mov {done:e}, 1",
+        "jmp 5f",
+        ".long 0x53053053",
+        "4:",
+        "xor {done:e}, {done:e}",
+        "5:",
+        rseq = in(reg) rseq,
+        cpu = in(reg) cpu,
+        lock = in(reg) lock,
+        head = in(reg) head,
+        new_head = in(reg) new_head,
+        tail = in(reg) tail,
+        next = const std::mem::offset_of!(OxHeader, next),
+        tmp = out(reg) _,
+        done = out(reg) done,
+        options(nostack),
+    );
+
+    done != 0
+}
+
+// Detaches up to `batch` blocks from `head` if the thread is still on `cpu` and the shard is not
+// locked. Returns (first, last, count), count 0 when the shard is empty, None when aborted.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub unsafe fn percpu_pop(
+    rseq: *mut Rseq,
+    cpu: usize,
+    lock: *const AtomicBool,
+    head: *const AtomicPtr<OxHeader>,
+    batch: usize,
+) -> Option<(*mut OxHeader, *mut OxHeader, usize)> {
+    let first: *mut OxHeader;
+    let last: *mut OxHeader;
+    let count: usize;
+    core::arch::asm!(
+        ".pushsection __rseq_cs, \"aw\"",
+        ".balign 32",
+        "9:",
+        ".long 0, 0",
+        ".quad 2f, 3f - 2f, 4f",
+        ".popsection",
+        "lea {tmp}, [rip + 9b]",
+        "mov qword ptr [{rseq} + 8], {tmp}",
+        "2:",
+        "cmp dword ptr [{rseq} + 4], {cpu:e}",
+        "jne 4f",
+        "cmp byte ptr [{lock}], 0",
+        "jne 4f",
+        "xor {count:e}, {count:e}",
+        "mov {first}, qword ptr [{head}]",
+        "mov {last}, {first}",
+        "test {first}, {first}",
+        "jz 3f",
+        "inc {count}",
+        "6:",
+        "cmp {count}, {batch}",
+        "jae 7f",
+        "mov {tmp}, qword ptr [{last} + {next}]",
+        "test {tmp}, {tmp}",
+        "jz 7f",
+        "mov {last}, {tmp}",
+        "inc {count}",
+        "jmp 6b",
+        "7:",
+        "mov {tmp}, qword ptr [{last} + {next}]",
+        "mov qword ptr [{head}], {tmp}",
+        "3:",
+        "jmp 5f",
+        ".long 0x53053053",
+        "4:",
+        "mov {count}, -1",
+        "5:",
+        rseq = in(reg) rseq,
+        cpu = in(reg) cpu,
+        lock = in(reg) lock,
+        head = in(reg) head,
+        batch = in(reg) batch,
+        next = const std::mem::offset_of!(OxHeader, next),
+        tmp = out(reg) _,
+        first = out(reg) first,
+        last = out(reg) last,
+        count = out(reg) count,
+        options(nostack),
+    );
+
+    (count != usize::MAX).then_some((first, last, count))
+}
+
+// Never reached, `RSEQ_ICC` stays false without the x86_64 sections
+#[cfg(not(target_arch = "x86_64"))]
+pub unsafe fn percpu_push(
+    _: *mut Rseq,
+    _: usize,
+    _: *const AtomicBool,
+    _: *const AtomicPtr<OxHeader>,
+    _: *mut OxHeader,
+    _: *mut OxHeader,
+) -> bool {
+    false
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub unsafe fn percpu_pop(
+    _: *mut Rseq,
+    _: usize,
+    _: *const AtomicBool,
+    _: *const AtomicPtr<OxHeader>,
+    _: usize,
+) -> Option<(*mut OxHeader, *mut OxHeader, usize)> {
+    None
+}
@@ -9,12 +9,20 @@ use std::{
 use crate::sys::memory_system::getrandom;
 use crate::{
     MetaData, OxHeader, OxidallocError,
+    internals::trace::trace_thread_exit,
     slab::{
-        NUM_SIZE_CLASSES, bulk_allocation::drain_pending, global::GlobalHandler, xor_ptr_general,
+        NUM_SIZE_CLASSES,
+        bulk_allocation::drain_pending,
+        global::GlobalHandler,
+        remote::{acquire_owner_id, release_owner_id},
+        rseq::{Rseq, register_thread},
+        xor_ptr_general,
     },
     sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, mmap_memory, unmap_memory},
     va::is_ours,
 };
+#[cfg(feature = "hardened-malloc")]
+use crate::{abi::free::release_block, slab::quarantine::Quarantine};
 
 #[cfg(target_arch = "x86_64")]
 #[inline(always)]
@@ -32,7 +40,10 @@ struct CleanupHandler;
 
 impl Drop for CleanupHandler {
     fn drop(&mut self) {
-        unsafe { cleanup_thread_cache(TLS) };
+        unsafe {
+            trace_thread_exit();
+            cleanup_thread_cache(TLS);
+        };
     }
 }
 
@@ -46,8 +57,13 @@ pub struct TlsBin {
 pub struct ThreadLocalEngine {
     pub tls: [TlsBin; NUM_SIZE_CLASSES],
     pub pending: [*mut MetaData; NUM_SIZE_CLASSES],
+    pub owner: u16,
+    // Null when the ICC cannot use rseq from this thread
+    pub rseq: *mut Rseq,
     #[cfg(feature = "hardened-linked-list")]
     pub xor_key: usize,
+    #[cfg(feature = "hardened-malloc")]
+    pub quarantine: Quarantine,
 }
 
 #[thread_local]
@@ -127,8 +143,12 @@ impl ThreadLocalEngine {
                     }
                 }; NUM_SIZE_CLASSES],
                 pending: [const { null_mut() }; NUM_SIZE_CLASSES],
+                owner: acquire_owner_id(),
+                rseq: register_thread(),
                 #[cfg(feature = "hardened-linked-list")]
                 xor_key: rand_s,
+                #[cfg(feature = "hardened-malloc")]
+                quarantine: Quarantine::new(),
             },
         );
 
@@ -218,55 +238,88 @@ impl ThreadLocalEngine {
         self.tls[class].head = self.xor_ptr(head);
         self.tls[class].usage += batch_size;
     }
-}
 
-unsafe fn cleanup_thread_cache(cache: *mut ThreadLocalEngine) {
-    TLS = null_mut();
-    if cache.is_null() {
-        return;
+    // A full front cache hands `header` and half of the bin to the CPU shard in one batch
+    pub unsafe fn spill_to_cpu(&mut self, class: usize, header: *mut OxHeader) {
+        let spill = self.tls[class].usage / 2;
+        let mut head = header;
+        (*header).next = null_mut();
+
+        for _ in 0..spill {
+            let block = self.pop_from_thread(class);
+            (*block).next = head;
+            head = block;
+        }
+
+        GlobalHandler.push_to_global(class, head, header, spill + 1);
     }
+}
 
+// Hand one bin back to the ICC, returns the number of blocks. They are stamped as expired so
+// GTrim may release them right away.
+pub unsafe fn flush_bin(cache: &mut ThreadLocalEngine, class: usize) -> usize {
     #[cfg(feature = "hardened-linked-list")]
-    let random_key = (*cache).xor_key;
+    let random_key = cache.xor_key;
     #[cfg(not(feature = "hardened-linked-list"))]
     let random_key = 0;
 
-    for class in 0..NUM_SIZE_CLASSES {
-        let head = xor_ptr_general((*cache).tls[class].head, random_key);
+    let head = xor_ptr_general(cache.tls[class].head, random_key);
+    cache.tls[class].head = null_mut();
+    cache.tls[class].usage = 0;
 
-        if !is_ours(head as usize) {
-            continue;
-        }
+    if head.is_null() || !is_ours(head as usize) {
+        return 0;
+    }
 
-        if !head.is_null() {
-            let mut tail = head;
-            let mut count = 1;
-            loop {
-                let next_encrypted = (*tail).next;
-                let next = xor_ptr_general(next_encrypted, random_key);
+    let mut tail = head;
+    let mut count = 1;
+    loop {
+        let next_encrypted = (*tail).next;
+        let next = xor_ptr_general(next_encrypted, random_key);
 
-                (*tail).next = next;
-                (*tail).life_time = 0;
+        (*tail).next = next;
+        (*tail).life_time = 0;
 
-                if next.is_null() {
-                    break;
-                }
+        if next.is_null() {
+            break;
+        }
 
-                if !is_ours(next as usize) {
-                    (*tail).next = null_mut();
-                    break;
-                }
+        if !is_ours(next as usize) {
+            (*tail).next = null_mut();
+            break;
+        }
 
-                tail = next;
-                count += 1;
-            }
+        tail = next;
+        count += 1;
+    }
 
-            GlobalHandler.push_to_global(class, head, tail, count);
-        }
+    GlobalHandler.push_to_global(class, head, tail, count);
+    count
+}
+
+// Hand every cached block and the untouched rest of pending slabs back to the ICC
+pub unsafe fn flush_thread_cache(cache: &mut ThreadLocalEngine) {
+    // Quarantined blocks go through the normal free path first, into the bins flushed below
+    #[cfg(feature = "hardened-malloc")]
+    while let Some(header) = cache.quarantine.pop() {
+        release_block(cache, header);
+    }
+
+    for class in 0..NUM_SIZE_CLASSES {
+        flush_bin(cache, class);
+        drain_pending(cache, class);
+    }
+}
 
-        drain_pending(&mut *cache, class);
+unsafe fn cleanup_thread_cache(cache: *mut ThreadLocalEngine) {
+    TLS = null_mut();
+    if cache.is_null() {
+        return;
     }
 
+    flush_thread_cache(&mut *cache);
+    release_owner_id((*cache).owner);
+
     let total_size = size_of::<ThreadLocalEngine>();
 
     let _ = unmap_memory(cache as *mut c_void, total_size);
@@ -300,6 +353,7 @@ mod tests {
                     next: null_mut(),
                     class: 0,
                     magic: FREED_MAGIC,
+                    owner: 0,
                     life_time: 0,
                 },
             );
@@ -310,6 +364,11 @@ mod tests {
                 black_box(tls.push_to_thread(1, header));
             }
             let end = Instant::now();
+
+            // The fake header is not ours, take it back before malloc can hand it out
+            let header = tls.pop_from_thread(1);
+            let _ = unmap_memory(header as *mut c_void, size_of::<OxHeader>());
+
             let dur = end - start;
             let ns = dur.as_nanos() as f64 / 1_000_000.0;
             println!("TLS pop+push: {:.2} ns/op", ns);
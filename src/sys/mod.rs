@@ -1,6 +1,8 @@
 #[cfg(any(target_os = "linux"))]
 mod syscall_linux;
 
+pub const EPERM: i32 = 1;
+pub const ENOENT: i32 = 2;
 pub const EINVAL: i32 = 22;
 pub const NOMEM: i32 = 12;
 pub const EEXIST: i32 = 17;
@@ -10,7 +12,7 @@ mod linux {
 
     use crate::sys::{
         EEXIST, EINVAL, NOMEM,
-        syscall_linux::{Advice, MapFlags, ProtFlags},
+        syscall_linux::{Advice, MapFlags, MemPolicy, ProtFlags, RemapFlags},
     };
     use std::ops::BitOr;
 
@@ -56,6 +58,9 @@ mod linux {
     impl MadviseFlags {
         pub const HUGEPAGE: Self = MadviseFlags(Advice::HUGEPAGE);
         pub const DONTNEED: Self = MadviseFlags(Advice::DONTNEED);
+        pub const FREE: Self = MadviseFlags(Advice::FREE);
+        pub const COLD: Self = MadviseFlags(Advice::COLD);
+        pub const PAGEOUT: Self = MadviseFlags(Advice::PAGEOUT);
         pub const NORMAL: Self = MadviseFlags(Advice::NORMAL);
     }
 
@@ -88,6 +93,25 @@ mod linux {
         }
     }
 
+    pub struct MRemapFlags(pub RemapFlags);
+    impl MRemapFlags {
+        pub const MAYMOVE: Self = MRemapFlags(RemapFlags::MAYMOVE);
+        pub const FIXED: Self = MRemapFlags(RemapFlags::FIXED);
+    }
+    impl BitOr for MRemapFlags {
+        type Output = MRemapFlags;
+
+        fn bitor(self, rhs: Self) -> Self::Output {
+            MRemapFlags(self.0 | rhs.0)
+        }
+    }
+
+    pub struct BindPolicy(pub MemPolicy);
+    impl BindPolicy {
+        pub const PREFERRED: Self = BindPolicy(MemPolicy::PREFERRED);
+        pub const BIND: Self = BindPolicy(MemPolicy::BIND);
+    }
+
     #[derive(Debug, Clone)]
     pub struct MMapFlags {
         pub prot: MProtFlags,
@@ -98,11 +122,12 @@ mod linux {
 #[cfg(target_os = "linux")]
 pub mod memory_system {
     pub use crate::sys::linux::{
-        MMapFlags, MProtFlags, MadviseFlags, MemoryFlags, RMProtFlags, SysErr,
+        BindPolicy, MMapFlags, MProtFlags, MRemapFlags, MadviseFlags, MemoryFlags, RMProtFlags,
+        SysErr,
     };
     use crate::sys::syscall_linux::{
-        get_random_val, madvise_memory, map_memory, mprotect_memory, munmap_memory, register_rseq,
-        syscall6,
+        MembarrierCmd, get_random_val, madvise_memory, map_memory, mbind_memory, membarrier,
+        mprotect_memory, mremap_memory, munmap_memory, register_rseq, syscall6,
     };
     use std::os::raw::c_void;
 
@@ -121,6 +146,34 @@ pub mod memory_system {
         map_memory(ptr, size, protf.0, mapf.0)
     }
 
+    pub unsafe fn remap_memory(
+        old: *mut c_void,
+        old_size: usize,
+        new: *mut c_void,
+        new_size: usize,
+        flags: MRemapFlags,
+    ) -> Result<*mut c_void, SysErr> {
+        mremap_memory(old, old_size, new_size, flags.0, new)
+    }
+
+    pub unsafe fn bind_memory(
+        ptr: *mut c_void,
+        len: usize,
+        node: usize,
+        policy: BindPolicy,
+    ) -> Result<(), SysErr> {
+        const MAX_NODES: usize = 1024;
+        const BITS: usize = usize::BITS as usize;
+
+        if node >= MAX_NODES {
+            return Err(SysErr::Unaligned);
+        }
+
+        let mut mask = [0usize; MAX_NODES / BITS];
+        mask[node / BITS] = 1 << (node % BITS);
+        mbind_memory(ptr, len, policy.0, &mask, MAX_NODES)
+    }
+
     pub unsafe fn madvise(
         ptr: *mut c_void,
         len: usize,
@@ -145,6 +198,16 @@ pub mod memory_system {
         register_rseq(ptr, len, sig)
     }
 
+    // Lets `rseq_fence` abort rseq critical sections of this process
+    pub unsafe fn reg_rseq_fence() -> Result<(), i32> {
+        membarrier(MembarrierCmd::REGISTER_PRIVATE_EXPEDITED_RSEQ, None)
+    }
+
+    // Restarts any rseq critical section running on `cpu` and orders memory with it
+    pub unsafe fn rseq_fence(cpu: usize) -> Result<(), i32> {
+        membarrier(MembarrierCmd::PRIVATE_EXPEDITED_RSEQ, Some(cpu))
+    }
+
     pub unsafe fn get_cpu_count() -> usize {
         let mut mask = [0u64; 8192 / 8]; // Supports up to 8192 cores
         let ret = syscall6(
@@ -163,4 +226,26 @@ pub mod memory_system {
 
         mask.iter().map(|part| part.count_ones() as usize).sum()
     }
+
+    // Highest CPU id the process may run on plus one, larger than the count with sparse masks
+    pub unsafe fn get_cpu_span() -> usize {
+        let mut mask = [0u64; 8192 / 64];
+        let ret = syscall6(
+            204,
+            0,
+            size_of_val(&mask),
+            mask.as_mut_ptr() as usize,
+            0,
+            0,
+            0,
+        );
+
+        if ret < 0 {
+            return 1;
+        }
+
+        mask.iter()
+            .rposition(|part| *part != 0)
+            .map_or(1, |i| (i + 1) * 64 - mask[i].leading_zeros() as usize)
+    }
 }
@@ -19,6 +19,9 @@ pub struct Advice(usize);
 
 impl Advice {
     pub const DONTNEED: Self = Self(4);
+    pub const FREE: Self = Self(8);
+    pub const COLD: Self = Self(20);
+    pub const PAGEOUT: Self = Self(21);
     pub const HUGEPAGE: Self = Self(14);
     pub const NORMAL: Self = Self(0);
 }
@@ -59,26 +62,48 @@ impl core::ops::BitOr for MapFlags {
     }
 }
 
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct RemapFlags(usize);
+
+impl RemapFlags {
+    pub const MAYMOVE: Self = Self(1);
+    pub const FIXED: Self = Self(2);
+}
+
+impl core::ops::BitOr for RemapFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 pub struct Sys;
 
 #[cfg(target_arch = "x86_64")]
 impl Sys {
     const SYS_MMAP: usize = 9;
     const SYS_MUNMAP: usize = 11;
+    const SYS_MREMAP: usize = 25;
+    const SYS_MBIND: usize = 237;
     const SYS_MADVISE: usize = 28;
     const SYS_MPROTECT: usize = 10;
     const SYS_GETRANDOM: usize = 318;
     const SYS_RSEQ: usize = 334;
+    const SYS_MEMBARRIER: usize = 324;
 }
 
 #[cfg(target_arch = "aarch64")]
 impl Sys {
     const SYS_MMAP: usize = 222;
     const SYS_MUNMAP: usize = 215;
+    const SYS_MREMAP: usize = 216;
+    const SYS_MBIND: usize = 235;
     const SYS_MADVISE: usize = 233;
     const SYS_MPROTECT: usize = 226;
     const SYS_GETRANDOM: usize = 278;
     const SYS_RSEQ: usize = 293;
+    const SYS_MEMBARRIER: usize = 283;
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -186,6 +211,70 @@ pub unsafe fn munmap_memory(ptr: *mut c_void, len: usize) -> Result<(), SysErr>
     }
 }
 
+// The kernel moves the page tables, the contents are never copied
+pub unsafe fn mremap_memory(
+    old: *mut c_void,
+    old_len: usize,
+    new_len: usize,
+    flags: RemapFlags,
+    new: *mut c_void,
+) -> Result<*mut c_void, SysErr> {
+    let ret = syscall6(
+        Sys::SYS_MREMAP,
+        old as usize,
+        old_len,
+        new_len,
+        flags.0,
+        new as usize,
+        0,
+    );
+
+    match syscall_result(ret) {
+        Ok(out) => Ok(out as *mut c_void),
+        Err(e) => match e {
+            NOMEM => Err(SysErr::OOM),
+            EINVAL => Err(SysErr::Unaligned),
+            _ => Err(SysErr::Other),
+        },
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct MemPolicy(usize);
+
+impl MemPolicy {
+    pub const PREFERRED: Self = Self(1);
+    pub const BIND: Self = Self(2);
+}
+
+// `nodemask` holds `maxnode` bits
+pub unsafe fn mbind_memory(
+    ptr: *mut c_void,
+    len: usize,
+    policy: MemPolicy,
+    nodemask: &[usize],
+    maxnode: usize,
+) -> Result<(), SysErr> {
+    let ret = syscall6(
+        Sys::SYS_MBIND,
+        ptr as usize,
+        len,
+        policy.0,
+        nodemask.as_ptr() as usize,
+        // The kernel drops the last bit
+        maxnode + 1,
+        0,
+    );
+
+    match syscall_result(ret) {
+        Ok(_) => Ok(()),
+        Err(NOMEM) => Err(SysErr::OOM),
+        Err(EINVAL) => Err(SysErr::Unaligned),
+        _ => Err(SysErr::Other),
+    }
+}
+
 pub unsafe fn madvise_memory(ptr: *mut c_void, len: usize, madvise: Advice) -> Result<(), SysErr> {
     let ret = syscall6(Sys::SYS_MADVISE, ptr as usize, len, madvise.0, 0, 0, 0);
 
@@ -243,3 +332,23 @@ pub unsafe fn register_rseq(ptr: *mut c_void, len: usize, sig: u32) -> Result<()
         Ok(())
     }
 }
+
+pub struct MembarrierCmd(usize);
+
+impl MembarrierCmd {
+    pub const PRIVATE_EXPEDITED_RSEQ: Self = Self(1 << 7);
+    pub const REGISTER_PRIVATE_EXPEDITED_RSEQ: Self = Self(1 << 8);
+}
+
+const MEMBARRIER_CMD_FLAG_CPU: usize = 1;
+
+// `cpu` restricts the barrier to one CPU
+pub unsafe fn membarrier(cmd: MembarrierCmd, cpu: Option<usize>) -> Result<(), i32> {
+    let (flags, cpu) = match cpu {
+        Some(cpu) => (MEMBARRIER_CMD_FLAG_CPU, cpu),
+        None => (0, 0),
+    };
+
+    let ret = syscall6(Sys::SYS_MEMBARRIER, cmd.0, flags, cpu, 0, 0, 0);
+    if ret < 0 { Err(-ret as i32) } else { Ok(()) }
+}
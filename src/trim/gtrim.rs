@@ -1,20 +1,37 @@
-use std::{ffi::c_void, ptr::null_mut, sync::atomic::Ordering};
+use std::{
+    ffi::c_void,
+    ptr::null_mut,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
 
+#[cfg(feature = "hardened-malloc")]
+use crate::sys::memory_system::{RMProtFlags, protect_memory};
 use crate::{
-    AVERAGE_BLOCK_TIMES_GLOBAL, FREED_MAGIC, HEADER_SIZE, OX_CURRENT_STAMP, OxHeader,
-    OxidallocError,
+    AVERAGE_BLOCK_TIMES_GLOBAL, FREED_MAGIC, HEADER_SIZE, MetaData, OX_CURRENT_STAMP, OxHeader,
+    OxidallocError, TOTAL_ALLOCATED, TOTAL_IN_USE,
+    big_cache::BIG_CACHE,
+    internals::lock::SerialLock,
     slab::{
-        NUM_SIZE_CLASSES, SIZE_CLASSES, get_size_4096_class, global::GlobalHandler,
-        interconnect::ICC,
+        ITERATIONS, NUM_SIZE_CLASSES, SIZE_CLASSES, bulk_allocation::remaining_blocks,
+        get_size_4096_class, global::GlobalHandler, interconnect::ICC, slab_of,
     },
-    sys::memory_system::{MadviseFlags, madvise},
     trim::{
-        TimeDecay,
+        TimeDecay, release_pages,
         thread::{GLOBAL_DECAY, LAST_PRESSURE_CHECK},
     },
-    va::is_ours,
+    va::{align_to, bitmap::VA_MAP, is_ours},
 };
 
+// Census state lives in `MetaData`, one reclaim pass at a time
+pub static RECLAIM_LOCK: SerialLock = SerialLock::new();
+static CENSUS_EPOCH: AtomicU32 = AtomicU32::new(0);
+pub static SLABS_RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+// `live` of a slab that is still being carved, it can not die this pass
+const PINNED: u16 = u16::MAX;
+// `live` of a slab that is already queued for release
+const RELEASING: u16 = u16::MAX - 1;
+
 pub struct GTrim;
 
 impl GTrim {
@@ -66,6 +83,28 @@ impl GTrim {
         let timing = AVERAGE_BLOCK_TIMES_GLOBAL.load(Ordering::Relaxed) as u32;
         let class_4096 = get_size_4096_class();
 
+        // Cached big regions are the cheapest memory to give back
+        if pad == 0 || force_trim {
+            total_freed += BIG_CACHE.purge(0);
+        }
+
+        // Small classes only give memory back as whole slabs
+        let min_age = if pad == 0 || force_trim {
+            0
+        } else {
+            timing + 1
+        };
+        {
+            let _guard = RECLAIM_LOCK.lock();
+            for class in (0..class_4096).filter(|&class| ITERATIONS[class] > 1) {
+                if total_freed >= pad && pad != 0 {
+                    return (1, total_freed);
+                }
+
+                total_freed += self.reclaim_slabs(class, min_age);
+            }
+        }
+
         for class in class_4096..NUM_SIZE_CLASSES {
             if total_freed >= pad && pad != 0 {
                 return (1, total_freed);
@@ -146,6 +185,121 @@ impl GTrim {
         }
     }
 
+    // Takes every block of `class` out of the ICC and counts them against their slabs. A fully
+    // carved slab whose blocks were all found, and idle for `min_age` seconds, is released. The
+    // rest goes back. Blocks cached by threads or pushed meanwhile keep their slab alive.
+    unsafe fn reclaim_slabs(&self, class: usize, min_age: u32) -> usize {
+        // Not even one slab worth of blocks, no need to starve the class
+        if ICC.get_size(class) < ITERATIONS[class] {
+            return 0;
+        }
+
+        let block_size = align_to(SIZE_CLASSES[class] + HEADER_SIZE, 16);
+        let mut epoch = CENSUS_EPOCH.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        // Fresh slabs start at epoch 0
+        if epoch == 0 {
+            epoch = CENSUS_EPOCH.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        }
+
+        let mut blocks = null_mut();
+        let mut budget = ICC.get_size(class);
+        while budget > 0 {
+            let (cache, size) = self.pop_from_global(class);
+            if cache.is_null() {
+                break;
+            }
+            budget = budget.saturating_sub(size);
+
+            let mut block = cache;
+            while !block.is_null() {
+                let next = (*block).next;
+                let slab = slab_of(block, class);
+
+                if (*slab).epoch != epoch {
+                    (*slab).epoch = epoch;
+                    (*slab).live = if remaining_blocks(slab, block_size) == 0 {
+                        ITERATIONS[class] as u16
+                    } else {
+                        PINNED
+                    };
+                }
+
+                if (*slab).live != PINNED
+                    && OX_CURRENT_STAMP.saturating_sub((*block).life_time) >= min_age
+                {
+                    (*slab).live -= 1;
+                }
+
+                (*block).next = blocks;
+                blocks = block;
+                block = next;
+            }
+        }
+
+        // The carve cursor of a dead slab is spent, it links the slabs to release
+        let mut dead: *mut MetaData = null_mut();
+        let mut keep = null_mut();
+        let mut keep_tail = null_mut();
+        let mut kept = 0;
+        while !blocks.is_null() {
+            let next = (*blocks).next;
+            let slab = slab_of(blocks, class);
+
+            match (*slab).live {
+                0 => {
+                    (*slab).live = RELEASING;
+                    (*slab).next = dead as usize;
+                    dead = slab;
+                }
+                RELEASING => {}
+                _ => {
+                    (*blocks).next = keep;
+                    if keep.is_null() {
+                        keep_tail = blocks;
+                    }
+                    keep = blocks;
+                    kept += 1;
+                }
+            }
+            blocks = next;
+        }
+
+        if kept > 0 {
+            GlobalHandler.push_to_global(class, keep, keep_tail, kept);
+        }
+
+        let mut freed = 0;
+        while !dead.is_null() {
+            let next = (*dead).next as *mut MetaData;
+            let start = (*dead).start;
+            let total = (*dead).end - start;
+
+            self.release_slab(start, total);
+            TOTAL_ALLOCATED.fetch_sub(total, Ordering::Relaxed);
+            TOTAL_IN_USE.fetch_sub(ITERATIONS[class] * SIZE_CLASSES[class], Ordering::Relaxed);
+            SLABS_RECLAIMED.fetch_add(1, Ordering::Relaxed);
+            freed += total;
+
+            dead = next;
+        }
+
+        freed
+    }
+
+    // A CAS pop that lost the race may still load a `next` from the slab, so it stays readable.
+    // Hardened builds fault stale writes until the range is reused, and with locked lists nothing
+    // can be reading it anymore.
+    unsafe fn release_slab(&self, start: usize, total: usize) {
+        let _ = release_pages(start as *mut c_void, total);
+
+        #[cfg(feature = "hardened-linked-list")]
+        let _ = protect_memory(start as *mut c_void, total, RMProtFlags::NONE);
+        #[cfg(all(feature = "hardened-malloc", not(feature = "hardened-linked-list")))]
+        let _ = protect_memory(start as *mut c_void, total, RMProtFlags::READ);
+
+        VA_MAP.free(start, total);
+    }
+
     #[inline]
     fn release_memory(&self, header_ptr: *mut OxHeader, size: usize) {
         unsafe {
@@ -164,7 +318,7 @@ impl GTrim {
             }
             let length = page_end - page_start;
 
-            let _ = madvise(page_start as *mut c_void, length, MadviseFlags::DONTNEED);
+            let _ = release_pages(page_start as *mut c_void, length);
         }
     }
 }
@@ -1,6 +1,58 @@
 pub mod gtrim;
+pub mod pressure;
+pub mod ptrim;
 pub mod thread;
 
+use std::{os::raw::c_void, sync::atomic::Ordering};
+
+use crate::{
+    OX_RELEASE,
+    sys::memory_system::{MadviseFlags, SysErr, madvise},
+    trim::thread::LAST_PRESSURE_CHECK,
+};
+
+// How freed pages go back to the kernel. Only DONTNEED makes them read back as zero, FREE keeps
+// the old contents until the kernel actually needs the memory, COLD and PAGEOUT keep them resident
+// or swapped but first in line for reclaim.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReleasePolicy {
+    DontNeed,
+    Free,
+    Cold,
+    PageOut,
+}
+
+impl ReleasePolicy {
+    fn advice(&self) -> MadviseFlags {
+        match self {
+            ReleasePolicy::DontNeed => MadviseFlags::DONTNEED,
+            ReleasePolicy::Free => MadviseFlags::FREE,
+            ReleasePolicy::Cold => MadviseFlags::COLD,
+            ReleasePolicy::PageOut => MadviseFlags::PAGEOUT,
+        }
+    }
+
+    // Pages released under this policy may still hold their old contents
+    pub fn keeps_contents(&self) -> bool {
+        *self != ReleasePolicy::DontNeed
+    }
+}
+
+// Falls back to DONTNEED above 85% memory pressure, where lazily freed pages would only be
+// reclaimed late, and on kernels that reject the advice (FREE needs 4.5, COLD/PAGEOUT 5.4)
+pub unsafe fn release_pages(addr: *mut c_void, len: usize) -> Result<(), SysErr> {
+    let policy = OX_RELEASE;
+    if policy.keeps_contents()
+        && LAST_PRESSURE_CHECK.load(Ordering::Relaxed) <= 85
+        && madvise(addr, len, policy.advice()).is_ok()
+    {
+        return Ok(());
+    }
+
+    madvise(addr, len, MadviseFlags::DONTNEED)
+}
+
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TimeDecay {
@@ -38,6 +90,26 @@ impl TimeDecay {
         }
     }
 
+    // Seconds a freed big region may stay in `BIG_CACHE`
+    pub fn get_big_cache_age(&self) -> u32 {
+        match self {
+            TimeDecay::Normal => 8,
+            TimeDecay::Medium => 4,
+            TimeDecay::High => 2,
+            TimeDecay::Aggressive => 1,
+        }
+    }
+
+    // Seconds a thread's bin may go without frees or refills before ptrim flushes it
+    pub fn get_ptrim_age(&self) -> u32 {
+        match self {
+            TimeDecay::Normal => 10,
+            TimeDecay::Medium => 5,
+            TimeDecay::High => 2,
+            TimeDecay::Aggressive => 1,
+        }
+    }
+
     pub fn get_threshold(&self) -> u64 {
         match self {
             TimeDecay::Normal => 32 * 1024 * 1024,
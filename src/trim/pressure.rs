@@ -0,0 +1,200 @@
+// Memory pressure sources besides `sysinfo`
+// Inside a container host RAM says little, so the trim thread also reads the cgroup v2
+// `memory.current`/`memory.max` of the process and takes the worse value. It sleeps in `poll` on a
+// PSI trigger for `/proc/pressure/memory`, a stall wakes it before the next tick.
+// `OX_CGROUP_DIR` and `OX_PSI_PATH` replace the cgroup directory and the PSI file, for tests.
+
+use std::{os::raw::c_int, time::Duration};
+
+use crate::{
+    internals::{conf::read_file, env::get_env_bytes},
+    va::numa::parse_num,
+};
+
+const CGROUP_ENV: &[u8] = b"OX_CGROUP_DIR";
+const PSI_ENV: &[u8] = b"OX_PSI_PATH";
+const CGROUP_ROOT: &[u8] = b"/sys/fs/cgroup";
+const PSI_PATH: &[u8] = b"/proc/pressure/memory";
+// 150 ms of stalls within 2 s, unprivileged triggers need a window that is a multiple of 2 s
+pub const PSI_TRIGGER: &[u8] = b"some 150000 2000000\0";
+const PATH_MAX: usize = 512;
+
+pub struct PressureSources {
+    max: [u8; PATH_MAX],
+    current: [u8; PATH_MAX],
+    cgroup: bool,
+    psi_fd: c_int,
+}
+
+impl PressureSources {
+    // Missing files just leave a source out, cgroup v1 and kernels before 5.2 only have `sysinfo`
+    pub unsafe fn open() -> Self {
+        let mut sources = Self {
+            max: [0; PATH_MAX],
+            current: [0; PATH_MAX],
+            cgroup: false,
+            psi_fd: -1,
+        };
+
+        let mut buf = [0u8; 4096];
+        let dir = match get_env_bytes(CGROUP_ENV) {
+            Some(dir) => Some((dir, &[][..])),
+            None => parse_cgroup(read_file(b"/proc/self/cgroup\0", &mut buf))
+                .map(|path| (CGROUP_ROOT, path)),
+        };
+        if let Some((root, path)) = dir {
+            sources.cgroup = join_path(&mut sources.max, &[root, path, b"/memory.max"])
+                && join_path(&mut sources.current, &[root, path, b"/memory.current"]);
+        }
+
+        let mut psi = [0u8; PATH_MAX];
+        if join_path(&mut psi, &[get_env_bytes(PSI_ENV).unwrap_or(PSI_PATH)]) {
+            sources.psi_fd = open_trigger(&psi);
+        }
+
+        sources
+    }
+
+    // Percent of `memory.max` in use, None without a limit
+    pub unsafe fn cgroup_usage(&self) -> Option<usize> {
+        if !self.cgroup {
+            return None;
+        }
+
+        let mut max = [0u8; 32];
+        let mut current = [0u8; 32];
+        usage_percent(
+            read_file(&self.max, &mut max),
+            read_file(&self.current, &mut current),
+        )
+    }
+
+    // Sleeps up to `timeout_ms`, true when the PSI trigger fired. 0 only checks.
+    pub unsafe fn wait(&mut self, timeout_ms: u64) -> bool {
+        if self.psi_fd < 0 {
+            if timeout_ms > 0 {
+                std::thread::sleep(Duration::from_millis(timeout_ms));
+            }
+            return false;
+        }
+
+        let mut pfd = libc::pollfd {
+            fd: self.psi_fd,
+            events: libc::POLLPRI,
+            revents: 0,
+        };
+        if libc::poll(&raw mut pfd, 1, timeout_ms as c_int) <= 0 {
+            return false;
+        }
+
+        // The cgroup of the trigger is gone, fall back to sleeping
+        if pfd.revents & libc::POLLERR != 0 {
+            libc::close(self.psi_fd);
+            self.psi_fd = -1;
+            return false;
+        }
+
+        pfd.revents & libc::POLLPRI != 0
+    }
+}
+
+unsafe fn open_trigger(path: &[u8]) -> c_int {
+    let fd = libc::open(
+        path.as_ptr().cast(),
+        libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+    );
+    if fd < 0 {
+        return -1;
+    }
+
+    if libc::write(fd, PSI_TRIGGER.as_ptr().cast(), PSI_TRIGGER.len()) != PSI_TRIGGER.len() as isize
+    {
+        libc::close(fd);
+        return -1;
+    }
+
+    fd
+}
+
+// Path of the unified hierarchy entry ("0::/path") in /proc/self/cgroup, "" for the root
+fn parse_cgroup(input: &[u8]) -> Option<&[u8]> {
+    input
+        .split(|b| *b == b'\n')
+        .find_map(|line| line.strip_prefix(b"0::"))
+        .map(|path| {
+            let path = path.trim_ascii_end();
+            path.strip_suffix(b"/").unwrap_or(path)
+        })
+}
+
+// NUL terminated concatenation of `parts`, false when it does not fit
+fn join_path(buf: &mut [u8; PATH_MAX], parts: &[&[u8]]) -> bool {
+    let mut len = 0;
+    for part in parts {
+        if len + part.len() >= PATH_MAX {
+            return false;
+        }
+        buf[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+
+    buf[len] = 0;
+    true
+}
+
+// `max` reads "max" without a limit
+fn usage_percent(max: &[u8], current: &[u8]) -> Option<usize> {
+    let max = parse_num(max.trim_ascii())?;
+    let current = parse_num(current.trim_ascii())?;
+    if max == 0 {
+        return None;
+    }
+
+    Some((current.saturating_mul(100) / max).min(100))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_parsing() {
+        assert_eq!(
+            parse_cgroup(b"1:cpu:/\n0::/kubepods/pod1/ctr\n"),
+            Some(&b"/kubepods/pod1/ctr"[..])
+        );
+        assert_eq!(parse_cgroup(b"0::/\n"), Some(&b""[..]));
+        assert_eq!(parse_cgroup(b"4:memory:/docker\n"), None);
+
+        assert_eq!(usage_percent(b"1000\n", b"950\n"), Some(95));
+        assert_eq!(usage_percent(b"max\n", b"950\n"), None);
+        assert_eq!(usage_percent(b"100\n", b"200\n"), Some(100));
+    }
+
+    #[test]
+    fn trigger_wakes_poll() {
+        unsafe {
+            let mut fds = [0; 2];
+            assert_eq!(
+                libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()),
+                0
+            );
+            let mut sources = PressureSources {
+                max: [0; PATH_MAX],
+                current: [0; PATH_MAX],
+                cgroup: false,
+                psi_fd: fds[0],
+            };
+            assert!(!sources.wait(10));
+
+            // Out of band data raises POLLPRI like a firing PSI trigger, kernels without
+            // AF_UNIX OOB support can not run this part
+            if libc::send(fds[1], b"x".as_ptr().cast(), 1, libc::MSG_OOB) == 1 {
+                assert!(sources.wait(1000));
+            }
+
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
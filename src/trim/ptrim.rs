@@ -0,0 +1,82 @@
+// Per-thread trim
+// Idle threads keep whatever their bins hold until they exit. Every `get_ptrim_age` seconds the
+// trim thread marks the live thread caches, the owner sees the mark on its next malloc or free and
+// hands every bin whose newest block is older than that back to the ICC, where GTrim can release
+// it. Pending slabs stay with the thread, carving them would touch their pages.
+
+use std::{
+    hint::unlikely,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{
+    OX_CURRENT_STAMP,
+    slab::{
+        NUM_SIZE_CLASSES,
+        remote::{MAX_REMOTE_OWNERS, owner_alive},
+        thread_local::{ThreadLocalEngine, flush_bin},
+    },
+    trim::{
+        TimeDecay,
+        thread::{LAST_PRESSURE_CHECK, PTRIM_DECAY},
+    },
+};
+
+static PTRIM_MARKS: [AtomicBool; MAX_REMOTE_OWNERS] =
+    [const { AtomicBool::new(false) }; MAX_REMOTE_OWNERS];
+
+// Blocks handed back to the ICC by per-thread trims
+pub static PTRIM_FLUSHED: AtomicUsize = AtomicUsize::new(0);
+
+// High memory pressure flushes anything idle for a second
+pub fn ptrim_decay() -> TimeDecay {
+    if LAST_PRESSURE_CHECK.load(Ordering::Relaxed) > 85 {
+        TimeDecay::Aggressive
+    } else {
+        TimeDecay::from_u8(PTRIM_DECAY.load(Ordering::Relaxed))
+    }
+}
+
+// Called by the trim thread, threads without an owner id are never marked
+pub fn mark_thread_caches() {
+    for (id, mark) in PTRIM_MARKS.iter().enumerate().skip(1) {
+        if owner_alive(id) {
+            mark.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[inline(always)]
+pub unsafe fn ptrim_if_marked(thread: &mut ThreadLocalEngine) {
+    let marked = PTRIM_MARKS
+        .get(thread.owner as usize)
+        .is_some_and(|mark| mark.load(Ordering::Relaxed));
+
+    if unlikely(marked) {
+        ptrim(thread);
+    }
+}
+
+#[cold]
+#[inline(never)]
+unsafe fn ptrim(thread: &mut ThreadLocalEngine) {
+    PTRIM_MARKS[thread.owner as usize].store(false, Ordering::Relaxed);
+
+    let age = ptrim_decay().get_ptrim_age();
+    let mut flushed = 0;
+
+    for class in 0..NUM_SIZE_CLASSES {
+        let head = thread.tls[class].head;
+        if head.is_null() {
+            continue;
+        }
+
+        // Frees and refills stamp the blocks they put on top
+        let newest = thread.xor_ptr(head);
+        if OX_CURRENT_STAMP.saturating_sub((*newest).life_time) >= age {
+            flushed += flush_bin(thread, class);
+        }
+    }
+
+    PTRIM_FLUSHED.fetch_add(flushed, Ordering::Relaxed);
+}
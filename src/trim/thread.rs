@@ -1,21 +1,28 @@
-use std::{
-    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
-    time::Duration,
-};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
 
 use crate::{
-    AVERAGE_BLOCK_TIMES_GLOBAL, OX_CURRENT_STAMP, OX_TRIM_THRESHOLD, get_clock,
-    trim::{TimeDecay, gtrim::GTrim},
+    AVERAGE_BLOCK_TIMES_GLOBAL, OX_BACKGROUND_THREAD, OX_CURRENT_STAMP, OX_TRIM_THRESHOLD,
+    abi::malloc::HOT_READY,
+    big_cache::BIG_CACHE,
+    get_clock,
+    trim::{
+        TimeDecay,
+        gtrim::GTrim,
+        pressure::PressureSources,
+        ptrim::{mark_thread_caches, ptrim_decay},
+    },
 };
 
 static TOTAL_TIME_GLOBAL: AtomicUsize = AtomicUsize::new(0);
 static LAST_TRIM_GLOBAL: AtomicUsize = AtomicUsize::new(0);
 pub static LAST_PRESSURE_CHECK: AtomicUsize = AtomicUsize::new(0);
+// Trims started early by the PSI trigger
+pub static PRESSURE_WAKEUPS: AtomicUsize = AtomicUsize::new(0);
 
 pub static GLOBAL_DECAY: AtomicU8 = AtomicU8::new(0);
 pub static PTRIM_DECAY: AtomicU8 = AtomicU8::new(0);
 
-unsafe fn decide_global(decay: &TimeDecay) -> bool {
+unsafe fn decide_global(decay: &TimeDecay, sources: &PressureSources) -> bool {
     if (OX_TRIM_THRESHOLD.load(Ordering::Relaxed) < decay.get_threshold() as usize)
         && (OX_TRIM_THRESHOLD.load(Ordering::Relaxed) != 0)
     {
@@ -23,7 +30,8 @@ unsafe fn decide_global(decay: &TimeDecay) -> bool {
     }
 
     let total = TOTAL_TIME_GLOBAL.load(Ordering::Relaxed);
-    LAST_PRESSURE_CHECK.store(check_memory_pressure(), Ordering::Relaxed);
+    let pressure = check_memory_pressure().max(sources.cgroup_usage().unwrap_or(0));
+    LAST_PRESSURE_CHECK.store(pressure, Ordering::Relaxed);
 
     if LAST_PRESSURE_CHECK.load(Ordering::Relaxed) > 85 {
         return true;
@@ -69,21 +77,95 @@ fn check_memory_pressure() -> usize {
     }
 }
 
+// State of whoever runs the trim ticks, the background thread or the allocator slow paths
+struct Trimmer {
+    sources: PressureSources,
+    last_ptrim: u32,
+}
+
+impl Trimmer {
+    unsafe fn tick(&mut self, decay: &TimeDecay, stalled: bool) {
+        TOTAL_TIME_GLOBAL.fetch_add(decay.get_trim_time_for_global() as usize, Ordering::Relaxed);
+
+        let time = get_clock().elapsed().as_secs() as u32;
+        OX_CURRENT_STAMP = time;
+
+        // Tasks are stalling on memory, give back everything that is idle right now
+        if stalled {
+            PRESSURE_WAKEUPS.fetch_add(1, Ordering::Relaxed);
+            LAST_PRESSURE_CHECK.store(100, Ordering::Relaxed);
+            self.last_ptrim = time;
+            mark_thread_caches();
+            GTrim.trim(OX_TRIM_THRESHOLD.load(Ordering::Relaxed));
+            return;
+        }
+
+        BIG_CACHE.purge(decay.get_big_cache_age());
+
+        if time.saturating_sub(self.last_ptrim) >= ptrim_decay().get_ptrim_age() {
+            self.last_ptrim = time;
+            mark_thread_caches();
+        }
+
+        if decide_global(decay, &self.sources) {
+            GTrim.trim(OX_TRIM_THRESHOLD.load(Ordering::Relaxed));
+        }
+    }
+}
+
 pub unsafe fn spawn_gtrim_thread() {
     std::thread::spawn(|| {
+        let mut trimmer = Trimmer {
+            sources: PressureSources::open(),
+            last_ptrim: 0,
+        };
         loop {
             let decay = TimeDecay::from_u8(GLOBAL_DECAY.load(Ordering::Relaxed));
-            std::thread::sleep(Duration::from_millis(decay.get_trim_time()));
+            let stalled = trimmer.sources.wait(decay.get_trim_time());
+            trimmer.tick(&decay, stalled);
+        }
+    });
+}
 
-            TOTAL_TIME_GLOBAL
-                .fetch_add(decay.get_trim_time_for_global() as usize, Ordering::Relaxed);
+static INLINE_BUSY: AtomicBool = AtomicBool::new(false);
+// Milliseconds since `get_clock` the next inline tick is due
+static INLINE_NEXT: AtomicU64 = AtomicU64::new(0);
+static mut INLINE_TRIMMER: Option<Trimmer> = None;
+
+// Without the background thread `try_fill` and `big_free` run its ticks, whichever comes first once
+// a tick is due. The PSI trigger is checked without waiting.
+#[inline(always)]
+pub unsafe fn trim_on_slow_path() {
+    if !OX_BACKGROUND_THREAD && HOT_READY {
+        trim_inline();
+    }
+}
 
-            let time = get_clock().elapsed().as_secs() as u32;
-            OX_CURRENT_STAMP = time;
+#[cold]
+#[inline(never)]
+unsafe fn trim_inline() {
+    let now = get_clock().elapsed().as_millis() as u64;
+    if now < INLINE_NEXT.load(Ordering::Relaxed)
+        || INLINE_BUSY
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+    {
+        return;
+    }
 
-            if decide_global(&decay) {
-                GTrim.trim(OX_TRIM_THRESHOLD.load(Ordering::Relaxed));
-            }
-        }
+    let slot = &raw mut INLINE_TRIMMER;
+    let trimmer = (*slot).get_or_insert_with(|| Trimmer {
+        sources: PressureSources::open(),
+        last_ptrim: 0,
     });
+    let decay = TimeDecay::from_u8(GLOBAL_DECAY.load(Ordering::Relaxed));
+    let stalled = trimmer.sources.wait(0);
+    trimmer.tick(&decay, stalled);
+
+    INLINE_NEXT.store(now + decay.get_trim_time(), Ordering::Relaxed);
+    INLINE_BUSY.store(false, Ordering::Release);
+}
+
+pub(crate) fn reset_fork_trim() {
+    INLINE_BUSY.store(false, Ordering::Relaxed);
 }
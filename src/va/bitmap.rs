@@ -1,7 +1,9 @@
 use crate::{
     OX_MAX_RESERVATION, OxidallocError,
     internals::{lock::SerialLock, once::Once},
-    sys::memory_system::{MMapFlags, MProtFlags, MemoryFlags, getrandom, mmap_memory},
+    sys::memory_system::{
+        MMapFlags, MProtFlags, MemoryFlags, getrandom, mmap_memory, unmap_memory,
+    },
     va::{
         align_to,
         bootstrap::{boot_strap, init_alloc_random},
@@ -64,11 +66,15 @@ unsafe fn randomize_base_hint() {
     BASE_HINT = BASE_HINT.wrapping_add(align_to(rand % max, 4096));
 }
 
-pub unsafe fn get_va_from_kernel() -> (*mut c_void, usize, usize) {
+// `min_size` lets a single allocation bigger than the usual reservation get a segment of its own.
+// The last pair is the whole reserved mapping, the pages in front of the segment included.
+pub unsafe fn get_va_from_kernel(
+    min_size: usize,
+) -> (*mut c_void, usize, usize, (*mut c_void, usize)) {
     boot_strap();
     randomize_base_hint();
 
-    const MIN_RESERVE: usize = CHUNK_SIZE;
+    let min_reserve = align_to(min_size.max(1), CHUNK_SIZE);
     #[allow(non_snake_case)]
     let MAX_SIZE: usize = if likely(RESERVE.load(Ordering::Relaxed) > 3) {
         LATEST_TRIED.load(Ordering::Relaxed)
@@ -76,11 +82,12 @@ pub unsafe fn get_va_from_kernel() -> (*mut c_void, usize, usize) {
         RESERVE.fetch_add(1, Ordering::Relaxed);
         OX_MAX_RESERVATION.load(Ordering::Relaxed)
     };
+    let oversized = min_reserve > MAX_SIZE.max(CHUNK_SIZE);
 
     let mut size = MAX_SIZE;
 
-    if unlikely(MAX_SIZE < MIN_RESERVE) {
-        size = MIN_RESERVE;
+    if unlikely(MAX_SIZE < min_reserve) {
+        size = min_reserve;
     }
 
     loop {
@@ -92,15 +99,18 @@ pub unsafe fn get_va_from_kernel() -> (*mut c_void, usize, usize) {
             MemoryFlags::PRIVATE | MemoryFlags::NORESERVE
         };
 
+        // Segments must own every radix chunk they touch, a chunk aligned hint needs no spare room
         let target = if BASE_INIT {
-            base_hint as *mut c_void
+            align_to(base_hint, CHUNK_SIZE) as *mut c_void
         } else {
             null_mut()
         };
 
+        // The kernel picks its own address, one spare chunk lets us align inside the mapping
+        let reserve = if BASE_INIT { size } else { size + CHUNK_SIZE };
         let probe = mmap_memory(
             target,
-            size,
+            reserve,
             MMapFlags {
                 prot: MProtFlags::NONE,
                 map: flags,
@@ -109,33 +119,66 @@ pub unsafe fn get_va_from_kernel() -> (*mut c_void, usize, usize) {
 
         match probe {
             Ok(output) => {
-                if size > LATEST_TRIED.load(Ordering::Relaxed) {
+                // Oversized segments must not raise the size of every later reservation
+                if !oversized && size > LATEST_TRIED.load(Ordering::Relaxed) {
                     LATEST_TRIED.store(size, Ordering::Relaxed);
                 }
 
+                let base = align_to(output as usize, CHUNK_SIZE);
+                if unlikely(base + size > output as usize + reserve) {
+                    // Hint not honoured, let the kernel place it with a spare chunk instead
+                    let _ = unmap_memory(output, reserve);
+                    BASE_INIT = false;
+                    continue;
+                }
+
+                // Give the spare room back, the reservation is then exactly the segment's chunks
+                if base > output as usize {
+                    let _ = unmap_memory(output, base - output as usize);
+                }
+                if output as usize + reserve > base + size {
+                    let _ = unmap_memory(
+                        (base + size) as *mut c_void,
+                        output as usize + reserve - (base + size),
+                    );
+                }
+
                 if unlikely(!BASE_INIT) {
-                    BASE_HINT = output as usize;
                     BASE_INIT = true;
-                } else {
-                    BASE_HINT += size;
                 }
-
-                return (output, (output as usize) + size, size);
+                BASE_HINT = base + size;
+
+                // Page granular offset keeps the base unpredictable, it never cuts into `min_size`
+                let offset = (alloc_random() & (MAX_RANDOM_BYTES - 1)).min(size - min_size)
+                    & !(BLOCK_SIZE - 1);
+                let start = base + offset;
+
+                return (
+                    start as *mut c_void,
+                    base + size,
+                    size - offset,
+                    (base as *mut c_void, size),
+                );
             }
             Err(err) => {
-                if (size <= MIN_RESERVE) && !BASE_INIT {
+                // Only the first reservation is fatal, later ones fail the allocation that needed them
+                if (size <= min_reserve)
+                    && !BASE_INIT
+                    && !oversized
+                    && LATEST_TRIED.load(Ordering::Relaxed) == 0
+                {
                     OxidallocError::VAIinitFailed.log_and_abort(
                         null_mut(),
                         "Init failed during Segment Allocation: No available VA reserve",
                         Some(err.get_errno()),
                     )
-                } else if size <= MIN_RESERVE {
+                } else if size <= min_reserve {
                     BASE_INIT = false;
-                    return (null_mut(), 0, 0);
+                    return (null_mut(), 0, 0, (null_mut(), 0));
                 }
 
-                BASE_HINT += size;
-                size /= 2;
+                BASE_HINT += reserve;
+                size = (size / 2).max(min_reserve);
             }
         }
     }
@@ -327,7 +370,9 @@ impl VaBitmap {
         addr >= s.va_start && addr < s.va_end
     }
 
-    pub unsafe fn grow(&mut self) -> Option<*mut Segment> {
+    // Claims `min_size` at `align` in the new segment before other threads can see it and returns
+    // its address
+    pub unsafe fn grow(&mut self, min_size: usize, align: usize) -> Option<(*mut Segment, usize)> {
         let _guard = self.lock.lock();
 
         if unlikely(self.radix_tree.nodes.l1.is_null()) {
@@ -336,7 +381,8 @@ impl VaBitmap {
             });
         }
 
-        let (user_va, end, total_size) = get_va_from_kernel();
+        let (user_va, end, total_size, (reserved, reserved_len)) =
+            get_va_from_kernel(min_size + align - BLOCK_SIZE);
 
         if user_va.is_null() {
             self.lock.unlock();
@@ -347,6 +393,7 @@ impl VaBitmap {
             .radix_tree
             .check_collision(user_va as usize, total_size)
         {
+            let _ = unmap_memory(reserved, reserved_len);
             self.lock.unlock();
             return None;
         }
@@ -365,6 +412,7 @@ impl VaBitmap {
         ) {
             Ok(ptr) => ptr,
             Err(_) => {
+                let _ = unmap_memory(reserved, reserved_len);
                 self.lock.unlock();
                 return None;
             }
@@ -380,6 +428,8 @@ impl VaBitmap {
         ) {
             Ok(ptr) => ptr,
             Err(_) => {
+                let _ = unmap_memory(map_raw, map_bytes);
+                let _ = unmap_memory(reserved, reserved_len);
                 self.lock.unlock();
                 return None;
             }
@@ -395,6 +445,9 @@ impl VaBitmap {
         ) {
             Ok(ptr) => ptr as *mut Segment,
             Err(_) => {
+                let _ = unmap_memory(claim_raw, map_bytes);
+                let _ = unmap_memory(map_raw, map_bytes);
+                let _ = unmap_memory(reserved, reserved_len);
                 self.lock.unlock();
                 return None;
             }
@@ -424,16 +477,31 @@ impl VaBitmap {
             },
         );
 
+        // Fresh and unpublished, nothing can hold these blocks yet
+        let skip = (align_to(user_va as usize, align) - user_va as usize) / BLOCK_SIZE;
+        (*seg_ptr).try_claim(skip, min_size.div_ceil(BLOCK_SIZE));
+
         self.radix_tree
             .set_range(user_va as usize, total_size, seg_ptr);
         self.map.store(seg_ptr, Ordering::Release);
         self.lock.unlock();
 
-        Some(seg_ptr)
+        Some((seg_ptr, user_va as usize + skip * BLOCK_SIZE))
     }
 
     #[inline(always)]
     pub unsafe fn alloc(&mut self, size: usize) -> Option<usize> {
+        self.alloc_in(size, BLOCK_SIZE)
+    }
+
+    // Slabs that find their metadata by masking are carved straight from aligned runs, an
+    // over-reservation would leave its trimmed ends scattered between them
+    pub unsafe fn alloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        self.alloc_in(size, align.max(BLOCK_SIZE))
+    }
+
+    #[inline(always)]
+    unsafe fn alloc_in(&mut self, size: usize, align: usize) -> Option<usize> {
         if unlikely(size == 0) {
             return None;
         }
@@ -443,11 +511,7 @@ impl VaBitmap {
 
         if likely(!hint_ptr.is_null()) {
             let segment = &*hint_ptr;
-            let res = if needed == 1 {
-                segment.alloc_single()
-            } else {
-                segment.alloc_multi(needed)
-            };
+            let res = segment.alloc_blocks(needed, align);
             if likely(res.is_some()) {
                 return res;
             }
@@ -455,9 +519,10 @@ impl VaBitmap {
 
         let mut curr = self.map.load(Ordering::Acquire);
         if unlikely(curr.is_null()) {
+            let mut first = None;
             ONCE.call_once(|| {
-                match self.grow() {
-                    Some(new) => curr = new,
+                match self.grow(size, align) {
+                    Some(new) => first = Some(new),
                     None => OxidallocError::VAIinitFailed.log_and_abort(
                         null_mut(),
                         "VA initialization failed during allocator start",
@@ -465,23 +530,22 @@ impl VaBitmap {
                     ),
                 };
             });
-            if curr.is_null() {
-                curr = self.map.load(Ordering::Acquire);
+            if let Some((seg_ptr, addr)) = first {
+                self.latest_segment.store(seg_ptr, Ordering::Release);
+                return Some(addr);
             }
+            curr = self.map.load(Ordering::Acquire);
         }
 
         while !curr.is_null() {
             let segment = &*curr;
-            if segment.full.load(Ordering::Relaxed) {
+            // Too small to ever fit this request, it says nothing about how full the segment is
+            if segment.full.load(Ordering::Relaxed) || needed > segment.max_bits() {
                 curr = (*curr).next;
                 continue;
             }
 
-            let res = if needed == 1 {
-                segment.alloc_single()
-            } else {
-                segment.alloc_multi(needed)
-            };
+            let res = segment.alloc_blocks(needed, align);
 
             if res.is_some() {
                 self.latest_segment.store(curr, Ordering::Release);
@@ -496,26 +560,18 @@ impl VaBitmap {
         }
 
         let mut tried = 0usize;
-        let mut new_seg_ptr = None;
 
+        // The request is already claimed in a new segment, only a failed reservation is retried
         while tried < 10 {
-            if let Some(seg) = self.grow() {
-                new_seg_ptr = Some(seg);
-                break;
-            }
             tried += 1;
+            if let Some((seg_ptr, addr)) = self.grow(size, align) {
+                self.latest_segment.store(seg_ptr, Ordering::Release);
+                return Some(addr);
+            }
             std::hint::spin_loop();
         }
 
-        let seg_ptr = new_seg_ptr?;
-        self.latest_segment.store(seg_ptr, Ordering::Release);
-
-        let new_seg = &*seg_ptr;
-        if needed == 1 {
-            new_seg.alloc_single()
-        } else {
-            new_seg.alloc_multi(needed)
-        }
+        None
     }
 
     pub unsafe fn free(&self, addr: usize, size: usize) {
@@ -635,6 +691,46 @@ impl Segment {
         None
     }
 
+    #[inline(always)]
+    fn alloc_blocks(&self, count: usize, align: usize) -> Option<usize> {
+        if align > BLOCK_SIZE {
+            self.alloc_multi_aligned(count, align)
+        } else if count == 1 {
+            self.alloc_single()
+        } else {
+            self.alloc_multi(count)
+        }
+    }
+
+    // Only tries runs starting on `align`, a claim fails on the first taken word so the stride
+    // walk stays cheap on a busy segment
+    #[inline(always)]
+    fn alloc_multi_aligned(&self, count: usize, align: usize) -> Option<usize> {
+        let total_bits = self.max_bits();
+        let first = (align_to(self.va_start, align) - self.va_start) / BLOCK_SIZE;
+        if unlikely(first + count > total_bits) {
+            return None;
+        }
+
+        let step = align / BLOCK_SIZE;
+        let slots = (total_bits - first - count) / step + 1;
+        let h = self.hint.load(Ordering::Relaxed);
+        let r = unsafe { alloc_random() };
+        let rand_bits = (r) & (MAX_RANDOM_BLOCKS - 1);
+        let start_slot = (h * 64 + rand_bits) / step % slots;
+
+        for (range_start, range_end) in [(start_slot, slots), (0, start_slot)] {
+            for slot in range_start..range_end {
+                let idx = first + slot * step;
+                if self.try_claim(idx, count) {
+                    self.hint.store(idx / 64, Ordering::Relaxed);
+                    return Some(self.va_start + (idx * BLOCK_SIZE));
+                }
+            }
+        }
+        None
+    }
+
     #[inline(always)]
     fn alloc_multi(&self, count: usize) -> Option<usize> {
         let map = unsafe { self.get_map() };
@@ -5,13 +5,25 @@ use std::{
 };
 
 use crate::{
-    FREED_MAGIC, MAGIC, OX_FORCE_THP, OX_MAX_RESERVATION, OX_TRIM_THRESHOLD, OxidallocError,
+    FREED_MAGIC, MAGIC, OX_BACKGROUND_THREAD, OX_FORCE_THP, OX_GUARD_BEFORE, OX_GUARD_PAGES,
+    OX_LEAK_REPORT, OX_MAX_RESERVATION, OX_RELEASE, OX_RSEQ, OX_TRIM_THRESHOLD, OxidallocError,
     REAL_NUMA_NODES,
     abi::{fallback::fallback_reinit_on_fork, malloc::reset_fork_thread_state},
-    internals::{env::get_env_usize, once::Once},
+    internals::{
+        conf::{ConfValue, load_conf},
+        env::get_env_usize,
+        leak::keep_stderr,
+        once::Once,
+        prof::{PROF_SAMPLE, reset_fork_prof},
+        trace::{init_trace, reset_fork_trace},
+    },
     slab::thread_local::ThreadLocalEngine,
     sys::memory_system::{get_cpu_count, getrandom},
-    va::bitmap::{reset_fork_locks, reset_fork_onces},
+    trim::thread::{GLOBAL_DECAY, PTRIM_DECAY},
+    va::{
+        bitmap::{reset_fork_locks, reset_fork_onces},
+        numa::init_topology,
+    },
 };
 
 pub static mut NTHREADS: usize = 0;
@@ -47,15 +59,21 @@ extern "C" fn fork_child() {
     }
     reset_fork_locks();
     reset_fork_onces();
-    #[cfg(feature = "hardened-linked-list")]
-    unsafe {
-        crate::slab::global::reset_global_locks()
-    };
+    unsafe { crate::slab::global::reset_global_locks() };
     reset_fork_thread_state();
     crate::slab::reset_fork_onces();
     crate::reset_fork_onces();
     fallback_reinit_on_fork();
+    crate::big_cache::BIG_CACHE.reset_on_fork();
+    crate::trim::gtrim::RECLAIM_LOCK.reset_on_fork();
+    crate::trim::thread::reset_fork_trim();
+    reset_fork_prof();
+    unsafe { reset_fork_trace() };
     ONCE.reset_at_fork();
+    unsafe {
+        let tls = crate::slab::thread_local::TLS;
+        crate::slab::remote::reset_fork_owners(if tls.is_null() { 0 } else { (*tls).owner });
+    }
 }
 
 pub unsafe fn register_fork_handlers() {
@@ -103,6 +121,7 @@ unsafe fn init_nthreads() {
 pub(crate) unsafe fn init_numa_nodes() {
     let nodes = detect_numa_nodes();
     REAL_NUMA_NODES = nodes;
+    init_topology(nodes);
 }
 
 pub(crate) unsafe fn init_magic() {
@@ -179,38 +198,100 @@ pub(crate) unsafe fn init_alloc_random() -> usize {
     usize::from_ne_bytes(rand)
 }
 
+unsafe fn apply_conf(key: &[u8], val: ConfValue) {
+    match (key, val) {
+        (b"trim_threshold", ConfValue::Size(val)) => set_trim_threshold(val),
+        (b"thp", ConfValue::Bool(val)) => OX_FORCE_THP.store(val, Ordering::Relaxed),
+        (b"max_reservation", ConfValue::Size(val)) => set_max_reservation(val),
+        (b"background_thread", ConfValue::Bool(val)) => OX_BACKGROUND_THREAD = val,
+        (b"decay", ConfValue::Decay(val)) => {
+            GLOBAL_DECAY.store(val as u8, Ordering::Relaxed);
+            PTRIM_DECAY.store(val as u8, Ordering::Relaxed);
+        }
+        (b"rseq", ConfValue::Bool(val)) => OX_RSEQ = val,
+        (b"release", ConfValue::Release(val)) => OX_RELEASE = val,
+        (b"guard_pages", ConfValue::Bool(val)) => OX_GUARD_PAGES = val,
+        (b"guard_before", ConfValue::Bool(val)) => OX_GUARD_BEFORE = val,
+        (b"prof_sample", ConfValue::Size(val)) => PROF_SAMPLE.store(val, Ordering::Relaxed),
+        (b"leak_report", ConfValue::Bool(val)) => OX_LEAK_REPORT = val,
+        _ => {}
+    }
+}
+
+// `/etc/oxidalloc.conf`, then `OX_CONF`. The single-purpose variables below still win.
+pub unsafe fn init_conf() {
+    load_conf(|key, val| apply_conf(key, val));
+}
+
 pub unsafe fn init_thp() {
     let key = b"OX_FORCE_THP";
 
     if let Some(val) = get_env_usize(key) {
         if val == 1 {
-            OX_FORCE_THP = true;
+            OX_FORCE_THP.store(true, Ordering::Relaxed);
         }
     }
 }
 
+pub unsafe fn init_background_thread() {
+    match get_env_usize(b"OX_BACKGROUND_THREAD") {
+        Some(0) => OX_BACKGROUND_THREAD = false,
+        Some(1) => OX_BACKGROUND_THREAD = true,
+        _ => {}
+    }
+}
+
+pub unsafe fn init_prof() {
+    if let Some(val) = get_env_usize(b"OX_PROF_SAMPLE") {
+        PROF_SAMPLE.store(val, Ordering::Relaxed);
+    }
+}
+
+pub unsafe fn init_leak_report() {
+    match get_env_usize(b"OX_LEAK_REPORT") {
+        Some(0) => OX_LEAK_REPORT = false,
+        Some(1) => OX_LEAK_REPORT = true,
+        _ => {}
+    }
+
+    if OX_LEAK_REPORT {
+        keep_stderr();
+        // Without a sampling rate every allocation is tracked
+        if PROF_SAMPLE.load(Ordering::Relaxed) == 0 {
+            PROF_SAMPLE.store(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub const MIN_TRIM_THRESHOLD: usize = 1024 * 1024;
+
+fn set_trim_threshold(val: usize) {
+    OX_TRIM_THRESHOLD.store(val.max(MIN_TRIM_THRESHOLD), Ordering::Relaxed);
+}
+
 pub unsafe fn init_threshold() {
     let key = b"OX_TRIM_THRESHOLD";
 
-    if let Some(mut val) = get_env_usize(key) {
-        if val == 0 || val < 1024 * 1024 {
-            val = 1024 * 1024;
-        }
-        OX_TRIM_THRESHOLD.store(val, Ordering::Relaxed);
+    if let Some(val) = get_env_usize(key) {
+        set_trim_threshold(val);
     }
 }
 
+fn set_max_reservation(val: usize) {
+    let next_power_of_two = val
+        .checked_next_power_of_two()
+        .unwrap_or(1024 * 1024 * 1024 * 16)
+        .max(1024 * 1024 * 1024 * 16)
+        .min(1024 * 1024 * 1024 * 1024 * 256);
+
+    OX_MAX_RESERVATION.store(next_power_of_two, Ordering::Relaxed);
+}
+
 pub unsafe fn init_reverse() {
     let key = b"OX_MAX_RESERVATION";
 
     if let Some(val) = get_env_usize(key) {
-        let next_power_of_two = val
-            .checked_next_power_of_two()
-            .unwrap_or(1024 * 1024 * 1024 * 16)
-            .max(1024 * 1024 * 1024 * 16)
-            .min(1024 * 1024 * 1024 * 1024 * 256);
-
-        OX_MAX_RESERVATION.store(next_power_of_two, Ordering::Relaxed);
+        set_max_reservation(val);
     }
 }
 
@@ -221,9 +302,14 @@ pub unsafe fn boot_strap() {
     ONCE.call_once(|| {
         ThreadLocalEngine::get_or_init();
         register_fork_handlers();
+        init_conf();
         init_reverse();
         init_threshold();
         init_thp();
+        init_background_thread();
+        init_prof();
+        init_leak_report();
+        init_trace();
         init_random();
         init_magic();
         init_numa_nodes();
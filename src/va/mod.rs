@@ -2,7 +2,8 @@ use crate::va::bitmap::VA_MAP;
 
 pub mod bitmap;
 pub mod bootstrap;
-mod rng;
+pub mod numa;
+pub(crate) mod rng;
 
 #[must_use]
 pub const fn align_to(size: usize, align: usize) -> usize {
@@ -0,0 +1,269 @@
+// NUMA topology
+// `init_topology` maps every CPU to its node, from sysfs or from `OX_NUMA_TOPOLOGY` to fake one on
+// single node machines ("0-3;4-7" puts CPUs 0-3 on node 0 and 4-7 on node 1). With more than one
+// node, new slabs and big allocations are bound to the calling CPU's node and the ICC steals from
+// same-node shards before remote ones.
+
+use std::{
+    fmt::Write,
+    os::raw::c_void,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use libc::sched_getcpu;
+
+use crate::{
+    MAX_NUMA_NODES,
+    internals::{
+        conf::read_file,
+        env::get_env_bytes,
+        writer::{StackWriter, fd_sink},
+    },
+    sys::memory_system::{BindPolicy, bind_memory},
+};
+
+// Same limit as `get_cpu_count`
+pub const MAX_CPUS: usize = 8192;
+const TOPOLOGY_ENV: &[u8] = b"OX_NUMA_TOPOLOGY";
+
+static mut CPU_NODE: [u16; MAX_CPUS] = [0; MAX_CPUS];
+pub static mut NUMA_NODES: usize = 1;
+pub static mut NUMA_ACTIVE: bool = false;
+
+// Nodes past `MAX_NUMA_NODES` share counters
+pub struct NodeStats {
+    // ICC batches taken from a shard on the caller's node
+    pub local: AtomicUsize,
+    // ICC batches taken from a shard on another node
+    pub remote: AtomicUsize,
+    // Bytes of new slabs and big allocations bound to the node
+    pub bound: AtomicUsize,
+}
+
+pub static NODE_STATS: [NodeStats; MAX_NUMA_NODES] = [const {
+    NodeStats {
+        local: AtomicUsize::new(0),
+        remote: AtomicUsize::new(0),
+        bound: AtomicUsize::new(0),
+    }
+}; MAX_NUMA_NODES];
+
+#[inline(always)]
+pub fn node_stats(node: usize) -> &'static NodeStats {
+    &NODE_STATS[node % MAX_NUMA_NODES]
+}
+
+#[inline(always)]
+pub unsafe fn cpu_node(cpu: usize) -> usize {
+    CPU_NODE[cpu % MAX_CPUS] as usize
+}
+
+pub(crate) fn parse_num(input: &[u8]) -> Option<usize> {
+    if input.is_empty() {
+        return None;
+    }
+
+    input.iter().try_fold(0usize, |acc, &b| {
+        if b.is_ascii_digit() {
+            acc.checked_mul(10)?.checked_add((b - b'0') as usize)
+        } else {
+            None
+        }
+    })
+}
+
+// Kernel cpulist format ("0-3,8,10-11"), `apply` gets every CPU. False on malformed input.
+pub fn parse_cpulist(input: &[u8], mut apply: impl FnMut(usize)) -> bool {
+    for part in input.split(|b| *b == b',') {
+        let part = part.trim_ascii();
+        if part.is_empty() {
+            continue;
+        }
+
+        let (first, last) = match part.iter().position(|b| *b == b'-') {
+            Some(dash) => (parse_num(&part[..dash]), parse_num(&part[dash + 1..])),
+            None => (parse_num(part), parse_num(part)),
+        };
+
+        match (first, last) {
+            (Some(first), Some(last)) if first <= last && last < MAX_CPUS => {
+                (first..=last).for_each(&mut apply);
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+// One cpulist per node, separated by `;`
+fn parse_topology(input: &[u8], mut apply: impl FnMut(usize, usize)) -> Option<usize> {
+    let mut nodes = 0;
+    for (node, list) in input.split(|b| *b == b';').enumerate() {
+        if node >= u16::MAX as usize || !parse_cpulist(list, |cpu| apply(cpu, node)) {
+            return None;
+        }
+        nodes = node + 1;
+    }
+
+    Some(nodes)
+}
+
+// "/sys/devices/system/node/node<N>/cpulist\0" without allocating
+fn node_cpulist_path(node: usize, buf: &mut [u8; 64]) -> &[u8] {
+    const PREFIX: &[u8] = b"/sys/devices/system/node/node";
+    const SUFFIX: &[u8] = b"/cpulist\0";
+
+    let mut digits = [0u8; 20];
+    let mut n = node;
+    let mut count = 0;
+    loop {
+        digits[count] = b'0' + (n % 10) as u8;
+        count += 1;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+
+    let mut len = PREFIX.len();
+    buf[..len].copy_from_slice(PREFIX);
+    for digit in digits[..count].iter().rev() {
+        buf[len] = *digit;
+        len += 1;
+    }
+    buf[len..len + SUFFIX.len()].copy_from_slice(SUFFIX);
+
+    &buf[..len + SUFFIX.len()]
+}
+
+pub unsafe fn init_topology(real_nodes: usize) {
+    let mut fake = get_env_bytes(TOPOLOGY_ENV);
+    if fake.is_some_and(|spec| parse_topology(spec, |_, _| {}).is_none()) {
+        let _ = StackWriter::new(fd_sink(2))
+            .write_str("[OXIDALLOC WARNING] Ignoring invalid OX_NUMA_TOPOLOGY\n");
+        fake = None;
+    }
+
+    let nodes = if let Some(spec) = fake {
+        parse_topology(spec, |cpu, node| CPU_NODE[cpu] = node as u16).unwrap_or(1)
+    } else {
+        let mut path = [0u8; 64];
+        let mut buf = [0u8; 4096];
+        for node in 0..real_nodes {
+            let list = read_file(node_cpulist_path(node, &mut path), &mut buf);
+            parse_cpulist(list, |cpu| CPU_NODE[cpu] = node as u16);
+        }
+        real_nodes
+    };
+
+    NUMA_NODES = nodes.max(1);
+    NUMA_ACTIVE = nodes > 1;
+}
+
+// Node of the CPU the caller runs on, None when there is only one node
+#[inline(always)]
+pub unsafe fn current_node() -> Option<usize> {
+    if !NUMA_ACTIVE {
+        return None;
+    }
+
+    let cpu = sched_getcpu();
+    if cpu < 0 {
+        return None;
+    }
+
+    Some(cpu_node(cpu as usize))
+}
+
+// Shards in the order the ICC steals from them: the caller's own, the rest of its node, then every
+// other node. The flag marks shards on another node.
+pub fn steal_order(
+    cpu: usize,
+    ncpu: usize,
+    node_of: impl Fn(usize) -> usize + Copy,
+) -> impl Iterator<Item = (usize, bool)> {
+    let node = node_of(cpu);
+    [false, true].into_iter().flat_map(move |remote| {
+        (0..ncpu)
+            .map(move |i| (cpu + i) % ncpu)
+            .filter(move |&victim| (node_of(victim) != node) == remote)
+            .map(move |victim| (victim, remote))
+    })
+}
+
+// Prefer the caller's node for pages not faulted in yet, the kernel falls back to other nodes
+// when it runs out of memory
+pub unsafe fn bind_to_current_node(ptr: *mut c_void, len: usize) {
+    let Some(node) = current_node() else {
+        return;
+    };
+
+    if bind_memory(ptr, len, node, BindPolicy::PREFERRED).is_ok() {
+        node_stats(node).bound.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(input: &[u8]) -> Option<Vec<usize>> {
+        let mut cpus = Vec::new();
+        parse_cpulist(input, |cpu| cpus.push(cpu)).then_some(cpus)
+    }
+
+    #[test]
+    fn cpulist_parsing() {
+        assert_eq!(collect(b"0-3,8,10-11\n"), Some(vec![0, 1, 2, 3, 8, 10, 11]));
+        assert_eq!(collect(b" 5 "), Some(vec![5]));
+        assert_eq!(collect(b"\n"), Some(vec![]));
+        assert_eq!(collect(b"3-1"), None);
+        assert_eq!(collect(b"1-"), None);
+        assert_eq!(collect(b"x"), None);
+        assert_eq!(collect(b"8192"), None);
+    }
+
+    #[test]
+    fn topology_parsing() {
+        let mut map = Vec::new();
+        assert_eq!(
+            parse_topology(b"0-1;2,3;", |cpu, node| map.push((cpu, node))),
+            Some(3)
+        );
+        assert_eq!(map, vec![(0, 0), (1, 0), (2, 1), (3, 1)]);
+        assert_eq!(parse_topology(b"0;1-x", |_, _| {}), None);
+    }
+
+    #[test]
+    fn steal_order_prefers_own_node() {
+        // 0-1 and 4-5 on node 0, 2-3 and 6-7 on node 1
+        let node_of = |cpu: usize| (cpu / 2) % 2;
+        let order: Vec<_> = steal_order(5, 8, node_of).collect();
+        assert_eq!(
+            order,
+            vec![
+                (5, false),
+                (0, false),
+                (1, false),
+                (4, false),
+                (6, true),
+                (7, true),
+                (2, true),
+                (3, true),
+            ]
+        );
+
+        let single: Vec<_> = steal_order(0, 1, |_| 0).collect();
+        assert_eq!(single, vec![(0, false)]);
+    }
+
+    #[test]
+    fn sysfs_path() {
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            node_cpulist_path(12, &mut buf),
+            b"/sys/devices/system/node/node12/cpulist\0"
+        );
+    }
+}
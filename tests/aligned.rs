@@ -0,0 +1,107 @@
+use std::{os::raw::c_void, ptr::null_mut};
+
+use oxidalloc::{
+    OX_ALIGN_TAG,
+    abi::{align::posix_memalign, free::free, malloc::malloc_usable_size, realloc::realloc},
+    va::is_ours,
+};
+
+fn aligned(alignment: usize, size: usize) -> *mut c_void {
+    let mut ptr: *mut c_void = null_mut();
+    assert_eq!(unsafe { posix_memalign(&raw mut ptr, alignment, size) }, 0);
+    assert!(!ptr.is_null());
+    assert_eq!((ptr as usize) % alignment, 0);
+    assert!(is_ours(ptr as usize));
+    ptr
+}
+
+fn is_tagged(ptr: *mut c_void) -> bool {
+    let tag_loc = (ptr as usize - size_of::<usize>() * 2) as *const usize;
+    unsafe { std::ptr::read_unaligned(tag_loc) == OX_ALIGN_TAG }
+}
+
+#[test]
+fn native_alignment_skips_tag() {
+    unsafe {
+        for (alignment, size) in [
+            (16, 100),
+            (32, 16),
+            (32, 80),
+            (64, 48),
+            (4096, 100),
+            (4096, 4096),
+            (4096, 1024 * 64),
+            (4096, 1024 * 1024 * 3),
+        ] {
+            let ptr = aligned(alignment, size);
+            assert!(!is_tagged(ptr), "{alignment} / {size} used the tag");
+            assert!(malloc_usable_size(ptr) >= size);
+
+            std::ptr::write_bytes(ptr as *mut u8, 0xAB, size);
+            free(ptr);
+        }
+    }
+}
+
+#[test]
+fn odd_alignments_still_work() {
+    unsafe {
+        for alignment in [64, 128, 256, 512, 1024, 2048, 4096, 8192, 65536] {
+            for size in [1, 64, 100, 1000, 5000, 1024 * 1024 * 4] {
+                let ptr = aligned(alignment, size);
+                std::ptr::write_bytes(ptr as *mut u8, 0xCD, size);
+                assert!(malloc_usable_size(ptr) >= size);
+                free(ptr);
+            }
+        }
+    }
+}
+
+#[test]
+fn page_aligned_realloc_keeps_data() {
+    unsafe {
+        for size in [4096, 1024 * 1024 * 3] {
+            let ptr = aligned(4096, size);
+            let bytes = std::slice::from_raw_parts_mut(ptr as *mut u8, size);
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = (i % 251) as u8;
+            }
+
+            let grown = realloc(ptr, size * 2);
+            assert!(!grown.is_null());
+            let bytes = std::slice::from_raw_parts(grown as *const u8, size);
+            for (i, byte) in bytes.iter().enumerate() {
+                assert_eq!(*byte, (i % 251) as u8);
+            }
+
+            let shrunk = realloc(grown, size / 2);
+            assert!(!shrunk.is_null());
+            assert!(malloc_usable_size(shrunk) >= size / 2);
+            free(shrunk);
+        }
+    }
+}
+
+#[test]
+fn native_blocks_reuse_cleanly() {
+    unsafe {
+        let mut ptrs = Vec::new();
+        for _ in 0..2048 {
+            ptrs.push(aligned(64, 48));
+        }
+        for ptr in ptrs.drain(..) {
+            free(ptr);
+        }
+
+        // Plain mallocs of the same class must still see sane headers
+        for _ in 0..2048 {
+            let ptr = oxidalloc::abi::malloc::malloc(48);
+            assert!(!ptr.is_null());
+            assert_eq!((ptr as usize) % 16, 0);
+            ptrs.push(ptr);
+        }
+        for ptr in ptrs {
+            free(ptr);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{env, fs, hint::black_box, os::raw::c_void, thread, time::Duration};
+
+use oxidalloc::abi::{free::free, malloc::malloc};
+
+mod common;
+
+use common::{ctl_read, run_child_ok};
+
+const CHILD: &str = "OX_BACKGROUND_TEST_CHILD";
+const MIB: usize = 1024 * 1024;
+
+// Threads of the child once the allocator is past its boot allocations
+fn child_threads(test: &str, envs: &[(&str, &str)]) -> usize {
+    run_child_ok(test, (CHILD, "1"), envs)
+        .lines()
+        .find_map(|line| line.strip_prefix("threads: "))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn boot_and_count_threads() {
+    for _ in 0..2048 {
+        unsafe { free(black_box(malloc(16))) };
+    }
+    thread::sleep(Duration::from_millis(50));
+    eprintln!(
+        "threads: {}",
+        fs::read_dir("/proc/self/task").unwrap().count()
+    );
+}
+
+#[test]
+fn no_thread_when_disabled() {
+    if env::var_os(CHILD).is_none() {
+        let with_thread = child_threads("no_thread_when_disabled", &[]);
+        assert_eq!(
+            child_threads("no_thread_when_disabled", &[("OX_BACKGROUND_THREAD", "0")]),
+            with_thread - 1
+        );
+        assert_eq!(
+            child_threads(
+                "no_thread_when_disabled",
+                &[("OX_CONF", "background_thread:0")]
+            ),
+            with_thread - 1
+        );
+        return;
+    }
+
+    boot_and_count_threads();
+}
+
+#[test]
+fn slow_paths_trim_without_thread() {
+    if env::var_os(CHILD).is_none() {
+        run_child_ok(
+            "slow_paths_trim_without_thread",
+            (CHILD, "1"),
+            &[("OX_CONF", "background_thread:0,decay:aggressive")],
+        );
+        return;
+    }
+
+    boot_and_count_threads();
+
+    // Hardened builds never cache big regions
+    #[cfg(not(feature = "hardened-malloc"))]
+    unsafe {
+        free(black_box(malloc(4 * MIB)));
+        let cached = ctl_read::<usize>("stats.big.cached");
+        assert!(cached >= 4 * MIB);
+
+        // The next big free runs a tick first, the old region has aged out by then
+        thread::sleep(Duration::from_millis(2500));
+        free(black_box(malloc(8 * MIB)));
+        let now = ctl_read::<usize>("stats.big.cached");
+        assert!(
+            now >= 8 * MIB && now < cached + 8 * MIB,
+            "{cached} -> {now}"
+        );
+    }
+
+    // Ticks also mark thread caches for ptrim
+    let usage = "stats.class.6.tls_usage";
+    let size = ctl_read::<usize>("stats.class.6.size");
+    let blocks: Vec<_> = (0..64)
+        .map(|_| unsafe { black_box(malloc(size)) })
+        .collect();
+    blocks
+        .iter()
+        .for_each(|&ptr| unsafe { free(ptr as *mut c_void) });
+    assert!(ctl_read::<usize>(usage) > 0);
+
+    thread::sleep(Duration::from_millis(2500));
+    unsafe { free(black_box(malloc(8 * MIB))) };
+    unsafe { free(black_box(malloc(16))) };
+    assert_eq!(ctl_read::<usize>(usage), 0);
+}
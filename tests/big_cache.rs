@@ -0,0 +1,54 @@
+// Hardened builds never cache freed regions
+#![cfg(not(feature = "hardened-malloc"))]
+
+use std::{hint::black_box, os::raw::c_void};
+
+use oxidalloc::abi::{
+    calloc::calloc,
+    free::free,
+    malloc::{malloc, malloc_trim, malloc_usable_size},
+};
+
+mod common;
+
+use common::ctl_read;
+
+const MIB: usize = 1024 * 1024;
+
+// Everything in one test, the cache is shared by the whole process
+#[test]
+fn freed_regions_are_reused_and_trimmed() {
+    unsafe {
+        let ptr = black_box(malloc(8 * MIB)) as *mut u8;
+        assert!(!ptr.is_null());
+        ptr.write_bytes(0xAB, 8 * MIB);
+        free(ptr as *mut c_void);
+        assert!(ctl_read::<usize>("stats.big.cached") >= 8 * MIB);
+
+        // A slightly smaller request fits the same region
+        let again = black_box(malloc(8 * MIB - 4096)) as *mut u8;
+        assert_eq!(again, ptr);
+        assert!(malloc_usable_size(again as *mut c_void) >= 8 * MIB - 4096);
+        assert_eq!(ctl_read::<usize>("stats.big.cached"), 0);
+        free(again as *mut c_void);
+
+        // Reused pages are dirty, calloc has to clear them
+        let zeroed = black_box(calloc(1, 8 * MIB)) as *mut u8;
+        assert_eq!(zeroed, ptr);
+        for off in (0..8 * MIB).step_by(4096) {
+            assert_eq!(zeroed.add(off).read_volatile(), 0, "offset {off}");
+        }
+        free(zeroed as *mut c_void);
+
+        // Too far off in size, a fresh mapping is used instead
+        let bigger = black_box(malloc(16 * MIB));
+        assert!(!bigger.is_null());
+        assert_ne!(bigger as *mut u8, ptr);
+        assert!(ctl_read::<usize>("stats.big.cached") >= 8 * MIB);
+
+        malloc_trim(0);
+        assert_eq!(ctl_read::<usize>("stats.big.cached"), 0);
+        free(bigger);
+        malloc_trim(0);
+    }
+}
@@ -0,0 +1,65 @@
+// Shuffled carve order: `cargo test --features hardened-malloc`
+#![cfg(feature = "hardened-malloc")]
+
+use std::{collections::HashSet, hint::black_box, os::raw::c_void, thread};
+
+use oxidalloc::{
+    HEADER_SIZE,
+    abi::{free::free, malloc::malloc},
+    va::align_to,
+};
+
+const SIZE: usize = 80;
+const COUNT: usize = 1024;
+
+fn carve(size: usize, count: usize) -> Vec<usize> {
+    (0..count)
+        .map(|_| unsafe { black_box(malloc(size)) } as usize)
+        .collect()
+}
+
+#[test]
+fn fresh_slabs_hand_out_blocks_out_of_order() {
+    // A new thread carves its own slabs
+    let addrs = thread::spawn(|| {
+        let addrs = carve(SIZE, COUNT);
+        addrs
+            .iter()
+            .for_each(|&ptr| unsafe { free(ptr as *mut c_void) });
+        addrs
+    })
+    .join()
+    .unwrap();
+
+    let block = align_to(SIZE + HEADER_SIZE, 16);
+    let in_order = addrs.windows(2).filter(|w| w[1] == w[0] + block).count();
+    assert!(
+        in_order < COUNT / 8,
+        "{in_order} of {COUNT} blocks in address order"
+    );
+}
+
+#[test]
+fn shuffled_blocks_stay_distinct() {
+    // The exiting thread leaves its pending slabs to the global list in shuffled windows
+    for size in [16, 48, 512] {
+        thread::spawn(move || unsafe { free(black_box(malloc(size))) })
+            .join()
+            .unwrap();
+
+        let addrs = carve(size, 4096);
+        assert!(addrs.iter().all(|&ptr| ptr != 0));
+        assert_eq!(addrs.iter().collect::<HashSet<_>>().len(), addrs.len());
+
+        unsafe {
+            for (i, &ptr) in addrs.iter().enumerate() {
+                black_box(std::slice::from_raw_parts_mut(ptr as *mut u8, size)).fill(i as u8);
+            }
+            for (i, &ptr) in addrs.iter().enumerate() {
+                let bytes = std::slice::from_raw_parts(ptr as *const u8, size);
+                assert!(bytes.iter().all(|&b| b == i as u8));
+                free(ptr as *mut c_void);
+            }
+        }
+    }
+}
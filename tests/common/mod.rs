@@ -0,0 +1,70 @@
+// Shared by the tests: fresh processes for state read once at boot, crashes and exit reports, and
+// `ox_ctl` access
+#![allow(dead_code)]
+
+use std::{
+    env,
+    os::raw::c_int,
+    process::{Command, Output},
+    ptr::null_mut,
+};
+
+use oxidalloc::abi::ctl::ox_ctl;
+
+// Runs `test` of this test binary again in a child process. `child` is the variable that tells the
+// test it is the child (and what to do), `envs` are set on top of it. Every other `OX_*` variable
+// of the parent is dropped, so the child only sees what the test asked for.
+pub fn run_child(test: &str, child: (&str, &str), envs: &[(&str, &str)]) -> Output {
+    let mut cmd = Command::new(env::current_exe().unwrap());
+    cmd.args([test, "--exact", "--nocapture", "--test-threads=1"]);
+    for (key, _) in env::vars_os() {
+        if key.as_encoded_bytes().starts_with(b"OX_") {
+            cmd.env_remove(key);
+        }
+    }
+
+    cmd.env(child.0, child.1)
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+// `run_child` for a child that has to exit cleanly, returns its stderr
+pub fn run_child_ok(test: &str, child: (&str, &str), envs: &[(&str, &str)]) -> String {
+    let out = run_child(test, child, envs);
+    let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+    assert!(out.status.success(), "child failed: {stderr}");
+    stderr
+}
+
+// Reads `name` and writes `new` if given, through `ox_ctl`. `T` has to be the exact type of the
+// entry, the old value comes back.
+pub fn ctl<T: Copy + Default>(name: &str, new: Option<T>) -> Result<T, c_int> {
+    let name = format!("{name}\0");
+    let mut old = T::default();
+    let mut len = size_of::<T>();
+    let mut new = new;
+    let (newp, newlen) = match new.as_mut() {
+        Some(val) => ((val as *mut T).cast(), size_of::<T>()),
+        None => (null_mut(), 0),
+    };
+    let ret = unsafe {
+        ox_ctl(
+            name.as_ptr().cast(),
+            (&raw mut old).cast(),
+            &mut len,
+            newp,
+            newlen,
+        )
+    };
+    if ret == 0 { Ok(old) } else { Err(ret) }
+}
+
+pub fn ctl_read<T: Copy + Default>(name: &str) -> T {
+    ctl(name, None).unwrap_or_else(|err| panic!("{name}: {err}"))
+}
+
+// Returns the value it replaced
+pub fn ctl_write<T: Copy + Default>(name: &str, val: T) -> T {
+    ctl(name, Some(val)).unwrap_or_else(|err| panic!("{name}: {err}"))
+}
@@ -0,0 +1,51 @@
+use std::env;
+
+mod common;
+
+use common::{ctl_read, run_child_ok};
+
+const CHILD: &str = "OX_CONF_TEST_CHILD";
+
+#[test]
+fn conf_string_applies_at_boot() {
+    if env::var_os(CHILD).is_some() {
+        assert_eq!(ctl_read::<usize>("trim.threshold"), 64 * 1024 * 1024);
+        assert!(ctl_read::<bool>("thp.force"));
+        return;
+    }
+
+    // `OX_CONF` is seen by the first allocation
+    let stderr = run_child_ok(
+        "conf_string_applies_at_boot",
+        (CHILD, "1"),
+        &[(
+            "OX_CONF",
+            "trim_threshold:64M, thp:on,max_reservation:1T,background_thread:0",
+        )],
+    );
+    assert!(!stderr.contains("OXIDALLOC WARNING"), "{stderr}");
+}
+
+#[test]
+fn unknown_keys_warn_once() {
+    if env::var_os(CHILD).is_some() {
+        assert!(ctl_read::<bool>("thp.force"));
+        return;
+    }
+
+    let stderr = run_child_ok(
+        "unknown_keys_warn_once",
+        (CHILD, "1"),
+        &[(
+            "OX_CONF",
+            "colour:blue,thp:1,trim_threshold:lots,background_thread:0",
+        )],
+    );
+    let warnings: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("OXIDALLOC WARNING"))
+        .collect();
+
+    assert_eq!(warnings.len(), 1, "{stderr}");
+    assert!(warnings[0].ends_with("colour:blue, trim_threshold:lots"));
+}
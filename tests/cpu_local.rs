@@ -0,0 +1,47 @@
+// Per-CPU cache mode: `cargo test --features experimental-cpu-local-global`
+#![cfg(feature = "experimental-cpu-local-global")]
+
+use std::{hint::black_box, os::raw::c_void, thread};
+
+use oxidalloc::abi::{free::free, malloc::malloc};
+
+mod common;
+
+use common::ctl_read;
+
+const BLOCKS: usize = 4096;
+
+// Allocates and frees a working set, returns what the thread still caches for the class
+fn churn(class: usize) -> usize {
+    thread::spawn(move || unsafe {
+        let size = ctl_read::<usize>(&format!("stats.class.{class}.size"));
+        let blocks: Vec<_> = (0..BLOCKS)
+            .map(|_| {
+                let ptr = black_box(malloc(size)) as *mut u8;
+                assert!(!ptr.is_null());
+                ptr.write_bytes(0x5A, size);
+                ptr
+            })
+            .collect();
+        blocks.iter().for_each(|&ptr| free(ptr as *mut c_void));
+
+        ctl_read::<usize>(&format!("stats.class.{class}.tls_usage"))
+    })
+    .join()
+    .unwrap()
+}
+
+#[test]
+fn idle_threads_keep_only_a_front_cache() {
+    let class = 3;
+    assert_eq!(ctl_read::<usize>(&format!("stats.class.{class}.size")), 64);
+
+    // Whatever the first thread freed sits in the CPU shard, not in the exited thread
+    assert!(churn(class) <= 32);
+    assert!(ctl_read::<usize>(&format!("stats.class.{class}.icc_usage")) >= BLOCKS - 32);
+
+    // A later thread runs on the same memory, only the test's own bookkeeping needs new slabs
+    let allocated = ctl_read::<usize>("stats.allocated");
+    assert!(churn(class) <= 32);
+    assert!(ctl_read::<usize>("stats.allocated") - allocated < BLOCKS * 64 / 2);
+}
@@ -0,0 +1,141 @@
+use std::{collections::HashMap, env, fs, hint::black_box, io::Write};
+
+use oxidalloc::{
+    HEADER_SIZE, OxHeader,
+    abi::{free::free, malloc::malloc},
+};
+
+mod common;
+
+use common::run_child;
+
+const CHILD: &str = "OX_CRASH_TEST_CHILD";
+
+fn parse(report: &str) -> HashMap<&str, &str> {
+    report
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect()
+}
+
+fn hex(val: &str) -> usize {
+    usize::from_str_radix(val.trim_start_matches("0x"), 16).unwrap()
+}
+
+fn report_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("ox-crash-{}-{name}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+// Dies on purpose, the parent reads the report
+#[test]
+fn crash_child() {
+    let Some(mode) = env::var_os(CHILD) else {
+        return;
+    };
+
+    unsafe {
+        let ptr = black_box(malloc(64));
+        println!("victim={ptr:p}");
+        std::io::stdout().flush().unwrap();
+
+        match mode.to_str().unwrap() {
+            "double_free" => {
+                free(ptr);
+                free(ptr);
+            }
+            "bad_magic" => {
+                let header = ptr.cast::<u8>().sub(HEADER_SIZE).cast::<OxHeader>();
+                (*header).magic = 0x77;
+                free(ptr);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn double_free_writes_a_report() {
+    let path = report_path("double_free");
+    let out = run_child(
+        "crash_child",
+        (CHILD, "double_free"),
+        &[("OX_CRASH_REPORT", &path)],
+    );
+    assert!(!out.status.success());
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let victim = stdout
+        .lines()
+        .find_map(|line| line.split_once("victim=").map(|(_, ptr)| ptr))
+        .unwrap();
+    let report = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    let keys = parse(&report);
+
+    assert_eq!(report.lines().next(), Some("crash.version=1"));
+    assert_eq!(report.lines().last(), Some("crash.end=1"));
+    assert_eq!(keys["error"], "DoubleFree (0x1000)");
+    assert_eq!(keys["code"], "0x1000");
+    assert_eq!(keys["context"], "Pointer is tagged as in_use");
+
+    let header = hex(keys["header.addr"]);
+    assert_eq!(header, hex(victim) - HEADER_SIZE);
+    assert_eq!(keys["header.class"], "3");
+    assert_eq!(keys["header.size"], "64");
+    assert_eq!(keys["header.bytes"].len(), HEADER_SIZE * 2);
+    assert!(keys.contains_key("header.life_time"));
+
+    let (start, end) = (hex(keys["slab.start"]), hex(keys["slab.end"]));
+    assert!(start <= header && header < end, "{report}");
+    assert_eq!(hex(keys["slab.addr"]), start);
+    assert_eq!(keys["slab.block_offset"], "0");
+
+    assert!(keys.contains_key("thread.tid"));
+    assert!(keys.contains_key("thread.owner"));
+    assert!(keys.contains_key("icc.class.3.total") || keys.contains_key("icc.ready"));
+
+    let frames: usize = keys["frames"].parse().unwrap();
+    assert!(frames >= 2, "{report}");
+    assert!(keys.contains_key("frame.0"));
+    assert!(report.lines().any(|line| line.starts_with("map=")));
+}
+
+#[test]
+fn corrupted_header_is_dumped() {
+    let path = report_path("bad_magic");
+    let out = run_child(
+        "crash_child",
+        (CHILD, "bad_magic"),
+        &[("OX_CRASH_REPORT", &path)],
+    );
+    assert!(!out.status.success());
+
+    let report = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    let keys = parse(&report);
+
+    assert_eq!(keys["code"], "0x100b");
+    assert_eq!(keys["header.magic"], "0x77");
+    assert_eq!(keys["header.class"], "3");
+    assert!(keys.contains_key("slab.start"), "{report}");
+}
+
+#[test]
+fn report_goes_to_a_descriptor() {
+    let out = run_child(
+        "crash_child",
+        (CHILD, "double_free"),
+        &[("OX_CRASH_REPORT", "1")],
+    );
+    assert!(!out.status.success());
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stdout.contains("crash.version=1"), "{stdout}");
+    assert!(stdout.contains("crash.end=1"), "{stdout}");
+    assert!(!stderr.contains("crash.version"), "{stderr}");
+    // The one line message stays on stderr
+    assert!(stderr.contains("[OXIDALLOC FATAL] DoubleFree"), "{stderr}");
+}
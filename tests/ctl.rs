@@ -0,0 +1,99 @@
+use std::{
+    hint::black_box,
+    os::raw::{c_int, c_void},
+    ptr::null_mut,
+};
+
+use oxidalloc::abi::{ctl::ox_ctl, free::free, malloc::malloc};
+
+mod common;
+
+use common::{ctl, ctl_read, ctl_write};
+
+const ENOENT: c_int = 2;
+const EPERM: c_int = 1;
+const EINVAL: c_int = 22;
+
+#[test]
+fn unknown_and_malformed_names() {
+    assert_eq!(ctl::<usize>("trim", None), Err(ENOENT));
+    assert_eq!(ctl::<usize>("trim.threshold.extra", None), Err(ENOENT));
+    assert_eq!(ctl::<usize>("stats.class.999.icc_usage", None), Err(ENOENT));
+    assert_eq!(ctl::<usize>("stats.class.x.icc_usage", None), Err(ENOENT));
+    assert_eq!(ctl::<usize>("a.b.c.d.e.f", None), Err(ENOENT));
+    assert_eq!(ctl::<u8>("trim.threshold", None), Err(EINVAL));
+    assert_eq!(ctl("stats.allocated", Some(1usize)), Err(EPERM));
+    assert_eq!(ctl("trim.threshold", Some(1u8)), Err(EINVAL));
+    assert_eq!(ctl("thp.force", Some(2u8)), Err(EINVAL));
+}
+
+#[test]
+fn trim_threshold_round_trip() {
+    let old: usize = ctl_read("trim.threshold");
+
+    ctl_write("trim.threshold", 64 * 1024 * 1024usize);
+    assert_eq!(ctl_read::<usize>("trim.threshold"), 64 * 1024 * 1024);
+
+    // Clamped like OX_TRIM_THRESHOLD
+    ctl_write("trim.threshold", 1usize);
+    assert_eq!(ctl_read::<usize>("trim.threshold"), 1024 * 1024);
+
+    ctl_write("trim.threshold", old);
+}
+
+#[test]
+fn thp_toggle_between_malloc_and_free() {
+    let before: bool = ctl_read("thp.force");
+
+    ctl_write("thp.force", true);
+    let forced = black_box(unsafe { malloc(3 * 1024 * 1024) });
+    ctl_write("thp.force", false);
+    let plain = black_box(unsafe { malloc(3 * 1024 * 1024) });
+    assert!(!ctl_read::<bool>("thp.force"));
+
+    unsafe {
+        (forced as *mut u8).write_bytes(1, 3 * 1024 * 1024);
+        (plain as *mut u8).write_bytes(2, 3 * 1024 * 1024);
+        free(forced);
+        free(plain);
+    }
+
+    ctl_write("thp.force", before);
+}
+
+#[test]
+fn class_stats_and_tcache_flush() {
+    let size: usize = ctl_read("stats.class.19.size");
+    assert_eq!(size, 2560);
+
+    let ptrs: Vec<*mut c_void> = (0..256).map(|_| unsafe { malloc(2560) }).collect();
+    for ptr in ptrs {
+        unsafe { free(ptr) };
+    }
+
+    let cached: usize = ctl_read("stats.class.19.tls_usage");
+    assert!(cached > 0);
+    let icc_before: usize = ctl_read("stats.class.19.icc_usage");
+
+    let name = b"thread.tcache.flush\0";
+    assert_eq!(
+        unsafe { ox_ctl(name.as_ptr().cast(), null_mut(), null_mut(), null_mut(), 0) },
+        0
+    );
+
+    assert_eq!(ctl_read::<usize>("stats.class.19.tls_usage"), 0);
+    let icc_after: usize = ctl_read("stats.class.19.icc_usage");
+    assert!(icc_after >= icc_before + cached);
+
+    let ptr = unsafe { malloc(2560) };
+    assert!(!ptr.is_null());
+    unsafe { free(ptr) };
+}
+
+#[test]
+fn arena_trim_reports_released_bytes() {
+    let released: usize = ctl_read("arena.trim");
+    assert!(released < usize::MAX);
+    assert!(ctl_read::<usize>("stats.allocated") > 0);
+    assert!(ctl::<u32>("version", None).is_ok());
+}
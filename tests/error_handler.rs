@@ -0,0 +1,192 @@
+use std::{
+    env,
+    ffi::CStr,
+    hint::black_box,
+    os::{
+        raw::{c_char, c_void},
+        unix::process::ExitStatusExt,
+    },
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use oxidalloc::{
+    HEADER_SIZE, OxidallocError,
+    abi::{
+        error::{ErrorPolicy, ox_set_error_handler},
+        free::free,
+        malloc::malloc,
+    },
+    va::bitmap::VA_MAP,
+};
+
+mod common;
+
+use common::{ctl, ctl_write, run_child, run_child_ok};
+
+const CHILD: &str = "OX_ERROR_TEST_CHILD";
+
+static SEEN_CODE: AtomicU32 = AtomicU32::new(0);
+static SEEN_PTR: AtomicUsize = AtomicUsize::new(0);
+static SEEN_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Must not allocate, it runs inside the allocator
+unsafe extern "C" fn record(code: u32, ptr: *mut c_void, context: *const c_char) {
+    SEEN_CODE.store(code, Ordering::Relaxed);
+    SEEN_PTR.store(ptr as usize, Ordering::Relaxed);
+    SEEN_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    let context = unsafe { CStr::from_ptr(context) }.to_bytes();
+    unsafe {
+        libc::write(2, b"handler: ".as_ptr().cast(), 9);
+        libc::write(2, context.as_ptr().cast(), context.len());
+        libc::write(2, b"\n".as_ptr().cast(), 1);
+    }
+}
+
+#[test]
+fn default_policies() {
+    assert_eq!(
+        ctl::<u8>("error.policy.double_free", None),
+        Ok(ErrorPolicy::Abort as u8)
+    );
+    assert_eq!(
+        ctl::<u8>("error.policy.out_of_memory", None),
+        Ok(ErrorPolicy::ReturnNull as u8)
+    );
+    assert_eq!(
+        ctl::<u8>("error.policy.va_exhausted", None),
+        Ok(ErrorPolicy::ReturnNull as u8)
+    );
+    assert_eq!(
+        ctl::<u8>("error.policy.no_such_error", None),
+        Err(libc::ENOENT)
+    );
+    assert_eq!(
+        ctl::<u8>("error.policy.double_free", Some(3)),
+        Err(libc::EINVAL)
+    );
+
+    for err in OxidallocError::ALL {
+        assert!(
+            ctl::<u8>(&format!("error.policy.{}", err.name()), None).is_ok(),
+            "{}",
+            err.name()
+        );
+    }
+}
+
+#[test]
+fn handler_replaces_the_previous_one() {
+    unsafe {
+        assert!(ox_set_error_handler(Some(record)).is_none());
+        let old = ox_set_error_handler(None).unwrap();
+        assert_eq!(old as usize, record as *const () as usize);
+        assert!(ox_set_error_handler(None).is_none());
+    }
+}
+
+#[test]
+fn double_free_child() {
+    let Some(mode) = env::var_os(CHILD) else {
+        return;
+    };
+
+    unsafe {
+        ox_set_error_handler(Some(record));
+        if mode == "continue" {
+            assert_eq!(
+                ctl_write("error.policy.double_free", ErrorPolicy::Continue as u8),
+                0
+            );
+        }
+
+        let ptr = black_box(malloc(64));
+        free(ptr);
+        free(ptr);
+
+        // Only reached when the policy lets the process go on
+        assert_eq!(SEEN_CODE.load(Ordering::Relaxed), 0x1000);
+        assert_eq!(SEEN_PTR.load(Ordering::Relaxed), ptr as usize - HEADER_SIZE);
+
+        // The skipped free left the heap usable
+        let again = black_box(malloc(64));
+        assert!(!again.is_null());
+        free(again);
+    }
+}
+
+#[test]
+fn double_free_continues_under_policy() {
+    let stderr = run_child_ok("double_free_child", (CHILD, "continue"), &[]);
+    assert!(
+        stderr.contains("handler: Pointer is tagged as in_use"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("[OXIDALLOC ERROR] DoubleFree"), "{stderr}");
+}
+
+#[test]
+fn handler_runs_before_abort() {
+    let out = run_child("double_free_child", (CHILD, "abort"), &[]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.signal(), Some(libc::SIGABRT), "{stderr}");
+
+    let handler = stderr.find("handler: Pointer is tagged as in_use").unwrap();
+    let fatal = stderr.find("[OXIDALLOC FATAL] DoubleFree").unwrap();
+    assert!(handler < fatal, "{stderr}");
+}
+
+fn vm_size() -> libc::rlim_t {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmSize:"))
+        .and_then(|val| val.trim().strip_suffix("kB"))
+        .unwrap();
+    kb.trim().parse::<libc::rlim_t>().unwrap() * 1024
+}
+
+#[test]
+fn slab_oom_returns_enomem() {
+    if env::var_os(CHILD).is_none() {
+        let stderr = run_child_ok("slab_oom_returns_enomem", (CHILD, "1"), &[]);
+        assert!(
+            stderr.contains("handler: Cannot carve a new slab"),
+            "{stderr}"
+        );
+        // The default policy fails quietly
+        assert!(!stderr.contains("[OXIDALLOC"), "{stderr}");
+        return;
+    }
+
+    unsafe {
+        ox_set_error_handler(Some(record));
+
+        // No new VA can be reserved, then everything reserved so far is taken
+        let mut old = std::mem::zeroed::<libc::rlimit>();
+        assert_eq!(libc::getrlimit(libc::RLIMIT_AS, &mut old), 0);
+        let capped = libc::rlimit {
+            rlim_cur: vm_size(),
+            rlim_max: old.rlim_max,
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_AS, &capped), 0);
+
+        let va = &raw mut VA_MAP;
+        let mut size = 1 << 30;
+        while size >= 4096 {
+            while (*va).alloc(size).is_some() {}
+            size /= 2;
+        }
+
+        *libc::__errno_location() = 0;
+        let ptr = black_box(malloc(3072));
+        let errno = *libc::__errno_location();
+
+        assert_eq!(libc::setrlimit(libc::RLIMIT_AS, &old), 0);
+
+        assert!(ptr.is_null());
+        assert_eq!(errno, libc::ENOMEM);
+        assert_eq!(SEEN_CODE.load(Ordering::Relaxed), 0x1004);
+        assert_eq!(SEEN_COUNT.load(Ordering::Relaxed), 1);
+    }
+}
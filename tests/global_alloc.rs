@@ -0,0 +1,81 @@
+use std::{collections::HashMap, hint::black_box, thread};
+
+use oxidalloc::Oxidalloc;
+
+#[global_allocator]
+static GLOBAL: Oxidalloc = Oxidalloc;
+
+#[repr(align(64))]
+struct CacheLine([u8; 64]);
+
+#[repr(align(4096))]
+struct Page([u8; 4096]);
+
+#[test]
+fn vec_growth_keeps_contents() {
+    let mut v: Vec<u64> = Vec::new();
+    for i in 0..1_000_000u64 {
+        v.push(i);
+    }
+
+    for (i, val) in v.iter().enumerate() {
+        assert_eq!(*val, i as u64);
+    }
+
+    v.truncate(10);
+    v.shrink_to_fit();
+    assert_eq!(v, (0..10).collect::<Vec<u64>>());
+}
+
+#[test]
+fn over_aligned_boxes() {
+    let mut lines = Vec::new();
+    let mut pages = Vec::new();
+    for i in 0..256 {
+        let line = Box::new(CacheLine([i as u8; 64]));
+        assert_eq!((&*line as *const CacheLine as usize) % 64, 0);
+        lines.push(line);
+
+        let page = Box::new(Page([i as u8; 4096]));
+        assert_eq!((&*page as *const Page as usize) % 4096, 0);
+        pages.push(page);
+    }
+
+    for (i, (line, page)) in lines.iter().zip(pages.iter()).enumerate() {
+        assert!(line.0.iter().all(|b| *b == i as u8));
+        assert!(page.0.iter().all(|b| *b == i as u8));
+    }
+}
+
+#[test]
+fn zeroed_after_reuse() {
+    for _ in 0..64 {
+        let mut dirty = vec![0xAAu8; 4096];
+        black_box(&mut dirty);
+        drop(dirty);
+
+        let clean = vec![0u8; 4096];
+        assert!(clean.iter().all(|b| *b == 0));
+    }
+}
+
+#[test]
+fn cross_thread_collections() {
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            thread::spawn(move || {
+                let mut map = HashMap::new();
+                for i in 0..10_000 {
+                    map.insert(i, format!("{}-{}", t, i));
+                }
+                map
+            })
+        })
+        .collect();
+
+    for (t, handle) in handles.into_iter().enumerate() {
+        let map = handle.join().unwrap();
+        assert_eq!(map.len(), 10_000);
+        assert_eq!(map[&42], format!("{}-42", t));
+    }
+}
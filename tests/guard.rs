@@ -0,0 +1,153 @@
+use std::{
+    env,
+    hint::black_box,
+    os::{raw::c_void, unix::process::ExitStatusExt},
+    process::ExitStatus,
+    ptr::write_volatile,
+};
+
+use oxidalloc::{
+    HEADER_SIZE,
+    abi::{free::free, malloc::malloc, realloc::realloc},
+    va::bitmap::VA_MAP,
+};
+
+mod common;
+
+use common::run_child;
+
+const CHILD: &str = "OX_GUARD_TEST_CHILD";
+const PAGE: usize = 4096;
+const BIG: usize = 4 * 1024 * 1024 + 100;
+
+fn run_guard_child(mode: &str, conf: &str) -> ExitStatus {
+    run_child("guard_child", (CHILD, mode), &[("OX_CONF", conf)]).status
+}
+
+fn map_end(ptr: *mut c_void, size: usize) -> *mut u8 {
+    ((ptr as usize + size).next_multiple_of(PAGE)) as *mut u8
+}
+
+fn map_start(ptr: *mut c_void) -> *mut u8 {
+    ((ptr as usize - HEADER_SIZE) & !(PAGE - 1)) as *mut u8
+}
+
+unsafe fn fill(ptr: *mut c_void, size: usize) {
+    let bytes = unsafe { std::slice::from_raw_parts_mut(ptr as *mut u8, size) };
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (i % 251) as u8;
+    }
+}
+
+unsafe fn check(ptr: *mut c_void, size: usize) {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, size) };
+    for (i, byte) in bytes.iter().enumerate() {
+        assert_eq!(*byte, (i % 251) as u8);
+    }
+}
+
+// Faults on purpose in the modes the parent expects to die
+#[test]
+fn guard_child() {
+    let Some(mode) = env::var_os(CHILD) else {
+        return;
+    };
+
+    unsafe {
+        let ptr = black_box(malloc(BIG));
+        assert!(!ptr.is_null());
+        fill(ptr, BIG);
+
+        match mode.to_str().unwrap() {
+            "overflow" => {
+                // The slack up to the page end is still ours
+                let end = map_end(ptr, BIG);
+                write_volatile(end.sub(1), 1);
+                write_volatile(end, 1);
+            }
+            "underflow" => {
+                write_volatile(map_start(ptr).sub(1), 1);
+            }
+            "grow" => {
+                let grown = realloc(ptr, BIG * 2);
+                assert!(!grown.is_null());
+                check(grown, BIG);
+                write_volatile(map_end(grown, BIG * 2), 1);
+            }
+            "shrink" => {
+                let shrunk = realloc(ptr, BIG / 2);
+                assert!(!shrunk.is_null());
+                check(shrunk, BIG / 2);
+                write_volatile(map_end(shrunk, BIG / 2), 1);
+            }
+            "claimed" => {
+                // Growing the reservation over the guard fails, nobody else can be given it
+                let base = map_start(ptr) as usize;
+                let total = map_end(ptr, BIG) as usize - base;
+                let va = &raw const VA_MAP;
+                assert!((*va).realloc_inplace(base, total, total + PAGE).is_none());
+                free(ptr);
+                return;
+            }
+            "churn" => {
+                free(ptr);
+                for round in 0..64 {
+                    let size = BIG + round * 3 * PAGE;
+                    let ptr = black_box(malloc(size));
+                    fill(ptr, size);
+
+                    let grown = realloc(ptr, size * 2);
+                    assert!(!grown.is_null());
+                    check(grown, size);
+                    fill(grown, size * 2);
+
+                    let shrunk = realloc(grown, size / 2);
+                    assert!(!shrunk.is_null());
+                    check(shrunk, size / 2);
+                    free(shrunk);
+                }
+                return;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    panic!("no guard page hit");
+}
+
+#[test]
+fn overflow_hits_the_guard() {
+    let status = run_guard_child("overflow", "guard_pages:1");
+    assert_eq!(status.signal(), Some(libc::SIGSEGV), "{status}");
+}
+
+#[test]
+fn guard_stays_reserved() {
+    assert!(run_guard_child("claimed", "guard_pages:1").success());
+}
+
+#[test]
+fn underflow_needs_guard_before() {
+    let status = run_guard_child("underflow", "guard_pages:1,guard_before:1");
+    assert_eq!(status.signal(), Some(libc::SIGSEGV), "{status}");
+}
+
+#[test]
+fn guard_follows_realloc() {
+    for mode in ["grow", "shrink"] {
+        let status = run_guard_child(mode, "guard_pages:1");
+        assert_eq!(status.signal(), Some(libc::SIGSEGV), "{mode}: {status}");
+    }
+}
+
+#[test]
+fn guarded_churn_stays_intact() {
+    let status = run_guard_child("churn", "guard_pages:1,guard_before:1");
+    assert!(status.success(), "{status}");
+}
+
+#[cfg(feature = "hardened-malloc")]
+#[test]
+fn hardened_builds_guard_by_default() {
+    assert!(run_guard_child("claimed", "").success());
+}
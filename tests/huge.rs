@@ -0,0 +1,123 @@
+use std::{hint::black_box, os::raw::c_void};
+
+use oxidalloc::abi::{
+    calloc::calloc,
+    free::free,
+    malloc::{malloc, malloc_usable_size},
+    realloc::realloc,
+};
+
+const GIB: usize = 1024 * 1024 * 1024;
+
+// Only a few pages are touched, the mappings stay mostly untouched VA
+fn touch(ptr: *mut c_void, size: usize, val: u8) {
+    let p = ptr as *mut u8;
+    for off in [0, size / 2, size - 1] {
+        unsafe { p.add(off).write_volatile(val) };
+    }
+}
+
+fn check(ptr: *mut c_void, size: usize, val: u8) {
+    let p = ptr as *mut u8;
+    for off in [0, size / 2, size - 1] {
+        assert_eq!(unsafe { p.add(off).read_volatile() }, val, "offset {off}");
+    }
+}
+
+#[test]
+fn allocations_past_a_segment() {
+    for size in [5 * GIB, 8 * GIB, 17 * GIB] {
+        unsafe {
+            let ptr = black_box(malloc(size));
+            assert!(!ptr.is_null(), "{size} bytes");
+            assert!(malloc_usable_size(ptr) >= size);
+
+            touch(ptr, size, 0x5A);
+            check(ptr, size, 0x5A);
+            free(ptr);
+        }
+    }
+}
+
+#[test]
+fn huge_realloc_keeps_contents() {
+    unsafe {
+        let ptr = black_box(malloc(3 * GIB));
+        assert!(!ptr.is_null());
+        touch(ptr, 3 * GIB, 7);
+
+        let grown = realloc(ptr, 9 * GIB);
+        assert!(!grown.is_null());
+        assert!(malloc_usable_size(grown) >= 9 * GIB);
+        check(grown, 3 * GIB, 7);
+        touch(grown, 9 * GIB, 9);
+
+        let shrunk = realloc(grown, 6 * GIB);
+        assert!(!shrunk.is_null());
+        assert!(malloc_usable_size(shrunk) >= 6 * GIB);
+        assert_eq!((shrunk as *mut u8).read_volatile(), 9);
+
+        let small = realloc(shrunk, 4096);
+        assert!(!small.is_null());
+        assert_eq!((small as *mut u8).read_volatile(), 9);
+        free(small);
+    }
+}
+
+#[test]
+fn huge_calloc_is_zero() {
+    unsafe {
+        let ptr = black_box(calloc(2, 4 * GIB));
+        assert!(!ptr.is_null());
+        check(ptr, 8 * GIB, 0);
+        free(ptr);
+    }
+}
+
+#[test]
+fn absurd_sizes_fail_cleanly() {
+    unsafe {
+        assert!(black_box(malloc(black_box(usize::MAX))).is_null());
+        assert!(black_box(malloc(black_box(isize::MAX as usize + 1))).is_null());
+
+        let ptr = malloc(64);
+        assert!(black_box(realloc(ptr, black_box(usize::MAX - 4096))).is_null());
+        free(ptr);
+    }
+}
+
+#[test]
+fn blocked_realloc_moves_pages() {
+    const MIB: usize = 1024 * 1024;
+
+    unsafe {
+        let ptr = black_box(malloc(64 * MIB)) as *mut u8;
+        assert!(!ptr.is_null());
+        // Usually lands right behind `ptr`, so growing in place is not possible
+        let neighbour = black_box(malloc(64 * MIB));
+        assert!(!neighbour.is_null());
+
+        for page in (0..64 * MIB).step_by(4096) {
+            ptr.add(page).write_volatile((page / 4096) as u8);
+        }
+
+        let grown = realloc(ptr as *mut c_void, GIB) as *mut u8;
+        assert!(!grown.is_null());
+        assert!(malloc_usable_size(grown as *mut c_void) >= GIB);
+        for page in (0..64 * MIB).step_by(4096) {
+            assert_eq!(grown.add(page).read_volatile(), (page / 4096) as u8);
+        }
+        touch(grown as *mut c_void, GIB, 3);
+        check(grown as *mut c_void, GIB, 3);
+
+        // The range it moved away from is usable again
+        let again = black_box(malloc(64 * MIB));
+        assert!(!again.is_null());
+        touch(again, 64 * MIB, 4);
+        check(again, 64 * MIB, 4);
+
+        free(again);
+        free(neighbour);
+        free(grown as *mut c_void);
+    }
+}
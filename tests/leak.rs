@@ -0,0 +1,204 @@
+use std::{
+    env,
+    hint::black_box,
+    os::raw::c_void,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use oxidalloc::{
+    abi::{free::free, malloc::malloc},
+    slab::{SIZE_CLASSES, match_size_class},
+};
+
+mod common;
+
+use common::run_child;
+
+const CHILD: &str = "OX_LEAK_TEST_CHILD";
+const SIZE: usize = 100;
+const COUNT: usize = 10;
+const FORKS: usize = 16;
+// Allocations of the trim thread would show up in the report
+const NO_THREAD: (&str, &str) = ("OX_BACKGROUND_THREAD", "0");
+
+// Loops over a slice, a fixed count gets unrolled into a call site per iteration
+#[inline(never)]
+fn leak_site(out: &mut [*mut c_void]) {
+    for ptr in out {
+        *ptr = unsafe { black_box(malloc(SIZE)) };
+    }
+}
+
+#[inline(never)]
+fn freed_site(out: &mut [*mut c_void]) {
+    for ptr in out.iter_mut() {
+        *ptr = unsafe { black_box(malloc(SIZE)) };
+    }
+    for &ptr in out.iter() {
+        unsafe { free(ptr) };
+    }
+}
+
+// Leaks from a fresh process, the parent reads the report it prints at exit
+#[test]
+fn leak_child() {
+    let Some(mode) = env::var_os(CHILD) else {
+        return;
+    };
+
+    match mode.to_str().unwrap() {
+        "leak" => {
+            println!(
+                "leak_site={:#x} freed_site={:#x}",
+                leak_site as *const () as usize, freed_site as *const () as usize
+            );
+            let mut ptrs = [null_mut(); COUNT];
+            leak_site(&mut ptrs);
+            freed_site(&mut [null_mut(); COUNT]);
+        }
+        "fork" => unsafe {
+            // Another thread keeps the profiler lock busy while the children fork and exit
+            static STOP: AtomicBool = AtomicBool::new(false);
+            let busy = thread::spawn(|| {
+                while !STOP.load(Ordering::Relaxed) {
+                    free(black_box(malloc(SIZE)));
+                }
+            });
+
+            for _ in 0..FORKS {
+                let pid = libc::fork();
+                assert!(pid >= 0);
+                if pid == 0 {
+                    black_box(malloc(SIZE));
+                    libc::exit(0);
+                }
+
+                let mut status = 0;
+                assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+                assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+            }
+
+            STOP.store(true, Ordering::Relaxed);
+            busy.join().unwrap();
+        },
+        _ => unreachable!(),
+    }
+}
+
+// `Leak of ...` header and the frame addresses under it
+fn leaks(stderr: &str) -> Vec<(&str, Vec<u64>)> {
+    let mut leaks: Vec<(&str, Vec<u64>)> = Vec::new();
+    for line in stderr.lines() {
+        if line.starts_with("Leak of ") {
+            leaks.push((line, Vec::new()));
+        } else if let Some((_, frame)) = line.trim_start().split_once(" 0x")
+            && let Some((_, last)) = leaks.last_mut()
+        {
+            let hex = frame.split_whitespace().next().unwrap();
+            last.push(u64::from_str_radix(hex, 16).unwrap());
+        }
+    }
+    leaks
+}
+
+fn site(stdout: &str, name: &str) -> u64 {
+    let (_, rest) = stdout.split_once(&format!("{name}=0x")).unwrap();
+    let hex = rest.split_whitespace().next().unwrap();
+    u64::from_str_radix(hex, 16).unwrap()
+}
+
+// The leak whose stack runs through the function at `site`. Functions are laid out in any order,
+// so it is the one with a frame closest past the start.
+fn leak_from<'a>(leaks: &'a [(&str, Vec<u64>)], site: u64) -> Option<&'a str> {
+    let distance = |frames: &Vec<u64>| {
+        frames
+            .iter()
+            .map(|addr| addr.wrapping_sub(site))
+            .min()
+            .unwrap_or(u64::MAX)
+    };
+    leaks
+        .iter()
+        .min_by_key(|(_, frames)| distance(frames))
+        .filter(|(_, frames)| distance(frames) < 512)
+        .map(|(header, _)| *header)
+}
+
+#[test]
+fn leaks_are_grouped_by_stack_and_class() {
+    let out = run_child(
+        "leak_child",
+        (CHILD, "leak"),
+        &[NO_THREAD, ("OX_LEAK_REPORT", "1")],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+
+    assert!(stderr.contains("oxidalloc: memory still live at exit\n"));
+    assert!(stderr.contains("SUMMARY: oxidalloc: "));
+
+    // Every allocation is tracked, the freed ones are gone
+    let leaks = leaks(&stderr);
+    assert_eq!(
+        leak_from(&leaks, site(&stdout, "leak_site")),
+        Some(
+            format!(
+                "Leak of {} byte(s) in {COUNT} object(s) allocated from:",
+                SIZE * COUNT
+            )
+            .as_str()
+        )
+    );
+    assert_eq!(leak_from(&leaks, site(&stdout, "freed_site")), None);
+
+    // Frames name the binary they are in
+    let exe = env::current_exe().unwrap();
+    assert!(stderr.contains(&format!(" ({}+0x", exe.to_str().unwrap())));
+
+    let class = match_size_class(SIZE).unwrap();
+    let (_, line) = stderr
+        .split_once(&format!(
+            "    class {class} ({} bytes): ",
+            SIZE_CLASSES[class]
+        ))
+        .unwrap();
+    let objects: usize = line.split_whitespace().next().unwrap().parse().unwrap();
+    assert!(objects >= COUNT, "{line}");
+}
+
+#[test]
+fn sampled_report_is_marked_as_estimated() {
+    let out = run_child(
+        "leak_child",
+        (CHILD, "leak"),
+        &[NO_THREAD, ("OX_LEAK_REPORT", "1"), ("OX_PROF_SAMPLE", "64")],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(stderr.contains("(sampled every 64 bytes, estimated)"));
+}
+
+#[test]
+fn no_report_by_default() {
+    let out = run_child("leak_child", (CHILD, "leak"), &[NO_THREAD]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(!stderr.contains("oxidalloc: memory still live"));
+}
+
+#[test]
+fn forked_children_report_without_deadlock() {
+    let out = run_child(
+        "leak_child",
+        (CHILD, "fork"),
+        &[NO_THREAD, ("OX_LEAK_REPORT", "1")],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+
+    // One report per child and one for the parent
+    assert_eq!(stderr.matches("SUMMARY: oxidalloc: ").count(), FORKS + 1);
+}
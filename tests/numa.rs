@@ -0,0 +1,75 @@
+use std::{env, hint::black_box};
+
+use oxidalloc::abi::{ctl::ox_thread_cache_flush, free::free, malloc::malloc};
+
+mod common;
+
+use common::{ctl_read, run_child_ok};
+
+const CHILD: &str = "OX_NUMA_TEST_CHILD";
+const MIB: usize = 1024 * 1024;
+
+#[test]
+fn fake_topology_binds_and_counts() {
+    if env::var_os(CHILD).is_none() {
+        // The topology is read once at boot, so every case runs in a fresh process
+        let stderr = run_child_ok(
+            "fake_topology_binds_and_counts",
+            (CHILD, "1"),
+            &[("OX_NUMA_TOPOLOGY", "0;1-3")],
+        );
+        assert!(!stderr.contains("OXIDALLOC WARNING"), "{stderr}");
+        return;
+    }
+
+    // The test harness may run anywhere, pin to CPU 0 so node 0 is the local one
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(0, &mut set);
+        assert_eq!(libc::sched_setaffinity(0, size_of_val(&set), &set), 0);
+    }
+
+    assert_eq!(ctl_read::<usize>("stats.numa.nodes"), 2);
+    let bound = ctl_read::<usize>("stats.numa.0.bound");
+    let local = ctl_read::<usize>("stats.numa.0.local");
+
+    unsafe {
+        let big = black_box(malloc(8 * MIB));
+        assert!(!big.is_null());
+        assert!(ctl_read::<usize>("stats.numa.0.bound") >= bound + 8 * MIB);
+
+        // New slabs are bound too, and blocks flushed to the ICC come back from the local shard
+        let blocks: Vec<_> = (0..64).map(|_| black_box(malloc(3000))).collect();
+        blocks.iter().for_each(|&ptr| free(ptr));
+        ox_thread_cache_flush();
+
+        let again = black_box(malloc(3000));
+        assert!(!again.is_null());
+        assert!(ctl_read::<usize>("stats.numa.0.local") > local);
+        assert_eq!(ctl_read::<usize>("stats.numa.0.remote"), 0);
+
+        free(again);
+        free(big);
+    }
+
+    assert_eq!(ctl_read::<usize>("stats.numa.1.remote"), 0);
+}
+
+#[test]
+fn invalid_topology_is_ignored() {
+    if env::var_os(CHILD).is_none() {
+        let stderr = run_child_ok(
+            "invalid_topology_is_ignored",
+            (CHILD, "1"),
+            &[("OX_NUMA_TOPOLOGY", "0-1;x")],
+        );
+        assert!(
+            stderr.contains("[OXIDALLOC WARNING] Ignoring invalid OX_NUMA_TOPOLOGY"),
+            "{stderr}"
+        );
+        return;
+    }
+
+    // Falls back to the real machine
+    assert!(ctl_read::<usize>("stats.numa.nodes") >= 1);
+}
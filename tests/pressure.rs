@@ -0,0 +1,60 @@
+use std::{env, fs, hint::black_box, thread, time::Duration};
+
+use oxidalloc::abi::{free::free, malloc::malloc};
+
+mod common;
+
+use common::{ctl_read, run_child};
+
+const CHILD: &str = "OX_PRESSURE_TEST_CHILD";
+
+#[test]
+fn cgroup_limit_and_psi_trigger() {
+    if env::var_os(CHILD).is_none() {
+        // Stand-ins for the cgroup directory and /proc/pressure/memory
+        let dir = env::temp_dir().join(format!("ox-pressure-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("memory.max"), "1000\n").unwrap();
+        fs::write(dir.join("memory.current"), "950\n").unwrap();
+        let psi = dir.join("psi");
+        fs::write(&psi, "").unwrap();
+
+        let out = run_child(
+            "cgroup_limit_and_psi_trigger",
+            (CHILD, "1"),
+            &[
+                ("OX_CGROUP_DIR", dir.to_str().unwrap()),
+                ("OX_PSI_PATH", psi.to_str().unwrap()),
+            ],
+        );
+
+        let trigger = fs::read(&psi).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            out.status.success(),
+            "child failed: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(trigger, b"some 150000 2000000\0");
+        return;
+    }
+
+    // The trim thread starts once the allocator is past its boot allocations
+    for _ in 0..2048 {
+        unsafe { free(black_box(malloc(16))) };
+    }
+
+    // 950 of 1000 bytes in use, whatever the host has free
+    let mut percent = 0;
+    for _ in 0..50 {
+        percent = ctl_read::<usize>("stats.pressure.percent");
+        if percent >= 95 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(percent >= 95, "pressure {percent}");
+
+    // A regular file never raises POLLPRI
+    assert_eq!(ctl_read::<usize>("stats.pressure.wakeups"), 0);
+}
@@ -0,0 +1,373 @@
+use std::{collections::HashMap, env, fs, hint::black_box, os::raw::c_void, ptr::null_mut};
+
+use oxidalloc::abi::{free::free, malloc::malloc, prof::ox_prof_dump, realloc::realloc};
+
+mod common;
+
+use common::{ctl_read, ctl_write, run_child, run_child_ok};
+
+const CHILD: &str = "OX_PROF_TEST_CHILD";
+const PATH: &str = "OX_PROF_TEST_PATH";
+// The trim thread would start in the middle and its allocations stay live
+const NO_THREAD: (&str, &str) = ("OX_BACKGROUND_THREAD", "0");
+const SMALL: usize = 1000;
+const COUNT: usize = 100;
+const BIG: usize = 4 << 20;
+
+fn profile_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("ox-prof-{}-{name}.pb", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn dump(path: &str) {
+    let path = format!("{path}\0");
+    assert_eq!(unsafe { ox_prof_dump(path.as_ptr().cast()) }, 0);
+}
+
+#[inline(never)]
+fn small_site(out: &mut [*mut c_void]) {
+    for ptr in out {
+        *ptr = unsafe { black_box(malloc(SMALL)) };
+    }
+}
+
+#[inline(never)]
+fn big_site() -> *mut c_void {
+    unsafe { black_box(malloc(BIG)) }
+}
+
+// Does the allocations in a fresh process, the parent reads the profile
+#[test]
+fn prof_child() {
+    let Some(mode) = env::var_os(CHILD) else {
+        return;
+    };
+    let path = env::var(PATH).unwrap();
+
+    match mode.to_str().unwrap() {
+        "profile" => unsafe {
+            // Before sampling starts: stdout keeps its buffer for good and the first dump makes a
+            // few lasting allocations in libc
+            println!(
+                "small_site={:#x} big_site={:#x}",
+                small_site as *const () as usize, big_site as *const () as usize
+            );
+            dump(&path);
+            ctl_write::<usize>("prof.sample", 1);
+            let base = ctl_read::<usize>("prof.live");
+
+            let mut small = [null_mut(); COUNT];
+            small_site(&mut small);
+            let big = big_site();
+
+            for &ptr in &small[..COUNT / 2] {
+                free(ptr);
+            }
+            // The other half of the small blocks and the big one
+            assert!(ctl_read::<usize>("prof.live") > base + COUNT / 2);
+            dump(&path);
+
+            // Moving or resizing the big block keeps its sample, freeing everything drops them
+            let big = black_box(realloc(big, BIG * 16));
+            assert!(!big.is_null());
+            free(big);
+            for &ptr in &small[COUNT / 2..] {
+                free(ptr);
+            }
+            // `base` counts the name string of its own read, so does this one
+            assert_eq!(ctl_read::<usize>("prof.live"), base);
+            assert_eq!(ctl_read::<usize>("prof.dropped"), 0);
+        },
+        "env" => unsafe {
+            assert_eq!(ctl_read::<usize>("prof.sample"), 4096);
+            let ptrs: [*mut c_void; 1000] = std::array::from_fn(|_| black_box(malloc(64)));
+            let live = ctl_read::<usize>("prof.live");
+            assert!((1..100).contains(&live), "{live}");
+            dump(&path);
+            ptrs.iter().for_each(|&ptr| free(ptr));
+        },
+        "off" => unsafe {
+            assert_eq!(ctl_read::<usize>("prof.sample"), 0);
+            let ptrs: [*mut c_void; 1000] = std::array::from_fn(|_| black_box(malloc(SMALL)));
+            assert_eq!(ctl_read::<usize>("prof.live"), 0);
+            dump(&path);
+            ptrs.iter().for_each(|&ptr| free(ptr));
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Just enough of protobuf to read `profile.proto` back
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn varint(buf: &[u8], pos: &mut usize) -> u64 {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*pos];
+        *pos += 1;
+        val |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+fn fields(buf: &[u8]) -> Vec<(u64, Field<'_>)> {
+    let mut pos = 0;
+    let mut out = Vec::new();
+    while pos < buf.len() {
+        let key = varint(buf, &mut pos);
+        let field = match key & 7 {
+            0 => Field::Varint(varint(buf, &mut pos)),
+            2 => {
+                let len = varint(buf, &mut pos) as usize;
+                pos += len;
+                Field::Bytes(&buf[pos - len..pos])
+            }
+            wire => panic!("unexpected wire type {wire}"),
+        };
+        out.push((key >> 3, field));
+    }
+    out
+}
+
+fn packed(buf: &[u8]) -> Vec<u64> {
+    let mut pos = 0;
+    let mut out = Vec::new();
+    while pos < buf.len() {
+        out.push(varint(buf, &mut pos));
+    }
+    out
+}
+
+fn uints(buf: &[u8]) -> HashMap<u64, u64> {
+    fields(buf)
+        .into_iter()
+        .filter_map(|(num, field)| match field {
+            Field::Varint(val) => Some((num, val)),
+            Field::Bytes(_) => None,
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Profile {
+    sample_types: Vec<(u64, u64)>,
+    samples: Vec<(Vec<u64>, Vec<u64>)>,
+    // id -> (start, limit, filename)
+    mappings: HashMap<u64, (u64, u64, u64)>,
+    // id -> (mapping, address)
+    locations: HashMap<u64, (u64, u64)>,
+    strings: Vec<String>,
+    period_type: (u64, u64),
+    period: u64,
+    default_sample_type: u64,
+}
+
+impl Profile {
+    fn parse(buf: &[u8]) -> Self {
+        let mut profile = Profile::default();
+        for (num, field) in fields(buf) {
+            match (num, field) {
+                (1, Field::Bytes(body)) => {
+                    let vals = uints(body);
+                    profile.sample_types.push((vals[&1], vals[&2]));
+                }
+                (2, Field::Bytes(body)) => {
+                    let mut sample = (Vec::new(), Vec::new());
+                    for (num, field) in fields(body) {
+                        match (num, field) {
+                            (1, Field::Bytes(ids)) => sample.0 = packed(ids),
+                            (2, Field::Bytes(vals)) => sample.1 = packed(vals),
+                            _ => panic!("unexpected sample field {num}"),
+                        }
+                    }
+                    profile.samples.push(sample);
+                }
+                (3, Field::Bytes(body)) => {
+                    let vals = uints(body);
+                    let get = |num| vals.get(&num).copied().unwrap_or(0);
+                    profile.mappings.insert(get(1), (get(2), get(3), get(5)));
+                }
+                (4, Field::Bytes(body)) => {
+                    let vals = uints(body);
+                    let get = |num| vals.get(&num).copied().unwrap_or(0);
+                    profile.locations.insert(get(1), (get(2), get(3)));
+                }
+                (6, Field::Bytes(text)) => {
+                    profile
+                        .strings
+                        .push(String::from_utf8(text.to_vec()).unwrap());
+                }
+                (9, Field::Varint(_)) => {}
+                (11, Field::Bytes(body)) => {
+                    let vals = uints(body);
+                    profile.period_type = (vals[&1], vals[&2]);
+                }
+                (12, Field::Varint(val)) => profile.period = val,
+                (14, Field::Varint(val)) => profile.default_sample_type = val,
+                (num, _) => panic!("unexpected profile field {num}"),
+            }
+        }
+        profile
+    }
+
+    fn string(&self, idx: u64) -> &str {
+        &self.strings[idx as usize]
+    }
+
+    fn check_layout(&self) {
+        assert_eq!(self.strings[0], "");
+        let types: Vec<_> = self
+            .sample_types
+            .iter()
+            .map(|&(kind, unit)| (self.string(kind), self.string(unit)))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("alloc_objects", "count"),
+                ("alloc_space", "bytes"),
+                ("inuse_objects", "count"),
+                ("inuse_space", "bytes"),
+            ]
+        );
+        assert_eq!(self.string(self.default_sample_type), "inuse_space");
+        assert_eq!(
+            (
+                self.string(self.period_type.0),
+                self.string(self.period_type.1)
+            ),
+            ("space", "bytes")
+        );
+
+        for (ids, values) in &self.samples {
+            assert_eq!(values.len(), 4);
+            assert!(!ids.is_empty());
+            for id in ids {
+                let (mapping, _) = self.locations[id];
+                assert!(mapping == 0 || self.mappings.contains_key(&mapping));
+            }
+        }
+    }
+
+    // The sample whose stack runs through the function at `site`. Functions are laid out in any
+    // order, so it is the one with a return address closest past the start.
+    fn sample_from(&self, site: u64) -> &[u64] {
+        let distance = |ids: &Vec<u64>| {
+            ids.iter()
+                .map(|id| self.locations[id].1.wrapping_sub(site))
+                .min()
+                .unwrap()
+        };
+        let (ids, values) = self
+            .samples
+            .iter()
+            .min_by_key(|(ids, _)| distance(ids))
+            .unwrap();
+        let nearest = distance(ids);
+        assert!(nearest < 512, "no sample from the allocation site");
+        assert_eq!(
+            self.samples
+                .iter()
+                .filter(|(ids, _)| distance(ids) == nearest)
+                .count(),
+            1
+        );
+        values
+    }
+}
+
+fn site(stdout: &str, name: &str) -> u64 {
+    let (_, rest) = stdout.split_once(&format!("{name}=0x")).unwrap();
+    let hex = rest.split_whitespace().next().unwrap();
+    u64::from_str_radix(hex, 16).unwrap()
+}
+
+fn read_profile(path: &str) -> Profile {
+    let buf = fs::read(path).unwrap();
+    let _ = fs::remove_file(path);
+    let profile = Profile::parse(&buf);
+    profile.check_layout();
+    profile
+}
+
+#[test]
+fn profile_has_live_and_cumulative_views() {
+    let path = profile_path("profile");
+    let out = run_child(
+        "prof_child",
+        (CHILD, "profile"),
+        &[(PATH, &path), NO_THREAD],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let profile = read_profile(&path);
+    assert_eq!(profile.period, 1);
+
+    // Every allocation is sampled at rate 1, so nothing is scaled
+    let small = profile.sample_from(site(&stdout, "small_site"));
+    assert_eq!(
+        small,
+        [
+            COUNT as u64,
+            (COUNT * SMALL) as u64,
+            (COUNT / 2) as u64,
+            (COUNT / 2 * SMALL) as u64
+        ]
+    );
+    let big = profile.sample_from(site(&stdout, "big_site"));
+    assert_eq!(big, [1, BIG as u64, 1, BIG as u64]);
+
+    // The sites resolve to the test binary through its mapping
+    let addr = site(&stdout, "small_site");
+    let exe = env::current_exe().unwrap();
+    let (_, _, file) = profile
+        .mappings
+        .values()
+        .find(|(start, limit, _)| (*start..*limit).contains(&addr))
+        .unwrap();
+    assert_eq!(profile.string(*file), exe.to_str().unwrap());
+}
+
+#[test]
+fn rate_comes_from_the_environment() {
+    let path = profile_path("env");
+    run_child_ok(
+        "prof_child",
+        (CHILD, "env"),
+        &[(PATH, &path), NO_THREAD, ("OX_PROF_SAMPLE", "4096")],
+    );
+
+    let profile = read_profile(&path);
+    assert_eq!(profile.period, 4096);
+    // 64 byte blocks are sampled 1.5% of the time and scaled back up
+    let inuse: u64 = profile.samples.iter().map(|(_, values)| values[2]).sum();
+    assert!(inuse > 200, "{inuse}");
+}
+
+#[test]
+fn sampling_is_off_by_default() {
+    let path = profile_path("off");
+    run_child_ok("prof_child", (CHILD, "off"), &[(PATH, &path), NO_THREAD]);
+
+    let profile = read_profile(&path);
+    assert!(profile.samples.is_empty());
+    assert!(profile.locations.is_empty());
+    assert_eq!(profile.period, 0);
+}
+
+#[test]
+fn dump_reports_bad_paths() {
+    let path = "/nonexistent-dir/profile.pb\0";
+    assert_eq!(unsafe { ox_prof_dump(path.as_ptr().cast()) }, libc::ENOENT);
+    assert_eq!(unsafe { ox_prof_dump(null_mut()) }, libc::EINVAL);
+}
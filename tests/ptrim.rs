@@ -0,0 +1,73 @@
+use std::{env, hint::black_box, os::raw::c_void, thread, time::Duration};
+
+use oxidalloc::abi::{ctl::ox_thread_cache_flush, free::free, malloc::malloc};
+
+mod common;
+
+use common::{ctl_read, run_child_ok};
+
+const CHILD: &str = "OX_PTRIM_TEST_CHILD";
+const CLASS: usize = 6;
+const BLOCKS: usize = 64;
+
+// Leaves `BLOCKS` blocks of `CLASS` in the calling thread's bin
+fn fill_bin() {
+    let size = ctl_read::<usize>(&format!("stats.class.{CLASS}.size"));
+    let blocks: Vec<_> = (0..BLOCKS)
+        .map(|_| {
+            let ptr = unsafe { black_box(malloc(size)) };
+            assert!(!ptr.is_null());
+            ptr
+        })
+        .collect();
+    blocks
+        .iter()
+        .for_each(|&ptr| unsafe { free(ptr as *mut c_void) });
+}
+
+#[test]
+fn idle_bins_go_back_to_icc() {
+    if env::var_os(CHILD).is_none() {
+        run_child_ok(
+            "idle_bins_go_back_to_icc",
+            (CHILD, "1"),
+            &[("OX_CONF", "decay:aggressive")],
+        );
+        return;
+    }
+
+    // The trim thread starts once the allocator is past its boot allocations
+    for _ in 0..2048 {
+        unsafe { free(black_box(malloc(16))) };
+    }
+
+    thread::spawn(|| {
+        let usage = format!("stats.class.{CLASS}.tls_usage");
+        fill_bin();
+        assert!(ctl_read::<usize>(&usage) > 0);
+        let flushed = ctl_read::<usize>("stats.ptrim.flushed");
+
+        // Aggressive decay marks caches every second and flushes bins idle for a second
+        thread::sleep(Duration::from_millis(3500));
+        unsafe { free(black_box(malloc(16))) };
+
+        assert_eq!(ctl_read::<usize>(&usage), 0);
+        assert!(ctl_read::<usize>("stats.ptrim.flushed") >= flushed + BLOCKS);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn explicit_flush_empties_the_thread_cache() {
+    thread::spawn(|| {
+        let usage = format!("stats.class.{CLASS}.tls_usage");
+        fill_bin();
+        assert!(ctl_read::<usize>(&usage) > 0);
+
+        unsafe { ox_thread_cache_flush() };
+        assert_eq!(ctl_read::<usize>(&usage), 0);
+    })
+    .join()
+    .unwrap();
+}
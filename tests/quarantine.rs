@@ -0,0 +1,109 @@
+// Hardened quarantine: `cargo test --features hardened-malloc`
+#![cfg(feature = "hardened-malloc")]
+
+use std::{
+    env,
+    hint::black_box,
+    io::Write,
+    os::raw::c_void,
+    ptr::{read_volatile, write_volatile},
+};
+
+use oxidalloc::{
+    abi::{ctl::ox_thread_cache_flush, free::free, malloc::malloc},
+    slab::quarantine::{POISON, QUARANTINE_SLOTS},
+};
+
+mod common;
+
+use common::{ctl_write, run_child, run_child_ok};
+
+const CHILD: &str = "OX_QUARANTINE_TEST_CHILD";
+
+#[test]
+fn freed_blocks_are_poisoned_and_held() {
+    unsafe {
+        let victim = black_box(malloc(64)) as *mut u8;
+        assert!(!victim.is_null());
+        free(victim as *mut c_void);
+
+        for i in 0..64 {
+            assert_eq!(read_volatile(victim.add(i)), POISON);
+        }
+
+        let reused: Vec<_> = (0..QUARANTINE_SLOTS / 2)
+            .map(|_| black_box(malloc(64)))
+            .collect();
+        assert!(reused.iter().all(|&ptr| ptr as *mut u8 != victim));
+        reused.iter().for_each(|&ptr| free(ptr));
+
+        // Draining the quarantine checks every poison, the blocks are intact
+        ox_thread_cache_flush();
+    }
+}
+
+#[test]
+fn big_classes_skip_the_quarantine() {
+    unsafe {
+        for _ in 0..4 {
+            let ptr = black_box(malloc(2 * 1024 * 1024));
+            assert!(!ptr.is_null());
+            free(ptr);
+        }
+    }
+}
+
+#[test]
+fn write_after_free_aborts() {
+    if let Some(mode) = env::var_os(CHILD) {
+        unsafe {
+            if mode == "continue" {
+                ctl_write("error.policy.memory_corruption", 2u8);
+            }
+
+            let victim = black_box(malloc(64)) as *mut u8;
+            println!("victim={victim:p}");
+            std::io::stdout().flush().unwrap();
+            free(victim as *mut c_void);
+            write_volatile(victim.add(8), 0x41);
+
+            // Pushes the victim out of the FIFO
+            for _ in 0..QUARANTINE_SLOTS {
+                free(black_box(malloc(64)));
+            }
+
+            // Only reached when the policy lets the process go on, the victim was dropped
+            let blocks: Vec<_> = (0..QUARANTINE_SLOTS * 2)
+                .map(|_| black_box(malloc(64)) as *mut u8)
+                .collect();
+            assert!(blocks.iter().all(|&ptr| ptr != victim));
+            blocks.iter().for_each(|&ptr| free(ptr as *mut c_void));
+            ox_thread_cache_flush();
+        }
+        return;
+    }
+
+    let out = run_child("write_after_free_aborts", (CHILD, "1"), &[]);
+    assert!(!out.status.success());
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let victim = stdout
+        .lines()
+        .find_map(|line| line.split_once("victim=").map(|(_, ptr)| ptr))
+        .expect("child did not report the block");
+
+    assert!(stderr.contains("MemoryCorruption"), "{stderr}");
+    assert!(stderr.contains(&format!("ptr={victim}")), "{stderr}");
+    assert!(stderr.contains("class 3 (64 bytes), offset 8"), "{stderr}");
+}
+
+#[test]
+fn write_after_free_can_continue() {
+    let stderr = run_child_ok("write_after_free_aborts", (CHILD, "continue"), &[]);
+    assert!(
+        stderr.contains("[OXIDALLOC ERROR] MemoryCorruption"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("class 3 (64 bytes), offset 8"), "{stderr}");
+}
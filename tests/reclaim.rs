@@ -0,0 +1,117 @@
+use std::{hint::black_box, os::raw::c_void, sync::Mutex, thread};
+
+use oxidalloc::abi::{
+    ctl::ox_thread_cache_flush,
+    free::free,
+    malloc::{malloc, malloc_trim},
+    realloc::realloc,
+};
+
+mod common;
+
+use common::ctl_read;
+
+const BLOCKS: usize = 64 * 1024;
+const SIZE: usize = 64;
+
+// Both tests trim the whole process and read process wide counters
+static SERIAL: Mutex<()> = Mutex::new(());
+
+// Allocates `BLOCKS` tagged blocks on a thread that exits afterwards, `keep` picks the ones that
+// survive it
+fn phase(keep: fn(usize) -> bool) -> Vec<usize> {
+    thread::spawn(move || unsafe {
+        let blocks: Vec<_> = (0..BLOCKS)
+            .map(|i| {
+                let ptr = black_box(malloc(SIZE)) as *mut usize;
+                assert!(!ptr.is_null());
+                ptr.write(i);
+                ptr
+            })
+            .collect();
+
+        blocks
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, ptr)| {
+                if keep(i) {
+                    Some(ptr as usize)
+                } else {
+                    free(ptr as *mut c_void);
+                    None
+                }
+            })
+            .collect()
+    })
+    .join()
+    .unwrap()
+}
+
+#[test]
+fn free_slabs_go_back_to_va_map() {
+    let _serial = SERIAL.lock().unwrap();
+
+    let before = ctl_read::<usize>("stats.allocated");
+    phase(|_| false);
+    let grown = ctl_read::<usize>("stats.allocated") - before;
+    assert!(grown >= BLOCKS * SIZE);
+
+    let reclaimed = ctl_read::<usize>("stats.slabs.reclaimed");
+    unsafe { malloc_trim(0) };
+    assert!(ctl_read::<usize>("stats.slabs.reclaimed") > reclaimed);
+    assert!(ctl_read::<usize>("stats.allocated") < before + grown / 4);
+
+    // The released ranges are carved again
+    let kept = phase(|_| true);
+    for (i, &ptr) in kept.iter().enumerate() {
+        unsafe {
+            assert_eq!((ptr as *mut usize).read(), i);
+            free(ptr as *mut c_void);
+        }
+    }
+}
+
+#[test]
+fn live_blocks_keep_their_slab() {
+    let _serial = SERIAL.lock().unwrap();
+
+    let kept = phase(|i| i.is_multiple_of(64));
+    let allocated = ctl_read::<usize>("stats.allocated");
+    unsafe { malloc_trim(0) };
+
+    for &ptr in &kept {
+        let ptr = ptr as *mut usize;
+        unsafe {
+            assert_eq!(ptr.read() % 64, 0);
+            ptr.write_bytes(0x5A, SIZE / size_of::<usize>());
+        }
+    }
+
+    // A slab spans fewer than 64 * 64 blocks, every one of them still holds a live block
+    assert!(allocated.saturating_sub(ctl_read::<usize>("stats.allocated")) < BLOCKS * SIZE / 4);
+    kept.iter()
+        .for_each(|&ptr| unsafe { free(ptr as *mut c_void) });
+}
+
+// A block of a one block class that realloc grew in place is not part of a multi block slab
+#[test]
+fn grown_blocks_survive_the_census() {
+    let _serial = SERIAL.lock().unwrap();
+
+    let blocks: Vec<_> = (0..16)
+        .map(|_| unsafe {
+            let ptr = black_box(malloc(2500));
+            let grown = black_box(realloc(ptr, 3000));
+            assert!(!grown.is_null());
+            grown
+        })
+        .collect();
+    for ptr in blocks {
+        unsafe { free(ptr) };
+    }
+
+    unsafe {
+        ox_thread_cache_flush();
+        malloc_trim(0);
+    }
+}
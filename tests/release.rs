@@ -0,0 +1,92 @@
+use std::{env, hint::black_box, os::raw::c_void};
+
+use oxidalloc::abi::{
+    calloc::calloc,
+    free::free,
+    malloc::{malloc, malloc_trim},
+};
+
+mod common;
+
+use common::{ctl_read, run_child_ok};
+
+const CHILD: &str = "OX_RELEASE_TEST_CHILD";
+const BIG: usize = 63 * 1024 * 1024;
+
+// Freed and trimmed big regions keep their contents under lazy policies, a calloc landing on the
+// same VA must still read back as zero
+fn calloc_after_release() {
+    unsafe {
+        // Reservations start at a random offset past the last one, a region this size is hit
+        // about a quarter of the time
+        for _ in 0..16 {
+            let ptr = black_box(malloc(BIG)).cast::<u8>();
+            assert!(!ptr.is_null());
+            // Keeps the stores from being dropped as dead before the free
+            black_box(std::slice::from_raw_parts_mut(ptr, BIG)).fill(0xAA);
+            free(ptr as *mut c_void);
+            malloc_trim(0);
+
+            let zeroed = black_box(calloc(1, BIG)).cast::<u8>();
+            assert!(!zeroed.is_null());
+            let bytes = std::slice::from_raw_parts(zeroed, BIG);
+            assert!(bytes.iter().all(|&b| b == 0));
+            free(zeroed as *mut c_void);
+        }
+
+        // Small blocks and slabs go through the same release path
+        let blocks: Vec<_> = (0..4096).map(|_| black_box(malloc(64))).collect();
+        blocks.iter().for_each(|&ptr| {
+            assert!(!ptr.is_null());
+            black_box(std::slice::from_raw_parts_mut(ptr.cast::<u8>(), 64)).fill(0x55);
+            free(ptr as *mut c_void);
+        });
+        malloc_trim(0);
+
+        let zeroed = black_box(calloc(64, 64)) as *mut u8;
+        assert!(
+            std::slice::from_raw_parts(zeroed, 64 * 64)
+                .iter()
+                .all(|&b| b == 0)
+        );
+        free(zeroed as *mut c_void);
+    }
+}
+
+#[test]
+fn default_policy_is_dontneed() {
+    assert_eq!(ctl_read::<u8>("release.policy"), 0);
+}
+
+#[test]
+fn lazy_free_keeps_calloc_zeroed() {
+    if env::var_os(CHILD).is_some() {
+        assert_eq!(ctl_read::<u8>("release.policy"), 1);
+        calloc_after_release();
+        return;
+    }
+
+    run_child_ok(
+        "lazy_free_keeps_calloc_zeroed",
+        (CHILD, "1"),
+        &[("OX_CONF", "release:free")],
+    );
+}
+
+#[test]
+fn cold_and_pageout_apply() {
+    if let Some(conf) = env::var_os(CHILD) {
+        let expected = if conf == "cold" { 2 } else { 3 };
+        assert_eq!(ctl_read::<u8>("release.policy"), expected);
+        calloc_after_release();
+        return;
+    }
+
+    for policy in ["cold", "pageout"] {
+        run_child_ok(
+            "cold_and_pageout_apply",
+            (CHILD, policy),
+            &[("OX_CONF", &format!("release:{policy}"))],
+        );
+    }
+}
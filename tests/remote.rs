@@ -0,0 +1,92 @@
+use std::{
+    collections::HashSet,
+    os::raw::c_void,
+    sync::{Arc, Barrier, atomic::Ordering, mpsc},
+    thread,
+};
+
+use oxidalloc::{
+    abi::{free::free, malloc::malloc},
+    slab::remote::{REMOTE_DRAINED, REMOTE_FREES},
+};
+
+struct SendPtr(*mut c_void);
+unsafe impl Send for SendPtr {}
+
+#[test]
+fn consumer_frees_go_back_to_producer() {
+    const BATCH: usize = 4096;
+    const ROUNDS: usize = 8;
+
+    let frees_before = REMOTE_FREES.load(Ordering::Relaxed);
+    let drained_before = REMOTE_DRAINED.load(Ordering::Relaxed);
+
+    let (tx, rx) = mpsc::channel::<Vec<SendPtr>>();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    let consumer = thread::spawn(move || {
+        for batch in rx {
+            for ptr in batch {
+                unsafe { free(ptr.0) };
+            }
+            done_tx.send(()).unwrap();
+        }
+    });
+
+    let mut first_round = HashSet::new();
+    let mut reused = 0;
+    for round in 0..ROUNDS {
+        let mut batch = Vec::with_capacity(BATCH);
+        for _ in 0..BATCH {
+            let ptr = unsafe { malloc(64) };
+            assert!(!ptr.is_null());
+            if round == 0 {
+                first_round.insert(ptr as usize);
+            } else if first_round.contains(&(ptr as usize)) {
+                reused += 1;
+            }
+            batch.push(SendPtr(ptr));
+        }
+        tx.send(batch).unwrap();
+        done_rx.recv().unwrap();
+    }
+
+    drop(tx);
+    consumer.join().unwrap();
+
+    assert!(REMOTE_FREES.load(Ordering::Relaxed) - frees_before >= BATCH * (ROUNDS - 1));
+    assert!(REMOTE_DRAINED.load(Ordering::Relaxed) > drained_before);
+    assert!(
+        reused > 0,
+        "producer never reused blocks freed by the consumer"
+    );
+}
+
+#[test]
+fn remote_frees_survive_owner_exit() {
+    let barrier = Arc::new(Barrier::new(2));
+    let (tx, rx) = mpsc::channel::<Vec<SendPtr>>();
+
+    let b = barrier.clone();
+    let owner = thread::spawn(move || {
+        let batch = (0..1024)
+            .map(|i| SendPtr(unsafe { malloc(16 + (i % 512)) }))
+            .collect();
+        tx.send(batch).unwrap();
+        b.wait();
+    });
+
+    let batch = rx.recv().unwrap();
+    barrier.wait();
+    owner.join().unwrap();
+
+    for ptr in batch {
+        unsafe { free(ptr.0) };
+    }
+
+    for _ in 0..4096 {
+        let ptr = unsafe { malloc(128) };
+        assert!(!ptr.is_null());
+        unsafe { free(ptr) };
+    }
+}
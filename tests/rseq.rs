@@ -0,0 +1,91 @@
+use std::{env, hint::black_box, os::raw::c_void, thread};
+
+use oxidalloc::abi::{ctl::ox_thread_cache_flush, free::free, malloc::malloc};
+
+mod common;
+
+use common::{ctl_read, run_child_ok};
+
+const CHILD: &str = "OX_RSEQ_TEST_CHILD";
+const THREADS: usize = 8;
+const ROUNDS: usize = 200;
+
+// The ICC mode is picked once per process, the child reports it on stderr
+fn child_rseq_mode(test: &str, envs: &[(&str, &str)]) -> bool {
+    run_child_ok(test, (CHILD, "1"), envs).contains("rseq mode: true")
+}
+
+// Every thread fills the ICC with tagged blocks and takes batches back, some of them pushed by
+// other threads. A block handed out twice or lost shows up as a wrong tag.
+fn exchange_blocks() {
+    let workers: Vec<_> = (0..THREADS)
+        .map(|id| {
+            thread::spawn(move || unsafe {
+                for round in 0..ROUNDS {
+                    let tag = (id * ROUNDS + round) as u64;
+                    let blocks: Vec<_> = (0..128)
+                        .map(|_| {
+                            let ptr = black_box(malloc(48)) as *mut u64;
+                            assert!(!ptr.is_null());
+                            ptr.write(tag);
+                            ptr
+                        })
+                        .collect();
+
+                    for &ptr in &blocks {
+                        assert_eq!(ptr.read(), tag);
+                        free(ptr as *mut c_void);
+                    }
+                    ox_thread_cache_flush();
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+fn rseq_shards_exchange_blocks() {
+    if env::var_os(CHILD).is_none() {
+        run_child_ok("rseq_shards_exchange_blocks", (CHILD, "1"), &[]);
+        return;
+    }
+
+    // Kernels without rseq or membarrier, or a seccomp filter, end up on the CAS path
+    eprintln!("rseq mode: {}", ctl_read::<bool>("stats.icc.rseq"));
+    exchange_blocks();
+}
+
+#[test]
+fn own_rseq_area_without_glibc() {
+    if env::var_os(CHILD).is_none() {
+        // Registering our own area must work wherever glibc's does
+        let with_glibc = child_rseq_mode("own_rseq_area_without_glibc", &[]);
+        let without_glibc = child_rseq_mode(
+            "own_rseq_area_without_glibc",
+            &[("GLIBC_TUNABLES", "glibc.pthread.rseq=0")],
+        );
+        assert_eq!(with_glibc, without_glibc);
+        return;
+    }
+
+    eprintln!("rseq mode: {}", ctl_read::<bool>("stats.icc.rseq"));
+    exchange_blocks();
+}
+
+#[test]
+fn disabled_rseq_keeps_cas_path() {
+    if env::var_os(CHILD).is_none() {
+        assert!(!child_rseq_mode(
+            "disabled_rseq_keeps_cas_path",
+            &[("OX_CONF", "rseq:0")]
+        ));
+        return;
+    }
+
+    eprintln!("rseq mode: {}", ctl_read::<bool>("stats.icc.rseq"));
+    exchange_blocks();
+}
@@ -0,0 +1,70 @@
+use std::{
+    hint::black_box,
+    io::{Read, Seek, SeekFrom},
+    os::{fd::FromRawFd, raw::c_void},
+};
+
+use oxidalloc::abi::{
+    free::free,
+    malloc::malloc,
+    stats::{mallinfo, mallinfo2, malloc_info},
+};
+
+#[test]
+fn mallinfo2_tracks_slabs_and_big_allocations() {
+    let before = unsafe { mallinfo2() };
+
+    let small: Vec<*mut c_void> = (0..1024).map(|_| unsafe { malloc(256) }).collect();
+    let big = black_box(unsafe { malloc(8 * 1024 * 1024) });
+    assert!(!big.is_null());
+
+    let during = unsafe { mallinfo2() };
+    assert!(during.arena > 0);
+    assert!(during.arena >= during.uordblks);
+    assert!(during.uordblks >= 1024 * 256);
+    assert!(during.hblks > before.hblks);
+    assert!(during.hblkhd >= before.hblkhd + 8 * 1024 * 1024);
+    assert_eq!(during.fordblks, during.arena - during.uordblks);
+
+    unsafe { free(big) };
+    for ptr in small {
+        unsafe { free(ptr) };
+    }
+
+    let after = unsafe { mallinfo2() };
+    assert!(after.hblkhd + 8 * 1024 * 1024 <= during.hblkhd);
+    assert!(after.ordblks >= 1024);
+
+    let legacy = unsafe { mallinfo() };
+    assert_eq!(legacy.hblks as usize, after.hblks);
+}
+
+#[test]
+fn malloc_info_writes_glibc_xml() {
+    let ptrs: Vec<*mut c_void> = (0..64).map(|i| unsafe { malloc(16 + i * 32) }).collect();
+    for ptr in ptrs {
+        unsafe { free(ptr) };
+    }
+
+    let xml = unsafe {
+        let stream = libc::tmpfile();
+        assert!(!stream.is_null());
+        assert_eq!(malloc_info(1, stream), libc::EINVAL);
+        assert_eq!(malloc_info(0, stream), 0);
+        libc::fflush(stream);
+
+        let mut file = std::fs::File::from_raw_fd(libc::dup(libc::fileno(stream)));
+        libc::fclose(stream);
+
+        let mut xml = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut xml).unwrap();
+        xml
+    };
+
+    assert!(xml.starts_with("<malloc version=\"1\">\n<heap nr=\"0\">\n<sizes>\n"));
+    assert!(xml.ends_with("</malloc>\n"));
+    assert!(xml.contains("<size from=\""));
+    assert!(xml.contains("<total type=\"mmap\""));
+    assert_eq!(xml.matches("<system type=\"current\"").count(), 2);
+}
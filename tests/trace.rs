@@ -0,0 +1,184 @@
+use std::{env, fs, hint::black_box, process::Output, ptr::null_mut, thread};
+
+use oxidalloc::{
+    abi::{align::posix_memalign, calloc::calloc, free::free, malloc::malloc, realloc::realloc},
+    internals::trace::{TraceOp, TraceRecord, parse_trace},
+};
+
+mod common;
+
+use common::run_child;
+
+const CHILD: &str = "OX_TRACE_TEST_CHILD";
+const THREADS: usize = 4;
+// More than a ring holds, so every thread flushes while running and again at exit
+const PAIRS: usize = 5000;
+
+fn trace_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("ox-trace-{}-{name}.bin", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_owned()
+}
+
+fn run_trace_child(mode: &str, path: &str) -> Output {
+    run_child("trace_child", (CHILD, mode), &[("OX_TRACE", path)])
+}
+
+fn read_trace(path: &str) -> Vec<TraceRecord> {
+    let bytes = fs::read(path).unwrap();
+    let _ = fs::remove_file(path);
+    parse_trace(&bytes).expect("bad trace header").collect()
+}
+
+fn find(records: &[TraceRecord], op: TraceOp, size: usize) -> Vec<&TraceRecord> {
+    records
+        .iter()
+        .filter(|record| record.op == op as u32 && record.size == size as u64)
+        .collect()
+}
+
+// Makes the calls in a fresh process, the parent reads the trace it leaves
+#[test]
+fn trace_child() {
+    let Some(mode) = env::var_os(CHILD) else {
+        return;
+    };
+
+    match mode.to_str().unwrap() {
+        "calls" => unsafe {
+            let ptr = black_box(malloc(12345));
+            let ptr = black_box(realloc(ptr, 234567));
+            let mut aligned = null_mut();
+            assert_eq!(posix_memalign(&mut aligned, 256, 777), 0);
+            let zeroed = black_box(calloc(3, 1000));
+            free(ptr);
+            free(aligned);
+            free(zeroed);
+        },
+        "threads" => {
+            let workers: Vec<_> = (0..THREADS)
+                .map(|index| {
+                    thread::spawn(move || unsafe {
+                        for _ in 0..PAIRS {
+                            free(black_box(malloc(1000 + index)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .for_each(|worker| worker.join().unwrap());
+        }
+        "fork" => unsafe {
+            // Still in the parent's ring when it forks
+            black_box(malloc(31337));
+            let pid = libc::fork();
+            if pid == 0 {
+                black_box(malloc(4242));
+                libc::exit(0);
+            }
+
+            let mut status = 0;
+            assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+            assert!(libc::WIFEXITED(status));
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn calls_are_traced_once_with_addresses() {
+    let path = trace_path("calls");
+    let out = run_trace_child("calls", &path);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let records = read_trace(&path);
+
+    let [malloc] = find(&records, TraceOp::Malloc, 12345)[..] else {
+        panic!("malloc");
+    };
+    let [realloc] = find(&records, TraceOp::Realloc, 234567)[..] else {
+        panic!("realloc");
+    };
+    let [memalign] = find(&records, TraceOp::Memalign, 777)[..] else {
+        panic!("memalign");
+    };
+    let [calloc] = find(&records, TraceOp::Calloc, 3000)[..] else {
+        panic!("calloc");
+    };
+
+    assert_ne!(malloc.ret, 0);
+    assert_eq!(realloc.ptr, malloc.ret);
+    assert_eq!(memalign.align, 256);
+    assert_eq!(memalign.ret % 256, 0);
+
+    // Each block is freed once, by the address it was handed out at
+    for block in [realloc, memalign, calloc] {
+        let frees: Vec<_> = records
+            .iter()
+            .filter(|record| record.op == TraceOp::Free as u32 && record.ptr == block.ret)
+            .collect();
+        assert_eq!(frees.len(), 1);
+        assert!(frees[0].nanos >= block.nanos);
+    }
+
+    // One thread, in order, and the calls made inside realloc and posix_memalign are not logged
+    let tid = malloc.tid;
+    assert!([realloc, memalign, calloc].iter().all(|r| r.tid == tid));
+    assert!(malloc.nanos <= realloc.nanos && realloc.nanos <= memalign.nanos);
+    assert!(find(&records, TraceOp::Malloc, 234567).is_empty());
+    assert!(find(&records, TraceOp::Malloc, 3000).is_empty());
+}
+
+#[test]
+fn every_thread_is_flushed() {
+    let path = trace_path("threads");
+    let out = run_trace_child("threads", &path);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let records = read_trace(&path);
+
+    let mut tids = Vec::new();
+    for index in 0..THREADS {
+        let mallocs = find(&records, TraceOp::Malloc, 1000 + index);
+        assert_eq!(mallocs.len(), PAIRS);
+
+        let tid = mallocs[0].tid;
+        assert!(mallocs.iter().all(|record| record.tid == tid));
+        let frees = records
+            .iter()
+            .filter(|record| record.op == TraceOp::Free as u32 && record.tid == tid)
+            .count();
+        assert!(frees >= PAIRS);
+        tids.push(tid);
+    }
+    tids.sort_unstable();
+    tids.dedup();
+    assert_eq!(tids.len(), THREADS);
+}
+
+#[test]
+fn forked_child_does_not_repeat_parent_records() {
+    let path = trace_path("fork");
+    let out = run_trace_child("fork", &path);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let records = read_trace(&path);
+
+    let [parent] = find(&records, TraceOp::Malloc, 31337)[..] else {
+        panic!("parent record missing or repeated");
+    };
+    let [child] = find(&records, TraceOp::Malloc, 4242)[..] else {
+        panic!("child record");
+    };
+    assert_ne!(parent.tid, child.tid);
+}